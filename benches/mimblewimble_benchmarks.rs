@@ -1,14 +1,16 @@
 //! Benchmarks for Mimblewimble protocol
 
-use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use p521::Scalar;
 use silver_mimblewimble::kernel::KernelFeatures;
 use silver_mimblewimble::*;
+use std::num::NonZeroUsize;
 
 fn bench_range_proof_creation(c: &mut Criterion) {
     c.bench_function("range_proof_creation", |b| {
         let params = MimblewimbleParameters::default();
 
-        b.iter(|| RangeProof::create(black_box(1000), &params).unwrap());
+        b.iter(|| RangeProof::create(black_box(1000), &[7; 67], &params).unwrap());
     });
 }
 
@@ -28,9 +30,170 @@ fn bench_transaction_creation(c: &mut Criterion) {
     });
 }
 
+fn bench_transaction_hash(c: &mut Criterion) {
+    let params = MimblewimbleParameters::default();
+    let outputs = (1..=16u8)
+        .map(|n| Commitment::create(u64::from(n), vec![n; 32], &params).unwrap())
+        .collect();
+    let kernel = Kernel::new(KernelFeatures::Plain, 100, 0, vec![1; 32], vec![2; 64]);
+    let tx = Transaction::new(1, vec![], outputs, kernel, 100);
+
+    c.bench_function("transaction_hash_first", |b| {
+        b.iter_batched(
+            || tx.clone(),
+            |tx| tx.hash().unwrap(),
+            BatchSize::SmallInput,
+        );
+    });
+
+    tx.hash().unwrap();
+    c.bench_function("transaction_hash_cached", |b| {
+        b.iter(|| black_box(&tx).hash().unwrap());
+    });
+}
+
+fn bench_large_transaction_hash(c: &mut Criterion) {
+    let params = MimblewimbleParameters::default();
+    let outputs = (0..256u32)
+        .map(|n| Commitment::create(u64::from(n) + 1, n.to_be_bytes().repeat(8), &params).unwrap())
+        .collect();
+    let kernel = Kernel::new(KernelFeatures::Plain, 100, 0, vec![1; 32], vec![2; 64]);
+    let tx = Transaction::new(1, vec![], outputs, kernel, 100);
+
+    c.bench_function("transaction_hash_large", |b| {
+        b.iter(|| serialization::hash(black_box(&tx)).unwrap());
+    });
+}
+
+fn bench_balance_verification(c: &mut Criterion) {
+    let params = MimblewimbleParameters::default();
+    let commitment =
+        |value: u64, blinding: u8| Commitment::create(value, vec![blinding; 32], &params).unwrap();
+
+    // Transactions spending overlapping sets of the same eight inputs
+    let inputs: Vec<Commitment> = (1..=8u8).map(|n| commitment(100, n)).collect();
+    let txs: Vec<Transaction> = (0..4)
+        .map(|start| {
+            let spent = inputs[start..start + 4].to_vec();
+            let outputs = vec![commitment(390, 40 + start as u8)];
+            let excess = wallet::blinding_sum(
+                &spent
+                    .iter()
+                    .map(|c| crypto::scalar_from_bytes(&c.blinding).unwrap())
+                    .collect::<Vec<_>>(),
+                &[crypto::scalar_from_bytes(&outputs[0].blinding).unwrap()],
+                Scalar::ZERO,
+            );
+            let mut kernel = Kernel::new(KernelFeatures::Plain, 10, 0, vec![], vec![]);
+            kernel
+                .sign(&crypto::scalar_to_bytes(&excess), &params)
+                .unwrap();
+            Transaction::new(1, spent, outputs, kernel, 10)
+        })
+        .collect();
+
+    c.bench_function("balance_uncached", |b| {
+        b.iter(|| txs.iter().all(|tx| black_box(tx).is_balanced()));
+    });
+
+    let cache = crypto::PointCache::new(NonZeroUsize::new(1_024).unwrap());
+    c.bench_function("balance_cached", |b| {
+        b.iter(|| txs.iter().all(|tx| black_box(tx).is_balanced_with(&cache)));
+    });
+}
+
+fn bench_output_index_scan(c: &mut Criterion) {
+    let state = MimblewimbleState::new(MimblewimbleParameters::regtest()).unwrap();
+    let blocks = 1_000u64;
+    for n in 0..blocks {
+        let key = (n as u32 + 1).to_be_bytes().repeat(8);
+        let block = state.block_template(&key).unwrap().into_block(0);
+        state.submit_block(block).unwrap();
+    }
+
+    c.bench_function("output_index_scan", |b| {
+        b.iter(|| {
+            (0..black_box(blocks))
+                .filter_map(|height| state.outputs_at_height(height))
+                .map(|delta| delta.added.len())
+                .sum::<usize>()
+        });
+    });
+}
+
+/// State with `spends` coinbases spent through the mempool, and the block
+/// mining those spends
+fn block_spending_coinbases(
+    parameters: MimblewimbleParameters,
+    spends: u64,
+) -> (MimblewimbleState, Block) {
+    let state = MimblewimbleState::new(parameters).unwrap();
+    let params = state.parameters();
+    for n in 0..spends {
+        let key = (n as u32 + 1).to_be_bytes().repeat(8);
+        let mut block = state.block_template(&key).unwrap().into_block(0);
+        block.header.timestamp = n + 1;
+        state.submit_block(block).unwrap();
+    }
+
+    // Spend every coinbase in one block
+    for height in 0..spends {
+        let coinbase = state.outputs_at_height(height).unwrap().added[0].clone();
+        let tx = TransactionBuilder::new(&params)
+            .input(coinbase.clone())
+            .add_recipient(
+                coinbase.value - 10,
+                &(height as u32 + 1_000).to_be_bytes().repeat(8),
+            )
+            .fee(10)
+            .build()
+            .unwrap();
+        state.mempool().add(tx, &state).unwrap();
+    }
+    let key = 9_999u32.to_be_bytes().repeat(8);
+    let mut block = state.block_template(&key).unwrap().into_block(0);
+    block.header.timestamp = spends + 1;
+    state.verify_block(&block).unwrap();
+    (state, block)
+}
+
+fn bench_block_verification(c: &mut Criterion) {
+    let (state, block) = block_spending_coinbases(MimblewimbleParameters::regtest(), 32);
+
+    c.bench_function("verify_block", |b| {
+        b.iter(|| state.verify_block(black_box(&block)).unwrap());
+    });
+
+    c.bench_function("verify_block_per_transaction", |b| {
+        b.iter(|| {
+            block
+                .transactions
+                .iter()
+                .all(|tx| state.verify_transaction(black_box(tx)).unwrap())
+        });
+    });
+
+    // Without the cache every mempool transaction is verified again
+    let (uncached, block) = block_spending_coinbases(
+        MimblewimbleParameters {
+            verification_cache_size: 0,
+            ..MimblewimbleParameters::regtest()
+        },
+        32,
+    );
+    c.bench_function("verify_block_uncached", |b| {
+        b.iter(|| uncached.verify_block(black_box(&block)).unwrap());
+    });
+}
+
 criterion_group!(
     benches,
     bench_range_proof_creation,
-    bench_transaction_creation
+    bench_transaction_creation,
+    bench_transaction_hash,
+    bench_large_transaction_hash,
+    bench_balance_verification,
+    bench_output_index_scan,
+    bench_block_verification
 );
 criterion_main!(benches);
@@ -1,12 +1,34 @@
 //! Blocks for Mimblewimble
 
-use crate::transaction::Transaction;
+use crate::commitment::Commitment;
+use crate::crypto;
+use crate::errors::{MimblewimbleError, Result};
+use crate::kernel::Kernel;
+use crate::mmr::MerkleMountainRange;
+use crate::parameters::MimblewimbleParameters;
+use crate::pow;
+use crate::serialization;
+use crate::transaction::{self, Transaction};
 use hex;
+use p521::{ProjectivePoint, Scalar};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha512};
+use std::collections::HashMap;
+
+/// Length of a block hash: hex-encoded SHA-512 (bytes)
+pub const HASH_LENGTH: usize = 128;
+
+/// Domain prefix for merkle leaf hashes
+const MERKLE_LEAF_PREFIX: u8 = 0;
+
+/// Domain prefix for merkle interior node hashes
+const MERKLE_NODE_PREFIX: u8 = 1;
+
+/// Number of preceding timestamps whose median a header must exceed
+const MEDIAN_TIME_WINDOW: usize = 11;
 
 /// Block header
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct BlockHeader {
     /// Protocol version
     pub version: u32,
@@ -22,14 +44,37 @@ pub struct BlockHeader {
 
     /// Merkle root of transactions
     pub merkle_root: Vec<u8>,
+
+    /// Root of the output set after applying the block
+    pub output_root: Vec<u8>,
+
+    /// Root of the kernel merkle mountain range after applying the block
+    pub kernel_root: Vec<u8>,
+
+    /// Proof of work difficulty
+    pub difficulty: u64,
+
+    /// Proof of work nonce
+    pub nonce: u64,
 }
 
 impl BlockHeader {
     /// Get block header hash
-    pub fn hash(&self) -> Vec<u8> {
-        let mut hasher = Sha512::new();
-        hasher.update(serde_json::to_vec(self).unwrap_or_default());
-        hex::encode(hasher.finalize()).into_bytes()
+    pub fn hash(&self) -> Result<Vec<u8>> {
+        serialization::hash(self)
+    }
+
+    /// Check whether another header is a different block at the same height
+    ///
+    /// Headers hash equal exactly when all their fields are equal, so this
+    /// compares fields rather than hashes.
+    pub fn conflicts_with(&self, other: &BlockHeader) -> bool {
+        self.height == other.height && self != other
+    }
+
+    /// Check that the previous hash and merkle root are `HASH_LENGTH` bytes
+    pub fn has_valid_hash_lengths(&self) -> bool {
+        self.previous_hash.len() == HASH_LENGTH && self.merkle_root.len() == HASH_LENGTH
     }
 }
 
@@ -45,24 +90,530 @@ pub struct Block {
 
 impl Block {
     /// Get block hash
-    pub fn hash(&self) -> Vec<u8> {
+    pub fn hash(&self) -> Result<Vec<u8>> {
         self.header.hash()
     }
 
-    /// Get block size
-    pub fn size(&self) -> usize {
-        serde_json::to_vec(self).unwrap_or_default().len()
+    /// Size of the block's JSON encoding, as framed by the net codec (bytes)
+    ///
+    /// This is the size checked against `max_block_size`.
+    pub fn wire_size(&self) -> Result<usize> {
+        serialization::serialized_size(self)
     }
 
     /// Get transaction count
     pub fn transaction_count(&self) -> usize {
         self.transactions.len()
     }
+
+    /// Estimate the cryptographic work needed to verify the block
+    pub fn estimated_verification_cost(&self) -> VerificationCost {
+        self.transactions
+            .iter()
+            .fold(VerificationCost::default(), |cost, tx| VerificationCost {
+                range_proofs: cost.range_proofs + tx.outputs().len(),
+                signatures: cost.signatures + tx.kernels().len(),
+                // One point per commitment and excess, plus the fee commitment
+                point_operations: cost.point_operations
+                    + tx.inputs().len()
+                    + tx.outputs().len()
+                    + tx.kernels().len()
+                    + 1,
+            })
+    }
+
+    /// Measure what cut-through would remove from the block
+    ///
+    /// Every output spent by an input of the same block forms a pair that
+    /// cut-through eliminates. The savings compare the block with and
+    /// without those pairs, sizes being those of the wire encoding.
+    pub fn cut_through_savings(&self) -> Result<CutThroughStats> {
+        let mut created: HashMap<&[u8], usize> = HashMap::new();
+        for output in self.transactions.iter().flat_map(|tx| tx.outputs()) {
+            *created.entry(&output.commitment).or_default() += 1;
+        }
+
+        let mut cut = self.clone();
+        let mut spent: HashMap<Vec<u8>, usize> = HashMap::new();
+        for tx in &mut cut.transactions {
+            tx.inputs_mut()
+                .retain(|input| match created.get_mut(input.commitment.as_slice()) {
+                    Some(count) if *count > 0 => {
+                        *count -= 1;
+                        *spent.entry(input.commitment.clone()).or_default() += 1;
+                        false
+                    }
+                    _ => true,
+                });
+        }
+        let pairs = spent.values().sum();
+        for tx in &mut cut.transactions {
+            tx.outputs_mut()
+                .retain(|output| match spent.get_mut(&output.commitment) {
+                    Some(count) if *count > 0 => {
+                        *count -= 1;
+                        false
+                    }
+                    _ => true,
+                });
+        }
+
+        let weight = |block: &Block| {
+            block
+                .transactions
+                .iter()
+                .map(Transaction::weight)
+                .fold(0, u64::saturating_add)
+        };
+        Ok(CutThroughStats {
+            pairs,
+            bytes_saved: self.wire_size()?.saturating_sub(cut.wire_size()?),
+            weight_saved: weight(self).saturating_sub(weight(&cut)),
+        })
+    }
+
+    /// Check that the block mints exactly `reward` on top of its kernels
+    ///
+    /// Verifies `sum(outputs) - sum(inputs) == reward*H + sum(excesses) +
+    /// offset*G` over all transactions, where `offset` sums their offsets.
+    pub fn is_balanced(&self, reward: u64) -> bool {
+        match self.net_and_excess() {
+            Some((net, excesses)) => net == crypto::commit(reward, &Scalar::ZERO) + excesses,
+            None => false,
+        }
+    }
+
+    /// Check that a block without a coinbase burns exactly `fees`
+    ///
+    /// Verifies `sum(outputs) - sum(inputs) + fees*H == sum(excesses) +
+    /// offset*G`.
+    pub fn is_balanced_with_fees(&self, fees: u64) -> bool {
+        match self.net_and_excess() {
+            Some((net, excesses)) => net + crypto::commit(fees, &Scalar::ZERO) == excesses,
+            None => false,
+        }
+    }
+
+    /// Net commitment `sum(outputs) - sum(inputs)` of the block, encoded
+    ///
+    /// For a valid block this equals `sum(excesses) + reward*H` when it has a
+    /// coinbase, and `sum(excesses) - fees*H` otherwise. Fails with
+    /// `InvalidCommitment` if a commitment is not a curve point.
+    pub fn net_commitment_delta(&self) -> Result<Vec<u8>> {
+        let sum = |commitments: Vec<&[u8]>| {
+            crypto::sum_points(commitments).map_err(|_| MimblewimbleError::InvalidCommitment)
+        };
+        let outputs = sum(self
+            .transactions
+            .iter()
+            .flat_map(|tx| tx.outputs())
+            .map(|output| output.commitment.as_slice())
+            .collect())?;
+        let inputs = sum(self
+            .transactions
+            .iter()
+            .flat_map(|tx| tx.inputs())
+            .map(|input| input.commitment.as_slice())
+            .collect())?;
+
+        Ok(crypto::point_to_bytes(&(outputs - inputs)))
+    }
+
+    /// Value the block's coinbase must claim: the reward at its height plus
+    /// the fees of its transactions
+    ///
+    /// Fails with `InvalidBlock` if the sum overflows.
+    pub fn expected_coinbase_value(&self, parameters: &MimblewimbleParameters) -> Result<u64> {
+        total_fees(&self.transactions)
+            .and_then(|fees| {
+                parameters
+                    .block_reward(self.header.height)
+                    .checked_add(fees)
+            })
+            .ok_or(MimblewimbleError::InvalidBlock)
+    }
+
+    /// Check that the coinbase claims exactly `expected_coinbase_value`
+    ///
+    /// Over the coinbase transactions, verifies `sum(outputs) - sum(inputs) +
+    /// fee*H == value*H + sum(excesses) + offset*G`, which also covers a
+    /// coinbase aggregated with fee-paying transactions. A block without a
+    /// coinbase passes.
+    pub fn has_valid_coinbase(&self, parameters: &MimblewimbleParameters) -> Result<bool> {
+        let coinbases: Vec<&Transaction> = self
+            .transactions
+            .iter()
+            .filter(|tx| tx.is_coinbase())
+            .collect();
+        if coinbases.is_empty() {
+            return Ok(true);
+        }
+
+        let value = self.expected_coinbase_value(parameters)?;
+        let fees = coinbases
+            .iter()
+            .try_fold(0u64, |sum, tx| sum.checked_add(tx.fee()))
+            .ok_or(MimblewimbleError::InvalidBlock)?;
+        let sum = |points: Vec<&[u8]>| crypto::sum_points(points).ok();
+        let outputs = sum(coinbases
+            .iter()
+            .flat_map(|tx| tx.outputs())
+            .map(|output| output.commitment.as_slice())
+            .collect());
+        let inputs = sum(coinbases
+            .iter()
+            .flat_map(|tx| tx.inputs())
+            .map(|input| input.commitment.as_slice())
+            .collect());
+        let excesses = sum(coinbases
+            .iter()
+            .flat_map(|tx| tx.kernels())
+            .map(|kernel| kernel.excess.as_slice())
+            .collect());
+
+        let offset = transaction::sum_offsets(coinbases.iter().copied()).ok();
+
+        Ok(match (outputs, inputs, excesses, offset) {
+            (Some(outputs), Some(inputs), Some(excesses), Some(offset)) => {
+                outputs - inputs + crypto::commit(fees, &Scalar::ZERO)
+                    == crypto::commit(value, &offset) + excesses
+            }
+            _ => false,
+        })
+    }
+
+    /// Commitments of every input the block spends, in block order
+    ///
+    /// Includes outputs created earlier in the same block. Repeated
+    /// commitments are listed once.
+    pub fn spent_commitments(&self) -> Vec<Vec<u8>> {
+        unique(
+            self.transactions
+                .iter()
+                .flat_map(|tx| tx.inputs())
+                .map(|input| &input.commitment),
+        )
+    }
+
+    /// Commitments of every output the block creates, in block order
+    ///
+    /// Includes outputs spent later in the same block. Repeated commitments
+    /// are listed once.
+    pub fn created_commitments(&self) -> Vec<Vec<u8>> {
+        unique(
+            self.transactions
+                .iter()
+                .flat_map(|tx| tx.outputs())
+                .map(|output| &output.commitment),
+        )
+    }
+
+    /// Net commitment `sum(outputs) - sum(inputs)` and the kernel excess sum
+    /// plus the offset, or `None` if a point or offset fails to decode
+    fn net_and_excess(&self) -> Option<(ProjectivePoint, ProjectivePoint)> {
+        let outputs: Vec<Commitment> = self
+            .transactions
+            .iter()
+            .flat_map(|tx| tx.outputs().iter().cloned())
+            .collect();
+        let inputs: Vec<Commitment> = self
+            .transactions
+            .iter()
+            .flat_map(|tx| tx.inputs().iter().cloned())
+            .collect();
+        let excesses = crypto::sum_points(
+            self.transactions
+                .iter()
+                .flat_map(|tx| tx.kernels())
+                .map(|kernel| kernel.excess.as_slice()),
+        );
+
+        match (
+            transaction::sum_commitments(&outputs),
+            transaction::sum_commitments(&inputs),
+            excesses,
+            transaction::sum_offsets(&self.transactions),
+        ) {
+            (Ok(outputs), Ok(inputs), Ok(excesses), Ok(offset)) => {
+                Some((outputs - inputs, excesses + crypto::commit(0, &offset)))
+            }
+            _ => None,
+        }
+    }
+
+    /// Compute the net UTXO set change made by the block
+    ///
+    /// Outputs created and spent within the block cancel out and appear in
+    /// neither list. The delta carries the block's kernels and summed offset,
+    /// which balance its outputs. Fails if an offset does not decode.
+    pub fn utxo_delta(&self) -> Result<UtxoDelta> {
+        let mut added: Vec<Commitment> = Vec::new();
+        let mut removed = Vec::new();
+
+        for tx in &self.transactions {
+            for input in tx.inputs() {
+                match added
+                    .iter()
+                    .position(|output| output.commitment == input.commitment)
+                {
+                    Some(index) => {
+                        added.remove(index);
+                    }
+                    None => removed.push(input.commitment.clone()),
+                }
+            }
+            added.extend(tx.outputs().iter().cloned());
+        }
+
+        Ok(UtxoDelta {
+            height: self.header.height,
+            added,
+            removed,
+            kernels: self
+                .transactions
+                .iter()
+                .flat_map(|tx| tx.kernels())
+                .cloned()
+                .collect(),
+            offset: crypto::scalar_to_bytes(&transaction::sum_offsets(&self.transactions)?),
+        })
+    }
+}
+
+/// Cryptographic work needed to verify a block
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VerificationCost {
+    /// Range proofs to verify, one per output
+    pub range_proofs: usize,
+
+    /// Kernel signatures to verify
+    pub signatures: usize,
+
+    /// Points decoded and summed by the balance checks
+    pub point_operations: usize,
+}
+
+/// Savings from cutting through a block
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CutThroughStats {
+    /// Input/output pairs eliminated
+    pub pairs: usize,
+
+    /// Reduction of the block's wire size (bytes)
+    pub bytes_saved: usize,
+
+    /// Reduction of the block's transaction weight
+    pub weight_saved: u64,
+}
+
+/// Compact UTXO set change for one block
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UtxoDelta {
+    /// Height of the block the delta belongs to, at which the added outputs
+    /// were created
+    pub height: u64,
+
+    /// Outputs added to the UTXO set
+    pub added: Vec<Commitment>,
+
+    /// Commitments of outputs removed from the UTXO set
+    pub removed: Vec<Vec<u8>>,
+
+    /// Kernels of the block
+    pub kernels: Vec<Kernel>,
+
+    /// Sum of the kernel offsets of the block's transactions
+    pub offset: Vec<u8>,
+}
+
+/// Output removed from the UTXO set by applying a `UtxoDelta`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemovedOutput {
+    /// The removed output
+    pub output: Commitment,
+
+    /// Height the output was created at, if known
+    pub created_at: Option<u64>,
+}
+
+/// Block template for external miners
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockTemplate {
+    /// Header with everything but the nonce filled in
+    pub header: BlockHeader,
+
+    /// Selected transactions, coinbase first
+    pub transactions: Vec<Transaction>,
+}
+
+impl BlockTemplate {
+    /// Complete the template into a block with the given nonce
+    pub fn into_block(self, nonce: u64) -> Block {
+        Block {
+            header: BlockHeader {
+                nonce,
+                ..self.header
+            },
+            transactions: self.transactions,
+        }
+    }
+}
+
+/// Sum of the fees of transactions, or `None` on overflow
+pub(crate) fn total_fees(transactions: &[Transaction]) -> Option<u64> {
+    transactions
+        .iter()
+        .try_fold(0u64, |sum, tx| sum.checked_add(tx.fee()))
+}
+
+/// Distinct commitments in order of first appearance
+fn unique<'a>(commitments: impl Iterator<Item = &'a Vec<u8>>) -> Vec<Vec<u8>> {
+    let mut seen = std::collections::HashSet::new();
+    commitments
+        .filter(|commitment| seen.insert(*commitment))
+        .cloned()
+        .collect()
+}
+
+/// Previous hash of the genesis block
+pub(crate) fn genesis_previous_hash() -> Vec<u8> {
+    vec![0; HASH_LENGTH]
+}
+
+/// Median of the last `MEDIAN_TIME_WINDOW` header timestamps
+///
+/// A following header's timestamp must exceed this. Returns `None` for an
+/// empty chain.
+pub fn median_time_past(headers: &[BlockHeader]) -> Option<u64> {
+    let window = &headers[headers.len().saturating_sub(MEDIAN_TIME_WINDOW)..];
+    let mut timestamps: Vec<u64> = window.iter().map(|h| h.timestamp).collect();
+    timestamps.sort_unstable();
+    timestamps.get(timestamps.len() / 2).copied()
+}
+
+/// Verify a header chain starting at genesis without its block bodies
+///
+/// Checks that every header links to its predecessor, that its timestamp
+/// exceeds the median of the preceding `MEDIAN_TIME_WINDOW` timestamps, that
+/// its difficulty follows the retarget rules and that its proof of work meets
+/// that difficulty.
+pub fn verify_header_chain(
+    headers: &[BlockHeader],
+    parameters: &MimblewimbleParameters,
+) -> Result<()> {
+    let mut previous_hash = genesis_previous_hash();
+
+    for (index, header) in headers.iter().enumerate() {
+        if header.height != index as u64 || header.previous_hash != previous_hash {
+            return Err(MimblewimbleError::InvalidBlock);
+        }
+
+        if let Some(median) = median_time_past(&headers[..index]) {
+            if header.timestamp <= median {
+                return Err(MimblewimbleError::InvalidBlock);
+            }
+        }
+
+        if header.difficulty != pow::next_difficulty(&headers[..index], parameters)
+            || !pow::validate_pow(header, parameters)
+        {
+            return Err(MimblewimbleError::InvalidProofOfWork);
+        }
+
+        previous_hash = header.hash()?;
+    }
+
+    Ok(())
+}
+
+/// Compute merkle root of transactions
+pub fn compute_merkle_root(transactions: &[Transaction]) -> Result<Vec<u8>> {
+    merkle_root(transactions)
+}
+
+/// Compute the merkle root of serialized items
+///
+/// Leaves and interior nodes are hashed under distinct prefixes, and the
+/// last node of an odd-length level is carried up unchanged rather than
+/// paired with itself, so no two item lists share a root.
+///
+/// Fails with `SerializationError` if any item cannot be serialized.
+fn merkle_root<T: Serialize>(items: &[T]) -> Result<Vec<u8>> {
+    if items.is_empty() {
+        return Ok(vec![0; HASH_LENGTH]);
+    }
+
+    let mut hashes: Vec<Vec<u8>> = items
+        .iter()
+        .map(|item| {
+            Ok(merkle_hash(
+                MERKLE_LEAF_PREFIX,
+                &[&serialization::hash(item)?],
+            ))
+        })
+        .collect::<Result<_>>()?;
+
+    while hashes.len() > 1 {
+        hashes = hashes
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => merkle_hash(MERKLE_NODE_PREFIX, &[left, right]),
+                [lone] => lone.clone(),
+                _ => unreachable!("chunks of two"),
+            })
+            .collect();
+    }
+
+    Ok(hashes.swap_remove(0))
+}
+
+/// Hash merkle node contents under a domain prefix
+fn merkle_hash(prefix: u8, parts: &[&[u8]]) -> Vec<u8> {
+    let mut hasher = Sha512::new();
+    hasher.update([prefix]);
+    for part in parts {
+        hasher.update(part);
+    }
+    hex::encode(hasher.finalize()).into_bytes()
+}
+
+/// Build the merkle mountain range of an output set
+///
+/// Commitments are sorted first so the range does not depend on the order
+/// in which outputs were added.
+pub fn output_mmr(commitments: &[Vec<u8>]) -> MerkleMountainRange {
+    let mut sorted = commitments.to_vec();
+    sorted.sort();
+    MerkleMountainRange::from_commitments(&sorted)
+}
+
+/// Compute the root of an output set
+pub fn compute_output_root(commitments: &[Vec<u8>]) -> Vec<u8> {
+    output_mmr(commitments).root()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use serde::Serializer;
+
+    /// Block at `height` holding `transactions`, with a placeholder header
+    fn test_block(height: u64, transactions: Vec<Transaction>) -> Block {
+        Block {
+            header: BlockHeader {
+                version: 1,
+                height,
+                timestamp: 0,
+                previous_hash: vec![0; HASH_LENGTH],
+                merkle_root: vec![0; HASH_LENGTH],
+                output_root: vec![0; HASH_LENGTH],
+                kernel_root: vec![0; HASH_LENGTH],
+                difficulty: 1,
+                nonce: 0,
+            },
+            transactions,
+        }
+    }
 
     #[test]
     fn test_block_header_creation() {
@@ -72,6 +623,10 @@ mod tests {
             timestamp: 0,
             previous_hash: vec![0; 32],
             merkle_root: vec![0; 32],
+            output_root: vec![0; 32],
+            kernel_root: vec![0; 32],
+            difficulty: 1,
+            nonce: 0,
         };
 
         assert_eq!(header.version, 1);
@@ -86,6 +641,10 @@ mod tests {
             timestamp: 0,
             previous_hash: vec![0; 32],
             merkle_root: vec![0; 32],
+            output_root: vec![0; 32],
+            kernel_root: vec![0; 32],
+            difficulty: 1,
+            nonce: 0,
         };
 
         let block = Block {
@@ -104,6 +663,10 @@ mod tests {
             timestamp: 0,
             previous_hash: vec![0; 32],
             merkle_root: vec![0; 32],
+            output_root: vec![0; 32],
+            kernel_root: vec![0; 32],
+            difficulty: 1,
+            nonce: 0,
         };
 
         let block = Block {
@@ -111,7 +674,332 @@ mod tests {
             transactions: vec![],
         };
 
-        let hash = block.hash();
+        let hash = block.hash().unwrap();
         assert!(!hash.is_empty());
     }
+
+    #[test]
+    fn test_header_conflicts() -> Result<()> {
+        let header = BlockHeader {
+            version: 1,
+            height: 5,
+            timestamp: 0,
+            previous_hash: vec![0; 32],
+            merkle_root: vec![0; 32],
+            output_root: vec![0; 32],
+            kernel_root: vec![0; 32],
+            difficulty: 1,
+            nonce: 0,
+        };
+
+        let identical = header.clone();
+        assert!(!header.conflicts_with(&identical));
+
+        let fork = BlockHeader {
+            nonce: 1,
+            ..header.clone()
+        };
+        assert_ne!(fork.hash()?, header.hash()?);
+        assert!(header.conflicts_with(&fork));
+
+        let next = BlockHeader {
+            height: 6,
+            ..header.clone()
+        };
+        assert!(!header.conflicts_with(&next));
+        Ok(())
+    }
+
+    #[test]
+    fn test_utxo_delta_cuts_through() -> Result<()> {
+        use crate::kernel::{Kernel, KernelFeatures};
+        use crate::range_proof::RangeProof;
+
+        let output = |value: u64| {
+            Commitment::new(value, vec![value as u8; 32], RangeProof::new(vec![1; 64]))
+        };
+        let kernel = Kernel::new(KernelFeatures::Plain, 0, 0, vec![1; 32], vec![2; 64]);
+        let (a, b, c) = (output(1)?, output(2)?, output(3)?);
+
+        let block = test_block(
+            7,
+            vec![
+                Transaction::new(1, vec![a.clone()], vec![b.clone()], kernel.clone(), 0),
+                Transaction::new(1, vec![b], vec![c.clone()], kernel, 0),
+            ],
+        );
+
+        let delta = block.utxo_delta()?;
+        assert_eq!(delta.height, 7);
+        assert_eq!(delta.added, vec![c]);
+        assert_eq!(delta.removed, vec![a.commitment]);
+        assert_eq!(delta.kernels.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_spent_and_created_commitments() {
+        use crate::tests::{balanced_tx, output};
+
+        let (a, b, c) = (output(1000, 1), output(990, 2), output(980, 3));
+
+        // The second transaction spends the first one's output
+        let first = balanced_tx(vec![a.clone()], vec![b.clone()], 10);
+        let second = balanced_tx(vec![b.clone()], vec![c.clone()], 10);
+        let chained = test_block(1, vec![first.clone(), second]);
+        assert_eq!(
+            chained.spent_commitments(),
+            vec![a.commitment.clone(), b.commitment.clone()]
+        );
+        assert_eq!(
+            chained.created_commitments(),
+            vec![b.commitment.clone(), c.commitment.clone()]
+        );
+
+        // A commitment appearing twice is listed once
+        let repeated = test_block(1, vec![first.clone(), first]);
+        assert_eq!(repeated.spent_commitments(), vec![a.commitment]);
+        assert_eq!(repeated.created_commitments(), vec![b.commitment]);
+    }
+
+    #[test]
+    fn test_net_commitment_delta() -> Result<()> {
+        use crate::tests::{balanced_tx, output};
+
+        let params = MimblewimbleParameters::default();
+        let reward = params.block_reward(3);
+        let spend = balanced_tx(vec![output(1000, 1)], vec![output(990, 2)], 10);
+        let coinbase = Transaction::coinbase(3, 10, &[3; 32], &params)?;
+        let excess_sum = |block: &Block| {
+            crypto::sum_points(
+                block
+                    .transactions
+                    .iter()
+                    .flat_map(|tx| tx.kernels())
+                    .map(|kernel| kernel.excess.as_slice()),
+            )
+        };
+
+        // The coinbase claims the fee, so the block mints only the reward
+        let full = test_block(3, vec![coinbase, spend.clone()]);
+        assert_eq!(
+            full.net_commitment_delta()?,
+            crypto::point_to_bytes(&(excess_sum(&full)? + crypto::commit(reward, &Scalar::ZERO)))
+        );
+
+        // Without a coinbase the fee leaves the commitment sum
+        let burned = test_block(3, vec![spend]);
+        assert_eq!(
+            burned.net_commitment_delta()?,
+            crypto::point_to_bytes(&(excess_sum(&burned)? - crypto::commit(10, &Scalar::ZERO)))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_estimated_verification_cost() -> Result<()> {
+        use crate::kernel::{Kernel, KernelFeatures};
+        use crate::range_proof::RangeProof;
+
+        let output = |value: u64| {
+            Commitment::new(value, vec![value as u8; 32], RangeProof::new(vec![1; 64]))
+        };
+        let kernel = Kernel::new(KernelFeatures::Plain, 0, 0, vec![1; 32], vec![2; 64]);
+
+        let small = test_block(
+            0,
+            vec![Transaction::new(
+                1,
+                vec![output(1)?],
+                vec![output(2)?],
+                kernel.clone(),
+                0,
+            )],
+        );
+        let large = test_block(
+            0,
+            (0..10u64)
+                .map(|n| {
+                    Ok(Transaction::new(
+                        1,
+                        vec![output(n)?],
+                        vec![output(n + 10)?, output(n + 20)?],
+                        kernel.clone(),
+                        0,
+                    ))
+                })
+                .collect::<Result<_>>()?,
+        );
+
+        assert_eq!(
+            small.estimated_verification_cost(),
+            VerificationCost {
+                range_proofs: 1,
+                signatures: 1,
+                point_operations: 4,
+            }
+        );
+        assert_eq!(
+            large.estimated_verification_cost(),
+            VerificationCost {
+                range_proofs: 20,
+                signatures: 10,
+                point_operations: 50,
+            }
+        );
+        assert_eq!(
+            test_block(0, vec![]).estimated_verification_cost(),
+            VerificationCost::default()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_header_chain() -> Result<()> {
+        let params = MimblewimbleParameters {
+            difficulty_adjustment_interval: 4,
+            initial_difficulty: 100,
+            ..Default::default()
+        };
+
+        // Mine a chain whose blocks arrive twice as fast as the target
+        let mine_next = |headers: &[BlockHeader], mut header: BlockHeader| -> Result<BlockHeader> {
+            header.difficulty = pow::next_difficulty(headers, &params);
+            pow::mine(&mut header, &params)?;
+            Ok(header)
+        };
+        let mut headers: Vec<BlockHeader> = Vec::new();
+        for height in 0..6 {
+            let header = BlockHeader {
+                version: 1,
+                height,
+                timestamp: height * params.target_block_time / 2,
+                previous_hash: match headers.last() {
+                    Some(last) => last.hash()?,
+                    None => genesis_previous_hash(),
+                },
+                merkle_root: vec![0; 32],
+                output_root: vec![0; 32],
+                kernel_root: vec![0; 32],
+                difficulty: 0,
+                nonce: 0,
+            };
+            let header = mine_next(&headers, header)?;
+            headers.push(header);
+        }
+        verify_header_chain(&headers, &params)?;
+        assert_eq!(headers[4].difficulty, 200);
+
+        let (earlier, last) = headers.split_at(5);
+        let check = |last: BlockHeader| {
+            let mut chain = earlier.to_vec();
+            chain.push(last);
+            verify_header_chain(&chain, &params)
+        };
+
+        let mut unlinked = last[0].clone();
+        unlinked.previous_hash = earlier[3].hash()?;
+        assert!(matches!(
+            check(unlinked),
+            Err(MimblewimbleError::InvalidBlock)
+        ));
+
+        let mut early = last[0].clone();
+        early.timestamp = earlier[2].timestamp;
+        let early = mine_next(earlier, early)?;
+        assert!(matches!(check(early), Err(MimblewimbleError::InvalidBlock)));
+
+        let mut wrong_difficulty = last[0].clone();
+        wrong_difficulty.difficulty = 1;
+        pow::mine(&mut wrong_difficulty, &params)?;
+        assert!(matches!(
+            check(wrong_difficulty),
+            Err(MimblewimbleError::InvalidProofOfWork)
+        ));
+
+        let mut unworked = last[0].clone();
+        while pow::validate_pow(&unworked, &params) {
+            unworked.nonce += 1;
+        }
+        assert!(matches!(
+            check(unworked),
+            Err(MimblewimbleError::InvalidProofOfWork)
+        ));
+        Ok(())
+    }
+
+    /// Item whose serialization always fails
+    struct Unserializable;
+
+    impl Serialize for Unserializable {
+        fn serialize<S: Serializer>(&self, _serializer: S) -> std::result::Result<S::Ok, S::Error> {
+            Err(serde::ser::Error::custom("injected failure"))
+        }
+    }
+
+    #[test]
+    fn test_merkle_root_resists_duplicated_items() -> Result<()> {
+        let params = MimblewimbleParameters::default();
+        let txs = (1..=4u8)
+            .map(|key| Transaction::coinbase(0, 0, &[key; 32], &params))
+            .collect::<Result<Vec<_>>>()?;
+        let (a, b, c, d) = (&txs[0], &txs[1], &txs[2], &txs[3]);
+
+        // Duplicating the last item of an odd level no longer reproduces the root
+        let root = compute_merkle_root(&[a.clone(), b.clone(), c.clone()])?;
+        assert_ne!(
+            root,
+            compute_merkle_root(&[a.clone(), b.clone(), c.clone(), c.clone()])?
+        );
+        assert_ne!(
+            compute_merkle_root(&txs)?,
+            compute_merkle_root(&[a, b, c, d, c, d].map(Transaction::clone))?
+        );
+
+        // A single item's root is its tagged leaf, not its plain hash
+        let single = compute_merkle_root(std::slice::from_ref(a))?;
+        assert_eq!(single.len(), HASH_LENGTH);
+        assert_ne!(single, a.hash()?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_cut_through_savings() -> Result<()> {
+        use crate::tests::{balanced_tx, output};
+        use crate::transaction::{INPUT_WEIGHT, OUTPUT_WEIGHT};
+
+        let outputs: Vec<Commitment> = (1..=6).map(|n| output(1000 - 10 * n as u64, n)).collect();
+        let spend = |from: usize, to: usize| {
+            balanced_tx(vec![outputs[from].clone()], vec![outputs[to].clone()], 10)
+        };
+
+        // Nothing is spent within a block of unrelated transactions
+        let unrelated = test_block(1, vec![spend(0, 1), spend(4, 5)]);
+        assert_eq!(unrelated.cut_through_savings()?, CutThroughStats::default());
+
+        // Two outputs are created and spent in the same block
+        let chained = test_block(1, vec![spend(0, 1), spend(1, 2), spend(2, 3), spend(4, 5)]);
+        let savings = chained.cut_through_savings()?;
+        assert_eq!(savings.pairs, 2);
+        assert_eq!(savings.weight_saved, 2 * (INPUT_WEIGHT + OUTPUT_WEIGHT));
+
+        // The byte savings match dropping the pairs by hand
+        let mut cut = chained.clone();
+        cut.transactions[1].inputs_mut().clear();
+        cut.transactions[0].outputs_mut().clear();
+        cut.transactions[2].inputs_mut().clear();
+        cut.transactions[1].outputs_mut().clear();
+        assert!(savings.bytes_saved > 0);
+        assert_eq!(savings.bytes_saved, chained.wire_size()? - cut.wire_size()?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_merkle_root_propagates_serialization_failure() {
+        assert!(matches!(
+            merkle_root(&[Unserializable, Unserializable]),
+            Err(MimblewimbleError::SerializationError(_))
+        ));
+    }
 }
@@ -0,0 +1,80 @@
+//! Time sources for block timestamps
+//!
+//! `MimblewimbleState` reads the time through a `TimeSource`, the system
+//! clock by default. `ManualTimeSource` gives tests a clock they control.
+
+use crate::errors::{MimblewimbleError, Result};
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Source of the current time
+pub trait TimeSource: Debug + Send + Sync {
+    /// Current UNIX timestamp in seconds
+    fn now(&self) -> Result<u64>;
+}
+
+/// The system clock
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemTimeSource;
+
+impl TimeSource for SystemTimeSource {
+    fn now(&self) -> Result<u64> {
+        timestamp_from(SystemTime::now())
+    }
+}
+
+/// UNIX timestamp of a point in time, failing if it is before the epoch
+fn timestamp_from(time: SystemTime) -> Result<u64> {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .map_err(|e| MimblewimbleError::ClockError(e.to_string()))
+}
+
+/// Clock that only moves when told to
+#[derive(Debug, Default)]
+pub struct ManualTimeSource {
+    /// Current UNIX timestamp in seconds
+    now: AtomicU64,
+}
+
+impl ManualTimeSource {
+    /// Create a clock reading `timestamp`
+    pub fn new(timestamp: u64) -> Self {
+        Self {
+            now: AtomicU64::new(timestamp),
+        }
+    }
+
+    /// Set the clock to `timestamp`
+    pub fn set(&self, timestamp: u64) {
+        self.now.store(timestamp, Ordering::SeqCst);
+    }
+
+    /// Move the clock forward by `seconds`
+    pub fn advance(&self, seconds: u64) {
+        self.now.fetch_add(seconds, Ordering::SeqCst);
+    }
+}
+
+impl TimeSource for ManualTimeSource {
+    fn now(&self) -> Result<u64> {
+        Ok(self.now.load(Ordering::SeqCst))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_clock_before_epoch_is_an_error() {
+        let before_epoch = UNIX_EPOCH - Duration::from_secs(1);
+        assert!(matches!(
+            timestamp_from(before_epoch),
+            Err(MimblewimbleError::ClockError(_))
+        ));
+        assert_eq!(timestamp_from(UNIX_EPOCH).ok(), Some(0));
+    }
+}
@@ -1,21 +1,27 @@
 //! Pedersen commitments for Mimblewimble
 
+use crate::crypto;
 use crate::errors::Result;
+use crate::parameters::MimblewimbleParameters;
 use crate::range_proof::RangeProof;
-use hex;
-use serde::{Deserialize, Serialize};
-use sha2::{Digest, Sha512};
+use serde::{Deserialize, Deserializer, Serialize};
 
 /// Pedersen commitment
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+///
+/// The value and blinding factor are wallet-local secrets: they are never
+/// serialized and do not take part in equality.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Commitment {
-    /// Commitment value
+    /// Commitment value, a compressed curve point
+    #[serde(deserialize_with = "deserialize_point")]
     pub commitment: Vec<u8>,
 
-    /// Committed value
+    /// Committed value, known only to the owner and never serialized
+    #[serde(skip)]
     pub value: u64,
 
-    /// Blinding factor
+    /// Blinding factor, known only to the owner and never serialized
+    #[serde(skip)]
     pub blinding: Vec<u8>,
 
     /// Range proof
@@ -25,12 +31,9 @@ pub struct Commitment {
 impl Commitment {
     /// Create a new commitment
     pub fn new(value: u64, blinding: Vec<u8>, range_proof: RangeProof) -> Result<Self> {
-        // Compute commitment: H(value || blinding)
-        let mut hasher = Sha512::new();
-        hasher.update(value.to_le_bytes());
-        hasher.update(&blinding);
-
-        let commitment = hex::encode(hasher.finalize()).into_bytes();
+        // Compute commitment: value*H + blinding*G
+        let blinding_scalar = crypto::scalar_from_bytes(&blinding)?;
+        let commitment = crypto::point_to_bytes(&crypto::commit(value, &blinding_scalar));
 
         Ok(Self {
             commitment,
@@ -40,6 +43,57 @@ impl Commitment {
         })
     }
 
+    /// Create a switch commitment
+    ///
+    /// The stored blinding factor is the switched one, which is the key
+    /// needed to spend the output.
+    pub fn new_switch(value: u64, blinding: Vec<u8>, range_proof: RangeProof) -> Result<Self> {
+        let blinding_scalar = crypto::scalar_from_bytes(&blinding)?;
+        let switched = crypto::switch_blinding(value, &blinding_scalar);
+        Self::new(value, crypto::scalar_to_bytes(&switched), range_proof)
+    }
+
+    /// Create a commitment, using switch commitments if the parameters ask for them
+    pub fn with_parameters(
+        value: u64,
+        blinding: Vec<u8>,
+        range_proof: RangeProof,
+        parameters: &MimblewimbleParameters,
+    ) -> Result<Self> {
+        if parameters.use_switch_commitments {
+            Self::new_switch(value, blinding, range_proof)
+        } else {
+            Self::new(value, blinding, range_proof)
+        }
+    }
+
+    /// Create a commitment with a range proof bound to it
+    ///
+    /// The proof carries the value encrypted under the blinding key, so
+    /// the key's owner can recover it with `RangeProof::rewind`.
+    pub fn create(
+        value: u64,
+        blinding: Vec<u8>,
+        parameters: &MimblewimbleParameters,
+    ) -> Result<Self> {
+        let mut output =
+            Self::with_parameters(value, blinding.clone(), RangeProof::absent(), parameters)?;
+        output.range_proof =
+            RangeProof::create_with_rewind(value, &output.commitment, &blinding, parameters)?;
+        Ok(output)
+    }
+
+    /// Check that the commitment opens to its value and blinding factor
+    pub fn verify(&self) -> Result<bool> {
+        let blinding = crypto::scalar_from_bytes(&self.blinding)?;
+        Ok(crypto::point_to_bytes(&crypto::commit(self.value, &blinding)) == self.commitment)
+    }
+
+    /// Check that the commitment has the length of a compressed point
+    pub fn has_valid_length(&self) -> bool {
+        self.commitment.len() == crypto::POINT_LENGTH
+    }
+
     /// Get the commitment value
     pub fn commitment(&self) -> &[u8] {
         &self.commitment
@@ -56,6 +110,28 @@ impl Commitment {
     }
 }
 
+impl PartialEq for Commitment {
+    fn eq(&self, other: &Self) -> bool {
+        self.commitment == other.commitment && self.range_proof == other.range_proof
+    }
+}
+
+impl Eq for Commitment {}
+
+/// Deserialize commitment bytes, rejecting any length but a compressed point's
+fn deserialize_point<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Vec<u8>, D::Error> {
+    let bytes = Vec::<u8>::deserialize(deserializer)?;
+    if bytes.len() != crypto::POINT_LENGTH {
+        return Err(serde::de::Error::invalid_length(
+            bytes.len(),
+            &"a compressed curve point",
+        ));
+    }
+    Ok(bytes)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -70,4 +146,91 @@ mod tests {
         let commitment = Commitment::new(1000, blinding, range_proof);
         assert!(commitment.is_ok());
     }
+
+    #[test]
+    fn test_switch_commitments() -> Result<()> {
+        let proof = || RangeProof::new(vec![1; 64]);
+        let plain = Commitment::new(1000, vec![42; 32], proof())?;
+        let switch = Commitment::new_switch(1000, vec![42; 32], proof())?;
+        assert_ne!(plain.commitment, switch.commitment);
+        assert!(plain.verify()? && switch.verify()?);
+
+        // Switch commitments still add up homomorphically
+        let other = Commitment::new_switch(500, vec![7; 32], proof())?;
+        let sum = crypto::sum_points([switch.commitment(), other.commitment()])?;
+        let blinding = crypto::scalar_from_bytes(switch.blinding())?
+            + crypto::scalar_from_bytes(other.blinding())?;
+        assert_eq!(sum, crypto::commit(1500, &blinding));
+
+        let params = MimblewimbleParameters {
+            use_switch_commitments: true,
+            ..Default::default()
+        };
+        let chosen = Commitment::with_parameters(1000, vec![42; 32], proof(), &params)?;
+        assert_eq!(chosen, switch);
+        Ok(())
+    }
+
+    #[test]
+    fn test_deserialization_rejects_bad_lengths() -> Result<()> {
+        let commitment = Commitment::new(1000, vec![42; 32], RangeProof::new(vec![1; 64]))?;
+        let bytes = crate::serialization::to_vec(&commitment)?;
+        assert_eq!(
+            crate::serialization::from_slice::<Commitment>(&bytes)?,
+            commitment
+        );
+
+        for length in [16, 64] {
+            let mut short = commitment.clone();
+            short.commitment.truncate(length);
+            assert!(!short.has_valid_length());
+            let bytes = crate::serialization::to_vec(&short)?;
+            assert!(crate::serialization::from_slice::<Commitment>(&bytes).is_err());
+        }
+        Ok(())
+    }
+
+    /// Search small values and small blinding factors for an opening
+    fn brute_force_opening(commitment: &[u8], max_value: u64, max_blinding: u64) -> Option<u64> {
+        use p521::{ProjectivePoint, Scalar};
+
+        let target = crypto::point_from_bytes(commitment).ok()?;
+        let blinded: Vec<ProjectivePoint> = (0..=max_blinding)
+            .map(|b| ProjectivePoint::GENERATOR * Scalar::from(b))
+            .collect();
+
+        // Walk target - value*H by repeated subtraction of H
+        let mut remainder = target;
+        for value in 0..=max_value {
+            if blinded.contains(&remainder) {
+                return Some(value);
+            }
+            remainder -= crypto::generator_h();
+        }
+        None
+    }
+
+    #[test]
+    fn test_small_values_are_hidden() -> Result<()> {
+        let proof = || RangeProof::new(vec![1; 64]);
+
+        // The search recovers values committed with a guessable blinding
+        let weak = Commitment::new(
+            7,
+            crypto::scalar_to_bytes(&p521::Scalar::from(3u64)),
+            proof(),
+        )?;
+        assert_eq!(brute_force_opening(weak.commitment(), 256, 8), Some(7));
+
+        // A full-entropy blinding hides even the smallest values
+        for value in [0u64, 1, 7, 255] {
+            let blinding = crypto::scalar_to_bytes(&crypto::hash_to_scalar(
+                b"hiding-test",
+                &[&value.to_be_bytes()],
+            ));
+            let hidden = Commitment::new(value, blinding, proof())?;
+            assert_eq!(brute_force_opening(hidden.commitment(), 256, 8), None);
+        }
+        Ok(())
+    }
 }
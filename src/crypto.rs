@@ -0,0 +1,334 @@
+//! Elliptic curve primitives for Mimblewimble
+//!
+//! Commitments, kernel excesses and signatures live on the NIST P-521
+//! curve. Commitments are Pedersen commitments `value*H + blinding*G` and
+//! signatures are Schnorr signatures with deterministically derived nonces.
+
+use crate::errors::{MimblewimbleError, Result};
+use lru::LruCache;
+use p521::elliptic_curve::group::GroupEncoding;
+use p521::{AffinePoint, FieldBytes, ProjectivePoint, Scalar};
+use parking_lot::Mutex;
+use sha2::{Digest, Sha512};
+use std::num::NonZeroUsize;
+use std::sync::OnceLock;
+
+/// Length of a compressed curve point
+pub const POINT_LENGTH: usize = 67;
+
+/// Length of an encoded scalar
+pub const SCALAR_LENGTH: usize = 66;
+
+/// Length of a Schnorr signature (nonce point followed by scalar)
+pub const SIGNATURE_LENGTH: usize = POINT_LENGTH + SCALAR_LENGTH;
+
+/// Domain tag for nonce derivation
+const NONCE_TAG: &[u8] = b"silver-mimblewimble/nonce";
+
+/// Domain tag for the Schnorr challenge
+const CHALLENGE_TAG: &[u8] = b"silver-mimblewimble/challenge";
+
+/// Domain tag for deriving the value generator
+const GENERATOR_H_TAG: &[u8] = b"silver-mimblewimble/generator-h";
+
+/// Domain tag for deriving the switch commitment generator
+const GENERATOR_J_TAG: &[u8] = b"silver-mimblewimble/generator-j";
+
+/// Domain tag for the switch commitment blinding adjustment
+const SWITCH_TAG: &[u8] = b"silver-mimblewimble/switch";
+
+/// Value generator `H` for Pedersen commitments
+pub fn generator_h() -> ProjectivePoint {
+    static GENERATOR_H: OnceLock<ProjectivePoint> = OnceLock::new();
+    *GENERATOR_H.get_or_init(|| nothing_up_my_sleeve(GENERATOR_H_TAG))
+}
+
+/// Switch commitment generator `J`
+pub fn generator_j() -> ProjectivePoint {
+    static GENERATOR_J: OnceLock<ProjectivePoint> = OnceLock::new();
+    *GENERATOR_J.get_or_init(|| nothing_up_my_sleeve(GENERATOR_J_TAG))
+}
+
+/// Derive a generator from a tag
+///
+/// Nobody knows the generator's discrete logarithm with respect to `G`.
+fn nothing_up_my_sleeve(tag: &[u8]) -> ProjectivePoint {
+    hash_to_point(tag, &[])
+}
+
+/// Hash arbitrary data to a curve point
+///
+/// Hashes to an x-coordinate, with a counter, until it lies on the curve,
+/// so the discrete logarithm of the result is unknown.
+pub fn hash_to_point(tag: &[u8], data: &[&[u8]]) -> ProjectivePoint {
+    let mut counter = 0u32;
+    loop {
+        let mut hasher = Sha512::new();
+        hasher.update(tag);
+        for item in data {
+            hasher.update(item);
+        }
+        let digest = hasher.chain_update(counter.to_be_bytes()).finalize();
+
+        let mut encoded = [0u8; POINT_LENGTH];
+        encoded[0] = 0x02;
+        encoded[POINT_LENGTH - digest.len()..].copy_from_slice(&digest);
+
+        if let Ok(point) = point_from_bytes(&encoded) {
+            return point;
+        }
+        counter += 1;
+    }
+}
+
+/// Compute the switch commitment blinding factor
+///
+/// Returns `blinding + Hash(value*H + blinding*G, blinding*J)`, binding the
+/// commitment to an ElGamal-style commitment that stays sound should the
+/// discrete logarithm problem be broken.
+pub fn switch_blinding(value: u64, blinding: &Scalar) -> Scalar {
+    let commitment = point_to_bytes(&commit(value, blinding));
+    let switch = point_to_bytes(&(generator_j() * blinding));
+    *blinding + hash_to_scalar(SWITCH_TAG, &[&commitment, &switch])
+}
+
+/// Compute the Pedersen commitment `value*H + blinding*G`
+pub fn commit(value: u64, blinding: &Scalar) -> ProjectivePoint {
+    generator_h() * Scalar::from(value) + ProjectivePoint::GENERATOR * blinding
+}
+
+/// Sum encoded points, failing on any invalid encoding
+pub fn sum_points<'a>(points: impl IntoIterator<Item = &'a [u8]>) -> Result<ProjectivePoint> {
+    points
+        .into_iter()
+        .try_fold(ProjectivePoint::IDENTITY, |sum, bytes| {
+            Ok(sum + point_from_bytes(bytes)?)
+        })
+}
+
+/// Interpret big-endian bytes as a scalar
+pub fn scalar_from_bytes(bytes: &[u8]) -> Result<Scalar> {
+    if bytes.len() > SCALAR_LENGTH {
+        return Err(MimblewimbleError::CryptoError(
+            "scalar encoding too long".to_string(),
+        ));
+    }
+
+    let mut repr = FieldBytes::default();
+    repr[SCALAR_LENGTH - bytes.len()..].copy_from_slice(bytes);
+
+    Option::from(Scalar::from_bytes(&repr))
+        .ok_or_else(|| MimblewimbleError::CryptoError("scalar out of range".to_string()))
+}
+
+/// Encode a scalar as big-endian bytes
+pub fn scalar_to_bytes(scalar: &Scalar) -> Vec<u8> {
+    scalar.to_bytes().to_vec()
+}
+
+/// Hash arbitrary data to a scalar
+pub fn hash_to_scalar(tag: &[u8], data: &[&[u8]]) -> Scalar {
+    let mut hasher = Sha512::new();
+    hasher.update(tag);
+    for item in data {
+        hasher.update(item);
+    }
+
+    // A 512-bit digest is always below the 521-bit group order
+    let mut repr = FieldBytes::default();
+    repr[SCALAR_LENGTH - 64..].copy_from_slice(&hasher.finalize());
+    Scalar::from_bytes(&repr).unwrap()
+}
+
+/// Encode a curve point in compressed form
+pub fn point_to_bytes(point: &ProjectivePoint) -> Vec<u8> {
+    point.to_affine().to_bytes().to_vec()
+}
+
+/// Decode a compressed curve point
+pub fn point_from_bytes(bytes: &[u8]) -> Result<ProjectivePoint> {
+    if bytes.len() != POINT_LENGTH {
+        return Err(MimblewimbleError::CryptoError(
+            "invalid point length".to_string(),
+        ));
+    }
+
+    if bytes.iter().all(|b| *b == 0) {
+        return Ok(ProjectivePoint::IDENTITY);
+    }
+
+    let mut repr = <AffinePoint as GroupEncoding>::Repr::default();
+    repr.copy_from_slice(bytes);
+
+    Option::<AffinePoint>::from(AffinePoint::from_bytes(&repr))
+        .map(ProjectivePoint::from)
+        .ok_or_else(|| MimblewimbleError::CryptoError("invalid point encoding".to_string()))
+}
+
+/// Bounded least-recently-used cache of decoded points
+///
+/// Decompressing a point needs a modular square root, so verifiers that see
+/// the same commitments repeatedly keep recently decoded points around.
+/// Invalid encodings are never cached.
+#[derive(Debug)]
+pub struct PointCache {
+    points: Mutex<LruCache<Vec<u8>, ProjectivePoint>>,
+}
+
+impl PointCache {
+    /// Create a cache holding at most `capacity` points
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        Self {
+            points: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+
+    /// Decode a compressed point, consulting the cache first
+    pub fn point(&self, bytes: &[u8]) -> Result<ProjectivePoint> {
+        if let Some(point) = self.points.lock().get(bytes) {
+            return Ok(*point);
+        }
+
+        let point = point_from_bytes(bytes)?;
+        self.points.lock().put(bytes.to_vec(), point);
+        Ok(point)
+    }
+
+    /// Sum encoded points like `sum_points`, decoding through the cache
+    pub fn sum_points<'a>(
+        &self,
+        points: impl IntoIterator<Item = &'a [u8]>,
+    ) -> Result<ProjectivePoint> {
+        points
+            .into_iter()
+            .try_fold(ProjectivePoint::IDENTITY, |sum, bytes| {
+                Ok(sum + self.point(bytes)?)
+            })
+    }
+
+    /// Number of cached points
+    pub fn len(&self) -> usize {
+        self.points.lock().len()
+    }
+
+    /// Check whether the cache is empty
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Compute the public key for a secret key
+pub fn public_key(secret: &[u8]) -> Result<Vec<u8>> {
+    let secret = secret_scalar(secret)?;
+    Ok(point_to_bytes(&(ProjectivePoint::GENERATOR * secret)))
+}
+
+/// Derive a signing nonce deterministically from the secret key and message
+///
+/// The nonce is a tagged hash of the secret and the message (in the spirit
+/// of RFC 6979), so signing the same message twice can never leak the key
+/// through nonce reuse.
+pub fn derive_nonce(secret: &Scalar, message: &[u8]) -> Scalar {
+    let secret_bytes = secret.to_bytes();
+    let mut counter = 0u32;
+
+    loop {
+        let nonce = hash_to_scalar(NONCE_TAG, &[&secret_bytes, message, &counter.to_be_bytes()]);
+        if !bool::from(nonce.is_zero()) {
+            return nonce;
+        }
+        counter += 1;
+    }
+}
+
+/// Create a Schnorr signature over a message
+pub fn sign(secret: &[u8], message: &[u8]) -> Result<Vec<u8>> {
+    let secret = secret_scalar(secret)?;
+    let public = point_to_bytes(&(ProjectivePoint::GENERATOR * secret));
+
+    let nonce = derive_nonce(&secret, message);
+    let nonce_point = point_to_bytes(&(ProjectivePoint::GENERATOR * nonce));
+
+    let challenge = hash_to_scalar(CHALLENGE_TAG, &[&nonce_point, &public, message]);
+    let s = nonce + challenge * secret;
+
+    let mut signature = nonce_point;
+    signature.extend_from_slice(&s.to_bytes());
+    Ok(signature)
+}
+
+/// Verify a Schnorr signature over a message
+pub fn verify(public_key: &[u8], message: &[u8], signature: &[u8]) -> Result<bool> {
+    if signature.len() != SIGNATURE_LENGTH {
+        return Ok(false);
+    }
+
+    let public = point_from_bytes(public_key)?;
+    let (nonce_point, s) = signature.split_at(POINT_LENGTH);
+    let nonce = point_from_bytes(nonce_point)?;
+    let s = scalar_from_bytes(s)?;
+
+    let challenge = hash_to_scalar(CHALLENGE_TAG, &[nonce_point, public_key, message]);
+
+    Ok(ProjectivePoint::GENERATOR * s == nonce + public * challenge)
+}
+
+/// Parse a secret key, rejecting zero
+fn secret_scalar(secret: &[u8]) -> Result<Scalar> {
+    let secret = scalar_from_bytes(secret)?;
+    if bool::from(secret.is_zero()) {
+        return Err(MimblewimbleError::CryptoError(
+            "zero secret key".to_string(),
+        ));
+    }
+    Ok(secret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_and_verify() -> Result<()> {
+        let secret = vec![7; 32];
+        let public = public_key(&secret)?;
+        let signature = sign(&secret, b"message")?;
+
+        assert!(verify(&public, b"message", &signature)?);
+        assert!(!verify(&public, b"other message", &signature)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_point_round_trip() -> Result<()> {
+        let point = ProjectivePoint::GENERATOR * Scalar::from(42u64);
+        let bytes = point_to_bytes(&point);
+        assert_eq!(bytes.len(), POINT_LENGTH);
+        assert_eq!(point_from_bytes(&bytes)?, point);
+        assert!(point_from_bytes(&[1; 32]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_commitments_are_homomorphic() -> Result<()> {
+        let (a, b) = (Scalar::from(11u64), Scalar::from(31u64));
+        assert_eq!(commit(5, &a) + commit(7, &b), commit(12, &(a + b)));
+        assert_ne!(generator_h(), ProjectivePoint::GENERATOR);
+        assert_ne!(generator_j(), generator_h());
+        Ok(())
+    }
+
+    #[test]
+    fn test_hash_to_point() {
+        assert_eq!(hash_to_point(GENERATOR_H_TAG, &[]), generator_h());
+        assert_eq!(
+            hash_to_point(b"tag", &[b"a"]),
+            hash_to_point(b"tag", &[b"a"])
+        );
+        assert_ne!(
+            hash_to_point(b"tag", &[b"a"]),
+            hash_to_point(b"tag", &[b"b"])
+        );
+        assert_ne!(hash_to_point(b"tag", &[b"a"]), ProjectivePoint::IDENTITY);
+    }
+}
@@ -0,0 +1,403 @@
+//! Dandelion-style stem/fluff transaction relay
+//!
+//! A new transaction first enters the stem phase, in which it is relayed to
+//! a single peer, and is held for a random embargo before it fluffs and is
+//! broadcast to all peers. Transactions fluffing together are aggregated
+//! where possible, hiding which inputs and outputs belong together. Each stem
+//! hop relays a shuffled copy, and the receiver restores canonical order.
+
+use crate::errors::{MimblewimbleError, Result};
+use crate::mempool::TxId;
+use crate::parameters::MimblewimbleParameters;
+use crate::transaction::Transaction;
+use parking_lot::Mutex;
+use rand::Rng;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+/// Shortest stem-phase embargo by default
+const DEFAULT_MIN_EMBARGO: Duration = Duration::from_secs(10);
+
+/// Longest stem-phase embargo by default
+const DEFAULT_MAX_EMBARGO: Duration = Duration::from_secs(30);
+
+/// Most transactions held in the stem phase by default
+const DEFAULT_MAX_STEM_SIZE: usize = 10_000;
+
+/// Relay phase of a transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Relayed to a single peer
+    Stem,
+
+    /// Broadcast to all peers
+    Fluff,
+}
+
+/// Position of a stemmed transaction: end of its embargo, then arrival order
+type StemKey = (Instant, u64);
+
+/// Transactions in the stem phase
+#[derive(Debug, Default)]
+struct StemPool {
+    /// Stemmed transactions, ordered by the end of their embargo
+    entries: BTreeMap<StemKey, Transaction>,
+
+    /// Transaction hash to its position in `entries`
+    by_hash: HashMap<TxId, StemKey>,
+
+    /// Arrival counter breaking ties between equal embargoes
+    next_sequence: u64,
+}
+
+/// Stem-phase transactions waiting to fluff
+#[derive(Debug)]
+pub struct DandelionState {
+    /// Shortest embargo
+    min_embargo: Duration,
+
+    /// Longest embargo
+    max_embargo: Duration,
+
+    /// Most transactions held in the stem phase
+    max_stem_size: usize,
+
+    /// Transactions in the stem phase
+    stem: Mutex<StemPool>,
+}
+
+impl Default for DandelionState {
+    fn default() -> Self {
+        Self::with_embargo(DEFAULT_MIN_EMBARGO, DEFAULT_MAX_EMBARGO)
+    }
+}
+
+impl DandelionState {
+    /// Create an empty stem pool
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create an empty stem pool drawing embargoes from `min..=max`
+    pub fn with_embargo(min: Duration, max: Duration) -> Self {
+        Self {
+            min_embargo: min.min(max),
+            max_embargo: max.max(min),
+            max_stem_size: DEFAULT_MAX_STEM_SIZE,
+            stem: Mutex::new(StemPool::default()),
+        }
+    }
+
+    /// Limit the number of transactions held in the stem phase
+    pub fn with_max_stem_size(mut self, max_stem_size: usize) -> Self {
+        self.max_stem_size = max_stem_size;
+        self
+    }
+
+    /// Enter a transaction into the stem phase
+    ///
+    /// The transaction is put in canonical order and must pass its stateless
+    /// checks. Fails with `PoolFull` once the stem pool holds
+    /// `max_stem_size` transactions. Adding a transaction already in the pool
+    /// does nothing.
+    pub fn add(&self, transaction: Transaction, parameters: &MimblewimbleParameters) -> Result<()> {
+        transaction.validate(parameters)?;
+        self.add_at(transaction, Instant::now())
+    }
+
+    /// Enter a transaction into the stem phase at a point in time
+    fn add_at(&self, mut transaction: Transaction, now: Instant) -> Result<()> {
+        transaction.sort_canonical();
+        let hash = transaction.hash()?;
+        let embargo = rand::thread_rng().gen_range(self.min_embargo..=self.max_embargo);
+        let mut stem = self.stem.lock();
+        if stem.by_hash.contains_key(&hash) {
+            return Ok(());
+        }
+        if stem.entries.len() >= self.max_stem_size {
+            return Err(MimblewimbleError::PoolFull);
+        }
+
+        let key = (now + embargo, stem.next_sequence);
+        stem.next_sequence += 1;
+        stem.by_hash.insert(hash, key);
+        stem.entries.insert(key, transaction);
+        Ok(())
+    }
+
+    /// Relay phase of a transaction, or `None` once it has left the stem pool
+    pub fn phase(&self, transaction: &Transaction) -> Option<Phase> {
+        self.phase_at(transaction, Instant::now())
+    }
+
+    /// Relay phase of a transaction at a point in time
+    fn phase_at(&self, transaction: &Transaction, now: Instant) -> Option<Phase> {
+        let hash = if transaction.is_canonical() {
+            transaction.hash()
+        } else {
+            let mut sorted = transaction.clone();
+            sorted.sort_canonical();
+            sorted.hash()
+        }
+        .ok()?;
+        self.stem.lock().by_hash.get(&hash).map(|(fluff_at, _)| {
+            if *fluff_at <= now {
+                Phase::Fluff
+            } else {
+                Phase::Stem
+            }
+        })
+    }
+
+    /// Take the transactions whose embargo has expired, to be broadcast
+    ///
+    /// Transactions that spend distinct inputs, do not spend each other's
+    /// outputs and carry compatible anchors are aggregated into one. If a
+    /// group fails to aggregate, its transactions fluff individually, except
+    /// those that cannot be aggregated even alone, which are dropped.
+    pub fn ready_to_fluff(&self) -> Vec<Transaction> {
+        self.ready_to_fluff_at(Instant::now())
+    }
+
+    /// Take the transactions whose embargo has expired at a point in time
+    fn ready_to_fluff_at(&self, now: Instant) -> Vec<Transaction> {
+        let ready = {
+            let mut stem = self.stem.lock();
+            let waiting = stem.entries.split_off(&(now, u64::MAX));
+            let ready = std::mem::replace(&mut stem.entries, waiting);
+            for transaction in ready.values() {
+                if let Ok(hash) = transaction.hash() {
+                    stem.by_hash.remove(&hash);
+                }
+            }
+            ready
+        };
+
+        let mut groups: Vec<Group> = Vec::new();
+        for transaction in ready.values() {
+            match groups.iter_mut().find(|group| group.accepts(transaction)) {
+                Some(group) => group.push(transaction),
+                None => groups.push(Group::of(transaction)),
+            }
+        }
+
+        let mut fluffed = Vec::with_capacity(groups.len());
+        for group in &groups {
+            let members: Vec<Transaction> =
+                group.transactions.iter().map(|tx| (*tx).clone()).collect();
+            if let [single] = members.as_slice() {
+                fluffed.push(single.clone());
+                continue;
+            }
+            match Transaction::aggregate(&members) {
+                Ok(aggregate) => fluffed.push(aggregate),
+                Err(_) => fluffed.extend(
+                    members
+                        .into_iter()
+                        .filter(|tx| Transaction::aggregate(std::slice::from_ref(tx)).is_ok()),
+                ),
+            }
+        }
+        fluffed
+    }
+
+    /// Copy of a transaction to relay over the next stem hop
+    ///
+    /// Inputs, outputs and kernels are shuffled, so their order does not
+    /// reveal how an aggregate was built.
+    pub fn prepare_stem_relay(transaction: &Transaction) -> Transaction {
+        let mut relayed = transaction.clone();
+        relayed.shuffle_for_relay(&mut rand::thread_rng());
+        relayed
+    }
+
+    /// Number of transactions in the stem phase
+    pub fn stem_size(&self) -> usize {
+        self.stem.lock().entries.len()
+    }
+}
+
+/// Stemmed transactions fluffing together as one aggregate
+struct Group<'a> {
+    /// Transactions in the group
+    transactions: Vec<&'a Transaction>,
+
+    /// Commitments spent or created by the group
+    commitments: HashSet<&'a [u8]>,
+
+    /// Anchor shared by the group, if any
+    anchor: Option<&'a [u8]>,
+}
+
+impl<'a> Group<'a> {
+    /// Start a group with a single transaction
+    fn of(transaction: &'a Transaction) -> Self {
+        let mut group = Self {
+            transactions: Vec::new(),
+            commitments: HashSet::new(),
+            anchor: None,
+        };
+        group.push(transaction);
+        group
+    }
+
+    /// Check whether a transaction may join the group
+    ///
+    /// It must not share an input or output with the group, which also
+    /// rules out spending an output created by the group or the reverse.
+    fn accepts(&self, transaction: &Transaction) -> bool {
+        let anchors_agree = match (self.anchor, transaction.anchor()) {
+            (Some(a), Some(b)) => a == b,
+            _ => true,
+        };
+        anchors_agree
+            && transaction
+                .inputs()
+                .iter()
+                .chain(transaction.outputs())
+                .all(|commitment| !self.commitments.contains(commitment.commitment.as_slice()))
+    }
+
+    /// Add a transaction to the group
+    fn push(&mut self, transaction: &'a Transaction) {
+        self.anchor = self.anchor.or(transaction.anchor());
+        self.commitments.extend(
+            transaction
+                .inputs()
+                .iter()
+                .chain(transaction.outputs())
+                .map(|commitment| commitment.commitment.as_slice()),
+        );
+        self.transactions.push(transaction);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{balanced_tx, output};
+
+    fn spend(funding: u8, blinding: u8) -> Transaction {
+        let input = output(1000, funding);
+        balanced_tx(vec![input], vec![output(990, blinding)], 10)
+    }
+
+    #[test]
+    fn test_stem_to_fluff_after_embargo() -> Result<()> {
+        let embargo = Duration::from_secs(5);
+        let dandelion = DandelionState::with_embargo(embargo, embargo);
+        let now = Instant::now();
+        let tx = spend(1, 2);
+        dandelion.add_at(tx.clone(), now)?;
+
+        assert_eq!(dandelion.phase_at(&tx, now), Some(Phase::Stem));
+        assert!(dandelion.ready_to_fluff_at(now).is_empty());
+        assert_eq!(dandelion.stem_size(), 1);
+
+        let later = now + embargo;
+        assert_eq!(dandelion.phase_at(&tx, later), Some(Phase::Fluff));
+        assert_eq!(dandelion.ready_to_fluff_at(later), vec![tx.clone()]);
+        assert_eq!(dandelion.phase_at(&tx, later), None);
+        assert_eq!(dandelion.stem_size(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_stem_hop_is_shuffled_and_restored() -> Result<()> {
+        let embargo = Duration::from_secs(5);
+        let dandelion = DandelionState::with_embargo(embargo, embargo);
+        let now = Instant::now();
+        let aggregate = Transaction::aggregate(&[spend(1, 3), spend(2, 4), spend(5, 6)])?;
+
+        let relayed = DandelionState::prepare_stem_relay(&aggregate);
+        assert_eq!(relayed.fingerprint(), aggregate.fingerprint());
+        dandelion.add(relayed.clone(), &crate::MimblewimbleParameters::default())?;
+        dandelion.add_at(aggregate.clone(), now)?;
+
+        // Both orders name the one canonical transaction
+        assert_eq!(dandelion.stem_size(), 1);
+        assert_eq!(dandelion.phase_at(&relayed, now), Some(Phase::Stem));
+        let fluffed = dandelion.ready_to_fluff_at(Instant::now() + embargo);
+        assert_eq!(fluffed, vec![aggregate]);
+        assert!(fluffed[0].is_canonical());
+        Ok(())
+    }
+
+    #[test]
+    fn test_stemmed_transactions_are_aggregated() -> Result<()> {
+        let embargo = Duration::from_secs(5);
+        let dandelion = DandelionState::with_embargo(embargo, embargo);
+        let now = Instant::now();
+        let (a, b) = (spend(1, 3), spend(2, 4));
+        dandelion.add_at(a.clone(), now)?;
+        dandelion.add_at(b.clone(), now)?;
+
+        // Two spends of the same output cannot share an aggregate
+        let conflicting = spend(1, 5);
+        dandelion.add_at(conflicting.clone(), now)?;
+
+        let fluffed = dandelion.ready_to_fluff_at(now + embargo);
+        assert_eq!(fluffed.len(), 2);
+        assert_eq!(fluffed[0], Transaction::aggregate(&[a, b])?);
+        assert_eq!(fluffed[0].kernels().len(), 2);
+        fluffed[0].validate(&crate::MimblewimbleParameters::default())?;
+        assert_eq!(fluffed[1], conflicting);
+        Ok(())
+    }
+
+    #[test]
+    fn test_chained_transactions_are_not_aggregated() -> Result<()> {
+        let embargo = Duration::from_secs(5);
+        let dandelion = DandelionState::with_embargo(embargo, embargo);
+        let now = Instant::now();
+        let parent = spend(1, 2);
+        let child = balanced_tx(vec![output(990, 2)], vec![output(980, 3)], 10);
+        dandelion.add_at(parent.clone(), now)?;
+        dandelion.add_at(child.clone(), now)?;
+
+        assert_eq!(
+            dandelion.ready_to_fluff_at(now + embargo),
+            vec![parent, child]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_failed_aggregation_fluffs_the_rest() -> Result<()> {
+        let embargo = Duration::from_secs(5);
+        let dandelion = DandelionState::with_embargo(embargo, embargo);
+        let now = Instant::now();
+        let mut malformed = spend(1, 3);
+        *malformed.offset_mut() = vec![1; 3];
+        assert!(dandelion
+            .add(malformed.clone(), &crate::MimblewimbleParameters::default())
+            .is_err());
+
+        // Had it entered the stem pool, it would not hold back the others
+        let (a, b) = (spend(2, 4), spend(5, 6));
+        dandelion.add_at(a.clone(), now)?;
+        dandelion.add_at(malformed.clone(), now)?;
+        dandelion.add_at(b.clone(), now)?;
+        assert_eq!(dandelion.ready_to_fluff_at(now + embargo), vec![a, b]);
+        assert_eq!(dandelion.stem_size(), 0);
+        assert_eq!(dandelion.phase_at(&malformed, now + embargo), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_stem_pool_is_bounded() -> Result<()> {
+        let dandelion = DandelionState::new().with_max_stem_size(2);
+        let now = Instant::now();
+        dandelion.add_at(spend(1, 2), now)?;
+        dandelion.add_at(spend(3, 4), now)?;
+
+        // A transaction already held does not count twice
+        dandelion.add_at(spend(1, 2), now)?;
+        assert!(matches!(
+            dandelion.add_at(spend(5, 6), now),
+            Err(MimblewimbleError::PoolFull)
+        ));
+        assert_eq!(dandelion.stem_size(), 2);
+        Ok(())
+    }
+}
@@ -43,6 +43,45 @@ pub enum MimblewimbleError {
 
     #[error("Range proof error: {0}")]
     RangeProofError(String),
+
+    #[error("Range proof verification failed for output {0}")]
+    InvalidRangeProof(usize),
+
+    #[error("Kernel signature verification failed for kernel {0}")]
+    InvalidKernelSignature(usize),
+
+    #[error("Kernel log hash chain is broken at entry {0}")]
+    CorruptKernelLog(usize),
+
+    #[error("Peer exceeded its transaction rate limit")]
+    RateLimited,
+
+    #[error("I/O error: {0}")]
+    IoError(String),
+
+    #[error("Clock error: {0}")]
+    ClockError(String),
+
+    #[error("Unsupported block version {0}")]
+    UnsupportedVersion(u32),
+
+    #[error("Block not found")]
+    BlockNotFound,
+
+    #[error("Block does not extend the current chain tip")]
+    StaleBlock,
+
+    #[error("Proof of work does not meet the required difficulty")]
+    InvalidProofOfWork,
+
+    #[error("Checkpoint bundle is inconsistent")]
+    InvalidCheckpoint,
+
+    #[error("Kernel fee exceeds the configured maximum")]
+    FeeTooHigh,
+
+    #[error("Transaction pool is full")]
+    PoolFull,
 }
 
 /// Result type for Mimblewimble operations
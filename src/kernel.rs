@@ -1,10 +1,17 @@
 //! Transaction kernels for Mimblewimble
 
-use crate::errors::Result;
-use crate::parameters::MimblewimbleParameters;
+use crate::crypto;
+use crate::errors::{MimblewimbleError, Result};
+use crate::parameters::{MimblewimbleParameters, Network};
 use hex;
 use serde::{Deserialize, Serialize};
-use sha2::{Digest, Sha512};
+use sha2::{Digest, Sha512, Sha512_256};
+
+/// Version of the kernel signing message format
+pub const KERNEL_SIGNATURE_VERSION: u32 = 2;
+
+/// Domain tag of the kernel signing message
+const KERNEL_SIGNATURE_TAG: &[u8] = b"silver-mimblewimble/kernel-signature";
 
 /// Transaction kernel
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -23,6 +30,27 @@ pub struct Kernel {
 
     /// Signature
     pub signature: Vec<u8>,
+
+    /// Relative lock on a spent output, carried by combined locked kernels
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub relative_lock: Option<RelativeLock>,
+
+    /// Hash of a block the kernel is bound to, if any
+    ///
+    /// An anchored kernel is only valid on chains containing that block, so
+    /// its transaction cannot be replayed on the other side of a split.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub anchor: Option<Vec<u8>>,
+}
+
+/// Lock on the age of an output the transaction spends
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RelativeLock {
+    /// Commitment of the spent output
+    pub commitment: Vec<u8>,
+
+    /// Blocks that must follow the output's creation before it is spent
+    pub blocks: u64,
 }
 
 /// Kernel features
@@ -36,6 +64,31 @@ pub enum KernelFeatures {
 
     /// Height locked kernel
     HeightLocked,
+
+    /// Kernel locked both to a height and to the age of a spent output
+    CombinedLocked,
+}
+
+impl KernelFeatures {
+    /// Byte identifying the features in hashed messages
+    fn id(self) -> u8 {
+        match self {
+            KernelFeatures::Plain => 0,
+            KernelFeatures::Coinbase => 1,
+            KernelFeatures::HeightLocked => 2,
+            KernelFeatures::CombinedLocked => 3,
+        }
+    }
+
+    /// Check whether kernels with these features pay a fee
+    pub fn carries_fee(self) -> bool {
+        match self {
+            KernelFeatures::Plain
+            | KernelFeatures::HeightLocked
+            | KernelFeatures::CombinedLocked => true,
+            KernelFeatures::Coinbase => false,
+        }
+    }
 }
 
 impl Kernel {
@@ -53,41 +106,303 @@ impl Kernel {
             lock_height,
             excess,
             signature,
+            relative_lock: None,
+            anchor: None,
         }
     }
 
+    /// Add a relative lock on the spent output `commitment`
+    pub fn with_relative_lock(mut self, commitment: Vec<u8>, blocks: u64) -> Self {
+        self.relative_lock = Some(RelativeLock { commitment, blocks });
+        self
+    }
+
+    /// Bind the kernel to the block with hash `block_hash`
+    pub fn with_anchor(mut self, block_hash: Vec<u8>) -> Self {
+        self.anchor = Some(block_hash);
+        self
+    }
+
+    /// Sign the kernel with the excess secret key
+    ///
+    /// Sets the excess to the public key of `excess_secret` and signs the
+    /// kernel signing message for the configured network with a
+    /// deterministically derived nonce.
+    pub fn sign(
+        &mut self,
+        excess_secret: &[u8],
+        parameters: &MimblewimbleParameters,
+    ) -> Result<()> {
+        self.excess = crypto::public_key(excess_secret)?;
+        let message = kernel_signing_message(self, parameters.network, KERNEL_SIGNATURE_VERSION);
+        self.signature = crypto::sign(excess_secret, &message)?;
+        Ok(())
+    }
+
+    /// Verify the kernel signature against its excess
+    pub fn verify_signature(&self, parameters: &MimblewimbleParameters) -> Result<bool> {
+        let message = kernel_signing_message(self, parameters.network, KERNEL_SIGNATURE_VERSION);
+        crypto::verify(&self.excess, &message, &self.signature)
+    }
+
     /// Verify the kernel
-    pub fn verify(&self, _parameters: &MimblewimbleParameters) -> Result<bool> {
-        // Verify excess is valid
-        if self.excess.is_empty() {
+    ///
+    /// Cheap structural checks run first and short-circuit: a well-formed
+    /// signature, an excess that decodes to a curve point and features
+    /// consistent with the fee and lock height. The signature is verified
+    /// last, with the excess as the public key, so a valid signature by any
+    /// other key is rejected.
+    pub fn verify(&self, parameters: &MimblewimbleParameters) -> Result<bool> {
+        if self.excess.is_empty() || self.signature.len() != crypto::SIGNATURE_LENGTH {
+            return Ok(false);
+        }
+
+        if crypto::point_from_bytes(&self.excess).is_err() {
             return Ok(false);
         }
 
-        // Verify signature is valid
-        if self.signature.is_empty() {
+        if !self.has_consistent_features() {
             return Ok(false);
         }
 
-        // Verify kernel hash
-        let mut hasher = Sha512::new();
-        match serde_json::to_vec(&self.features) {
-            Ok(features_bytes) => hasher.update(features_bytes),
-            Err(_) => hasher.update([]), // Use empty bytes if serialization fails
+        #[cfg(test)]
+        tests::SIGNATURE_CHECKS.with(|checks| checks.set(checks.get() + 1));
+
+        Ok(self.verify_signature(parameters).unwrap_or(false))
+    }
+
+    /// Check that the fee and lock height are allowed for the features
+    ///
+    /// Only features that carry a fee may have a non-zero one. Height locked
+    /// kernels carry a non-zero lock height and all others none. Combined
+    /// locked kernels carry both a lock height and a relative lock.
+    fn has_consistent_features(&self) -> bool {
+        let fee_allowed = self.features.carries_fee() || self.fee == 0;
+        let lock_allowed = match self.features {
+            KernelFeatures::HeightLocked => self.lock_height > 0 && self.relative_lock.is_none(),
+            KernelFeatures::CombinedLocked => self.lock_height > 0 && self.relative_lock.is_some(),
+            KernelFeatures::Plain | KernelFeatures::Coinbase => {
+                self.lock_height == 0 && self.relative_lock.is_none()
+            }
+        };
+        fee_allowed && lock_allowed
+    }
+}
+
+/// Message committed to by a kernel signature
+///
+/// Covers the network, the signing scheme version and every kernel field
+/// except the excess and signature, with the fee only for features that
+/// carry one and the anchor only if set. The excess is bound separately as
+/// the public key in the signature challenge.
+pub fn kernel_signing_message(kernel: &Kernel, network: Network, version: u32) -> [u8; 32] {
+    let mut hasher = Sha512_256::new();
+    hasher.update(KERNEL_SIGNATURE_TAG);
+    hasher.update([network.id()]);
+    hasher.update(version.to_be_bytes());
+    update_with_terms(&mut hasher, kernel);
+    hasher.finalize().into()
+}
+
+/// Hash of every kernel field except the excess and signature
+///
+/// Unlike the excess, these fields survive re-randomizing the offset of
+/// the kernel's transaction.
+pub(crate) fn kernel_terms_hash(kernel: &Kernel) -> Vec<u8> {
+    let mut hasher = Sha512::new();
+    update_with_terms(&mut hasher, kernel);
+    hasher.finalize().to_vec()
+}
+
+/// Feed the features, fee, locks and anchor of a kernel to a hasher
+fn update_with_terms(hasher: &mut impl Digest, kernel: &Kernel) {
+    hasher.update([kernel.features.id()]);
+    if kernel.features.carries_fee() {
+        hasher.update(kernel.fee.to_be_bytes());
+    }
+    hasher.update(kernel.lock_height.to_be_bytes());
+    if let Some(lock) = &kernel.relative_lock {
+        hasher.update((lock.commitment.len() as u64).to_be_bytes());
+        hasher.update(&lock.commitment);
+        hasher.update(lock.blocks.to_be_bytes());
+    }
+    if let Some(anchor) = &kernel.anchor {
+        hasher.update((anchor.len() as u64).to_be_bytes());
+        hasher.update(anchor);
+    }
+}
+
+/// Entry of a kernel log
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KernelLogEntry {
+    /// Logged kernel
+    pub kernel: Kernel,
+
+    /// Hash of the preceding entry, empty for the first entry
+    pub previous_hash: Vec<u8>,
+
+    /// Hash of the kernel signature, kept when the signature is pruned
+    pub signature_hash: Vec<u8>,
+
+    /// Hash of this entry over the previous hash, the kernel without its
+    /// signature and the signature hash
+    pub hash: Vec<u8>,
+}
+
+/// Append-only log of kernels chained by entry hashes
+///
+/// Each entry commits to its predecessor, so modifying a historical kernel
+/// breaks the chain from that entry onwards. Entries commit to a hash of the
+/// signature rather than the signature itself, so they remain fully
+/// verifiable once the signature is pruned.
+#[derive(Debug, Clone, Default)]
+pub struct KernelLog {
+    entries: Vec<KernelLogEntry>,
+}
+
+impl KernelLog {
+    /// Create an empty log
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Build a log from kernels in order
+    pub fn from_kernels(kernels: impl IntoIterator<Item = Kernel>) -> Self {
+        let mut log = Self::new();
+        for kernel in kernels {
+            log.append(kernel);
+        }
+        log
+    }
+
+    /// Append a kernel
+    pub fn append(&mut self, kernel: Kernel) {
+        let previous_hash = self.head_hash();
+        let signature_hash = signature_hash(&kernel.signature);
+        let hash = log_entry_hash(&previous_hash, &kernel, &signature_hash);
+        self.entries.push(KernelLogEntry {
+            kernel,
+            previous_hash,
+            signature_hash,
+            hash,
+        });
+    }
+
+    /// Drop the entries after the first `len`, as when rewinding the chain
+    pub fn truncate(&mut self, len: usize) {
+        self.entries.truncate(len);
+    }
+
+    /// Hash of the last entry, empty for an empty log
+    pub fn head_hash(&self) -> Vec<u8> {
+        self.entries
+            .last()
+            .map(|entry| entry.hash.clone())
+            .unwrap_or_default()
+    }
+
+    /// Number of logged kernels
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check whether the log is empty
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Logged kernels in order
+    pub fn kernels(&self) -> impl Iterator<Item = &Kernel> {
+        self.entries.iter().map(|entry| &entry.kernel)
+    }
+
+    /// Log entries in order
+    pub fn entries(&self) -> &[KernelLogEntry] {
+        &self.entries
+    }
+
+    /// Discard the signatures of the first `count` kernels
+    ///
+    /// Returns the number of signatures discarded.
+    pub fn prune_signatures(&mut self, count: usize) -> usize {
+        let count = count.min(self.entries.len());
+        self.entries[..count]
+            .iter_mut()
+            .filter(|entry| !entry.kernel.signature.is_empty())
+            .map(|entry| entry.kernel.signature = Vec::new())
+            .count()
+    }
+
+    /// Verify the hash chain
+    ///
+    /// Fails with `CorruptKernelLog` carrying the index of the first entry
+    /// that does not link to its predecessor or whose hash does not match
+    /// its kernel. A pruned signature is checked through its stored hash.
+    pub fn verify(&self) -> Result<()> {
+        let mut previous_hash = Vec::new();
+        for (index, entry) in self.entries.iter().enumerate() {
+            let pruned = entry.kernel.signature.is_empty();
+            if entry.previous_hash != previous_hash
+                || (!pruned && entry.signature_hash != signature_hash(&entry.kernel.signature))
+                || entry.hash
+                    != log_entry_hash(&previous_hash, &entry.kernel, &entry.signature_hash)
+            {
+                return Err(MimblewimbleError::CorruptKernelLog(index));
+            }
+            previous_hash = entry.hash.clone();
         }
-        hasher.update(self.fee.to_le_bytes());
-        hasher.update(self.lock_height.to_le_bytes());
-        hasher.update(&self.excess);
+        Ok(())
+    }
+}
 
-        let kernel_hash = hex::encode(hasher.finalize()).into_bytes();
+/// Hash of a kernel signature as committed to by the log
+fn signature_hash(signature: &[u8]) -> Vec<u8> {
+    hex::encode(Sha512::digest(signature)).into_bytes()
+}
 
-        // Verify signature matches kernel hash
-        Ok(!kernel_hash.is_empty())
+/// Hash of a kernel log entry
+fn log_entry_hash(previous_hash: &[u8], kernel: &Kernel, signature_hash: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha512::new();
+    hasher.update(previous_hash);
+    hasher.update([kernel.features.id()]);
+    hasher.update(kernel.fee.to_be_bytes());
+    hasher.update(kernel.lock_height.to_be_bytes());
+    if let Some(lock) = &kernel.relative_lock {
+        hasher.update((lock.commitment.len() as u64).to_be_bytes());
+        hasher.update(&lock.commitment);
+        hasher.update(lock.blocks.to_be_bytes());
+    }
+    if let Some(anchor) = &kernel.anchor {
+        hasher.update((anchor.len() as u64).to_be_bytes());
+        hasher.update(anchor);
     }
+    hasher.update(&kernel.excess);
+    hasher.update(signature_hash);
+    hex::encode(hasher.finalize()).into_bytes()
+}
+
+/// Height at which a relative lock matures, or `None` if it never does
+///
+/// Overflow means the lock can never be satisfied, rather than wrapping to
+/// a height that has already been reached.
+pub fn relative_lock_height(reference_height: u64, relative_height: u64) -> Option<u64> {
+    reference_height.checked_add(relative_height)
+}
+
+/// Check whether a relative lock has matured at `height`
+pub fn relative_lock_matured(reference_height: u64, relative_height: u64, height: u64) -> bool {
+    relative_lock_height(reference_height, relative_height).is_some_and(|mature| height >= mature)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::cell::Cell;
+
+    thread_local! {
+        /// Signature verifications performed by `Kernel::verify` on this thread
+        pub(super) static SIGNATURE_CHECKS: Cell<usize> = const { Cell::new(0) };
+    }
 
     #[test]
     fn test_kernel_creation() {
@@ -100,7 +415,8 @@ mod tests {
     #[test]
     fn test_kernel_verification() {
         let params = MimblewimbleParameters::default();
-        let kernel = Kernel::new(KernelFeatures::Plain, 100, 0, vec![1; 32], vec![2; 64]);
+        let mut kernel = Kernel::new(KernelFeatures::Plain, 100, 0, vec![], vec![]);
+        kernel.sign(&[9; 32], &params).unwrap();
 
         match kernel.verify(&params) {
             Ok(valid) => assert!(valid),
@@ -109,4 +425,240 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_structural_checks_precede_signature_check() -> Result<()> {
+        let params = MimblewimbleParameters::default();
+        let mut signed = Kernel::new(KernelFeatures::Plain, 100, 0, vec![], vec![]);
+        signed.sign(&[9; 32], &params)?;
+
+        let malformed = vec![
+            Kernel {
+                excess: vec![],
+                ..signed.clone()
+            },
+            Kernel {
+                excess: vec![0xff; crypto::POINT_LENGTH],
+                ..signed.clone()
+            },
+            Kernel {
+                signature: vec![2; 64],
+                ..signed.clone()
+            },
+            Kernel {
+                features: KernelFeatures::Coinbase,
+                ..signed.clone()
+            },
+            Kernel {
+                lock_height: 5,
+                ..signed.clone()
+            },
+            signed
+                .clone()
+                .with_relative_lock(vec![1; crypto::POINT_LENGTH], 5),
+        ];
+
+        SIGNATURE_CHECKS.with(|checks| checks.set(0));
+        for kernel in &malformed {
+            assert!(!kernel.verify(&params)?);
+        }
+        assert_eq!(SIGNATURE_CHECKS.with(Cell::get), 0);
+
+        assert!(signed.verify(&params)?);
+        assert_eq!(SIGNATURE_CHECKS.with(Cell::get), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_fee_rules_by_features() -> Result<()> {
+        let params = MimblewimbleParameters::default();
+        let signed = |features: KernelFeatures, fee: u64| -> Result<Kernel> {
+            let mut kernel = Kernel::new(features, fee, 0, vec![], vec![]);
+            kernel.sign(&[9; 32], &params)?;
+            Ok(kernel)
+        };
+
+        assert!(signed(KernelFeatures::Plain, 100)?.verify(&params)?);
+        assert!(signed(KernelFeatures::Coinbase, 0)?.verify(&params)?);
+        assert!(!signed(KernelFeatures::Coinbase, 100)?.verify(&params)?);
+
+        // Only fee-carrying features sign the fee
+        let message = |features, fee| {
+            kernel_signing_message(
+                &Kernel::new(features, fee, 0, vec![], vec![]),
+                params.network,
+                KERNEL_SIGNATURE_VERSION,
+            )
+        };
+        assert_ne!(
+            message(KernelFeatures::Plain, 0),
+            message(KernelFeatures::Plain, 100)
+        );
+        assert_eq!(
+            message(KernelFeatures::Coinbase, 0),
+            message(KernelFeatures::Coinbase, 100)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_kernel_signing_is_deterministic() -> Result<()> {
+        let params = MimblewimbleParameters::default();
+        let secret = vec![9; 32];
+
+        let mut first = Kernel::new(KernelFeatures::Plain, 100, 0, vec![], vec![]);
+        let mut second = first.clone();
+        first.sign(&secret, &params)?;
+        second.sign(&secret, &params)?;
+        assert_eq!(first.signature, second.signature);
+        assert!(crypto::verify(
+            &first.excess,
+            &kernel_signing_message(&first, params.network, KERNEL_SIGNATURE_VERSION),
+            &first.signature
+        )?);
+
+        // A different message must use a different nonce
+        let mut other = Kernel::new(KernelFeatures::Plain, 200, 0, vec![], vec![]);
+        other.sign(&secret, &params)?;
+        assert_ne!(
+            first.signature[..crypto::POINT_LENGTH],
+            other.signature[..crypto::POINT_LENGTH]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_relative_lock_overflow_never_matures() {
+        assert_eq!(relative_lock_height(100, 10), Some(110));
+        assert!(relative_lock_matured(100, 10, 110));
+        assert!(!relative_lock_matured(100, 10, 109));
+
+        // Wrapping would give 99, which every later height satisfies
+        let relative_height = u64::MAX - 10;
+        assert_eq!(relative_lock_height(110, relative_height), None);
+        assert!(!relative_lock_matured(110, relative_height, 200));
+        assert!(!relative_lock_matured(110, relative_height, u64::MAX));
+    }
+
+    #[test]
+    fn test_kernel_log_detects_tampering() {
+        let kernels: Vec<Kernel> = (1..=5u64)
+            .map(|fee| Kernel::new(KernelFeatures::Plain, fee, 0, vec![1; 32], vec![2; 64]))
+            .collect();
+        let log = KernelLog::from_kernels(kernels.clone());
+        assert_eq!(log.len(), 5);
+        assert!(log.kernels().eq(kernels.iter()));
+        assert!(log.verify().is_ok());
+
+        // Changing a middle kernel invalidates its entry
+        let mut tampered = log.clone();
+        tampered.entries[2].kernel.fee = 100;
+        assert!(matches!(
+            tampered.verify(),
+            Err(MimblewimbleError::CorruptKernelLog(2))
+        ));
+
+        // Rehashing the entry breaks the link from its successor
+        tampered.entries[2].hash = log_entry_hash(
+            &tampered.entries[2].previous_hash,
+            &tampered.entries[2].kernel,
+            &tampered.entries[2].signature_hash,
+        );
+        assert!(matches!(
+            tampered.verify(),
+            Err(MimblewimbleError::CorruptKernelLog(3))
+        ));
+    }
+
+    #[test]
+    fn test_pruned_kernel_log_detects_tampering() {
+        let kernels: Vec<Kernel> = (1..=3u64)
+            .map(|fee| Kernel::new(KernelFeatures::Plain, fee, 0, vec![1; 32], vec![2; 64]))
+            .collect();
+        let mut log = KernelLog::from_kernels(kernels);
+        assert_eq!(log.prune_signatures(2), 2);
+        assert!(log.verify().is_ok());
+
+        // A pruned kernel's fields are still bound by its entry hash
+        let mut tampered = log.clone();
+        tampered.entries[1].kernel.fee = 100;
+        assert!(matches!(
+            tampered.verify(),
+            Err(MimblewimbleError::CorruptKernelLog(1))
+        ));
+
+        let mut tampered = log.clone();
+        tampered.entries[0].kernel.excess = vec![3; 32];
+        assert!(matches!(
+            tampered.verify(),
+            Err(MimblewimbleError::CorruptKernelLog(0))
+        ));
+
+        // Restoring a different signature does not match the stored hash
+        let mut tampered = log;
+        tampered.entries[0].kernel.signature = vec![4; 64];
+        assert!(matches!(
+            tampered.verify(),
+            Err(MimblewimbleError::CorruptKernelLog(0))
+        ));
+    }
+
+    #[test]
+    fn test_signature_must_be_by_the_excess_key() -> Result<()> {
+        let params = MimblewimbleParameters::default();
+        let mut kernel = Kernel::new(KernelFeatures::Plain, 100, 0, vec![], vec![]);
+        kernel.sign(&[9; 32], &params)?;
+        let mut other = kernel.clone();
+        other.sign(&[8; 32], &params)?;
+        assert!(kernel.verify(&params)? && other.verify(&params)?);
+
+        // The other key's signature is valid for the message, but not under
+        // this kernel's excess
+        let forged = Kernel {
+            signature: other.signature.clone(),
+            ..kernel.clone()
+        };
+        let message = kernel_signing_message(&forged, params.network, KERNEL_SIGNATURE_VERSION);
+        assert!(crypto::verify(&other.excess, &message, &forged.signature)?);
+        assert!(!forged.verify(&params)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_signature_covers_every_kernel_field() -> Result<()> {
+        let params = MimblewimbleParameters::default();
+        let mut kernel = Kernel::new(KernelFeatures::Plain, 100, 0, vec![], vec![]);
+        kernel.sign(&[9; 32], &params)?;
+        assert!(kernel.verify_signature(&params)?);
+
+        let tampered: Vec<Kernel> = vec![
+            Kernel {
+                features: KernelFeatures::HeightLocked,
+                ..kernel.clone()
+            },
+            Kernel {
+                fee: 101,
+                ..kernel.clone()
+            },
+            Kernel {
+                lock_height: 1,
+                ..kernel.clone()
+            },
+            kernel
+                .clone()
+                .with_relative_lock(vec![1; crypto::POINT_LENGTH], 5),
+            kernel.clone().with_anchor(vec![1; 128]),
+            Kernel {
+                excess: crypto::public_key(&[8; 32])?,
+                ..kernel.clone()
+            },
+        ];
+        for modified in &tampered {
+            assert!(!modified.verify_signature(&params)?);
+        }
+
+        // Signatures do not carry over to another network
+        assert!(!kernel.verify_signature(&MimblewimbleParameters::regtest())?);
+        Ok(())
+    }
 }
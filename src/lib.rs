@@ -8,43 +8,425 @@
 //! - Privacy without trusted setup
 
 pub mod block;
+pub mod clock;
 pub mod commitment;
+pub mod crypto;
+pub mod dandelion;
 pub mod errors;
 pub mod kernel;
+pub mod mempool;
+pub mod mmr;
+pub mod net;
 pub mod parameters;
+pub mod pow;
 pub mod proof;
 pub mod range_proof;
+pub mod serialization;
 pub mod transaction;
+pub mod wallet;
 
-pub use block::{Block, BlockHeader};
+pub use block::{
+    Block, BlockHeader, BlockTemplate, CutThroughStats, RemovedOutput, UtxoDelta, VerificationCost,
+};
+pub use clock::{ManualTimeSource, SystemTimeSource, TimeSource};
 pub use commitment::Commitment;
+pub use dandelion::DandelionState;
 pub use errors::{MimblewimbleError, Result};
 pub use kernel::Kernel;
+pub use mempool::Mempool;
+pub use mmr::MembershipProof;
+pub use net::NetMessage;
 pub use parameters::MimblewimbleParameters;
+pub use pow::PowAlgorithm;
 pub use proof::Proof;
 pub use range_proof::RangeProof;
-pub use transaction::Transaction;
+pub use transaction::{Transaction, VerificationCache};
+pub use wallet::{Balance, TransactionBuilder, Wallet};
 
+use crypto::PointCache;
+use kernel::KernelLog;
+use mmr::MerkleMountainRange;
+use p521::{ProjectivePoint, Scalar};
 use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::num::NonZeroUsize;
 use std::sync::Arc;
 
 /// Mimblewimble protocol version
 pub const MIMBLEWIMBLE_VERSION: u32 = 1;
 
+/// Block space reserved for the coinbase when selecting transactions (bytes)
+const COINBASE_RESERVED_SIZE: usize = 4_096;
+
+/// Maximum number of headers served in one export
+pub const MAX_EXPORTED_HEADERS: u64 = 2_000;
+
+/// Domain tag for hashing kernel excesses into the kernel set hash
+const KERNEL_SET_TAG: &[u8] = b"silver-mimblewimble/kernel-set";
+
+/// Effect of applying a transaction to the state
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxEffect {
+    /// Commitments spent by the transaction
+    pub spent: Vec<Vec<u8>>,
+
+    /// Outputs created by the transaction
+    pub created: Vec<Commitment>,
+
+    /// Transaction fee
+    pub fee: u64,
+}
+
+impl TxEffect {
+    /// Compute the effect of a transaction without verifying it
+    fn of(transaction: &Transaction) -> Self {
+        Self {
+            spent: transaction
+                .inputs()
+                .iter()
+                .map(|input| input.commitment.clone())
+                .collect(),
+            created: transaction.outputs().to_vec(),
+            fee: transaction.fee(),
+        }
+    }
+}
+
+/// Outcome of a block submission
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SubmitResult {
+    /// Block extended the chain
+    Accepted {
+        /// Height of the accepted block
+        height: u64,
+
+        /// Hash of the accepted block
+        hash: Vec<u8>,
+    },
+
+    /// Block is already part of the chain
+    Duplicate,
+}
+
+/// Consistent point-in-time view of the chain state
+#[derive(Debug, Clone)]
+pub struct StateSnapshot {
+    /// Next block height
+    pub height: u64,
+
+    /// UTXO set
+    pub utxo_set: Vec<Commitment>,
+
+    /// Creation height of each UTXO, parallel to `utxo_set`, if known
+    pub output_heights: Vec<Option<u64>>,
+
+    /// Kernel set
+    pub kernel_set: Vec<Kernel>,
+}
+
+/// Operational metrics of a node's state
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StateMetrics {
+    /// Next block height
+    pub block_height: u64,
+
+    /// Number of unspent outputs
+    pub utxo_count: usize,
+
+    /// Number of kernels
+    pub kernel_count: usize,
+
+    /// Unspent outputs per kernel, see `MimblewimbleState::utxo_kernel_ratio`
+    pub utxo_kernel_ratio: f64,
+
+    /// Number of pending transactions
+    pub mempool_size: usize,
+
+    /// Approximate memory held by the chain state (bytes)
+    pub memory_bytes: usize,
+}
+
+/// Self-contained chain state for bootstrapping a node in one step
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckpointBundle {
+    /// Accepted headers, ending with the tip header
+    pub headers: Vec<BlockHeader>,
+
+    /// UTXO set at the tip
+    pub utxo_set: Vec<Commitment>,
+
+    /// Creation height of each output in `utxo_set`, in the same order
+    pub output_heights: Vec<u64>,
+
+    /// Kernel set at the tip
+    pub kernel_set: Vec<Kernel>,
+
+    /// Order-independent hash of the kernel excesses
+    pub kernel_set_hash: Vec<u8>,
+
+    /// Merkle mountain range root of the UTXO set
+    pub output_root: Vec<u8>,
+
+    /// Coins held by the UTXO set
+    pub supply: u64,
+
+    /// Sum of the kernel offsets of all accepted transactions
+    #[serde(default)]
+    pub total_offset: Vec<u8>,
+}
+
+/// Chain state, guarded as a whole by a single lock
+#[derive(Debug)]
+struct Chain {
+    /// Current block height
+    block_height: u64,
+
+    /// UTXO set (pruned)
+    utxo_set: Vec<Commitment>,
+
+    /// Kernel set, as a hash-chained append-only log
+    kernel_set: KernelLog,
+
+    /// Order-independent running hash of accepted kernel excesses
+    kernel_set_hash: ProjectivePoint,
+
+    /// Merkle mountain range over kernel excesses in acceptance order
+    kernel_mmr: MerkleMountainRange,
+
+    /// Sum of all accepted kernel excesses
+    total_excess: ProjectivePoint,
+
+    /// Sum of all accepted kernel offsets
+    total_offset: Scalar,
+
+    /// Coins held by the UTXO set: rewards claimed minus fees burned
+    supply: u64,
+
+    /// Kernel set size after each block, indexed by height, if known
+    kernel_counts: Vec<Option<usize>>,
+
+    /// Headers of accepted blocks, indexed by height
+    headers: Vec<BlockHeader>,
+
+    /// Height of each accepted block, by block hash
+    block_heights: HashMap<Vec<u8>, u64>,
+
+    /// Transactions of accepted blocks, indexed by height, if retained
+    bodies: Vec<Option<Vec<Transaction>>>,
+
+    /// Outputs created and spent by each locally accepted, unpruned block
+    output_index: BTreeMap<u64, UtxoDelta>,
+
+    /// Output set root after each block, indexed by height, if known
+    output_roots: Vec<Option<Vec<u8>>>,
+
+    /// Creation height of unspent outputs
+    output_heights: HashMap<Vec<u8>, u64>,
+}
+
+impl Chain {
+    /// Create an empty chain
+    fn new() -> Self {
+        Self {
+            block_height: 0,
+            utxo_set: Vec::new(),
+            kernel_set: KernelLog::new(),
+            kernel_set_hash: ProjectivePoint::IDENTITY,
+            kernel_mmr: MerkleMountainRange::new(),
+            total_excess: ProjectivePoint::IDENTITY,
+            total_offset: Scalar::ZERO,
+            supply: 0,
+            kernel_counts: Vec::new(),
+            headers: Vec::new(),
+            block_heights: HashMap::new(),
+            bodies: Vec::new(),
+            output_index: BTreeMap::new(),
+            output_roots: Vec::new(),
+            output_heights: HashMap::new(),
+        }
+    }
+
+    /// Apply a verified transaction effect
+    fn apply(&mut self, transaction: &Transaction, effect: TxEffect) {
+        // Add inputs to UTXO set (remove spent outputs)
+        for spent in &effect.spent {
+            self.utxo_set.retain(|utxo| &utxo.commitment != spent);
+            self.output_heights.remove(spent);
+        }
+
+        // Add outputs to UTXO set, created at the height being built
+        for created in &effect.created {
+            self.output_heights
+                .insert(created.commitment.clone(), self.block_height);
+        }
+        self.utxo_set.extend(effect.created);
+
+        // Verified transactions always carry a decodable offset
+        if let Ok(offset) = transaction.offset_scalar() {
+            self.total_offset += offset;
+        }
+
+        // Fees leave the UTXO set until a coinbase claims them
+        if !transaction.is_coinbase() {
+            self.supply = self.supply.saturating_sub(transaction.fee());
+        }
+
+        // Add kernels
+        for kernel in transaction.kernels() {
+            self.append_kernel(kernel);
+        }
+    }
+
+    /// Append a verified kernel to the kernel set and its running sums
+    fn append_kernel(&mut self, kernel: &Kernel) {
+        self.kernel_set_hash += kernel_set_element(&kernel.excess);
+        self.kernel_mmr.push(kernel.excess.clone());
+        // Verified kernels always carry a decodable excess
+        if let Ok(excess) = crypto::point_from_bytes(&kernel.excess) {
+            self.total_excess += excess;
+        }
+        self.kernel_set.append(kernel.clone());
+    }
+
+    /// Drop the kernels after the first `len`, undoing `append_kernel`
+    fn truncate_kernels(&mut self, len: usize) {
+        for kernel in self.kernel_set.kernels().skip(len) {
+            self.kernel_set_hash -= kernel_set_element(&kernel.excess);
+            if let Ok(excess) = crypto::point_from_bytes(&kernel.excess) {
+                self.total_excess -= excess;
+            }
+        }
+        self.kernel_set.truncate(len);
+        self.kernel_mmr.truncate(len);
+    }
+
+    /// Earliest timestamp the next block may carry
+    fn min_timestamp(&self) -> u64 {
+        block::median_time_past(&self.headers).map_or(0, |median| median + 1)
+    }
+
+    /// Check whether a block is among the last `window` accepted blocks
+    fn is_recent_block(&self, hash: &[u8], window: u64) -> bool {
+        self.block_heights
+            .get(hash)
+            .is_some_and(|height| self.block_height - height <= window)
+    }
+
+    /// Hash of the chain tip, or zeros before the first block
+    fn tip_hash(&self) -> Result<Vec<u8>> {
+        match self.headers.last() {
+            Some(header) => header.hash(),
+            None => Ok(block::genesis_previous_hash()),
+        }
+    }
+
+    /// Approximate heap and inline memory held by the chain (bytes)
+    fn approximate_memory(&self) -> usize {
+        let output = |output: &Commitment| {
+            std::mem::size_of::<Commitment>()
+                + output.commitment.len()
+                + output.blinding.len()
+                + output.range_proof.proof_data.len()
+        };
+        let kernel = |kernel: &Kernel| {
+            std::mem::size_of::<Kernel>() + kernel.excess.len() + kernel.signature.len()
+        };
+        let header = |header: &BlockHeader| {
+            std::mem::size_of::<BlockHeader>()
+                + header.previous_hash.len()
+                + header.merkle_root.len()
+                + header.output_root.len()
+                + header.kernel_root.len()
+        };
+        let transaction = |tx: &Transaction| {
+            std::mem::size_of::<Transaction>()
+                + tx.inputs().iter().map(output).sum::<usize>()
+                + tx.outputs().iter().map(output).sum::<usize>()
+                + tx.kernels().iter().map(kernel).sum::<usize>()
+        };
+
+        self.utxo_set.iter().map(output).sum::<usize>()
+            + self.kernel_set.kernels().map(kernel).sum::<usize>()
+            + self.headers.iter().map(header).sum::<usize>()
+            + self.block_heights.keys().map(Vec::len).sum::<usize>()
+            + self
+                .bodies
+                .iter()
+                .flatten()
+                .flatten()
+                .map(transaction)
+                .sum::<usize>()
+            + self
+                .output_index
+                .values()
+                .map(|delta| {
+                    delta.added.iter().map(output).sum::<usize>()
+                        + delta.removed.iter().map(Vec::len).sum::<usize>()
+                        + delta.kernels.iter().map(kernel).sum::<usize>()
+                })
+                .sum::<usize>()
+    }
+
+    /// Kernel root after appending the kernels of transactions
+    fn projected_kernel_root(&self, transactions: &[Transaction]) -> Vec<u8> {
+        let mut mmr = self.kernel_mmr.clone();
+        for kernel in transactions.iter().flat_map(|tx| tx.kernels()) {
+            mmr.push(kernel.excess.clone());
+        }
+        mmr.root()
+    }
+
+    /// Output root of the current UTXO set
+    fn output_root(&self) -> Vec<u8> {
+        self.projected_output_root(&[])
+    }
+
+    /// Output root after applying transactions to the UTXO set
+    fn projected_output_root(&self, transactions: &[Transaction]) -> Vec<u8> {
+        block::compute_output_root(&self.projected_output_set(transactions))
+    }
+
+    /// Output commitments after applying transactions to the UTXO set
+    fn projected_output_set(&self, transactions: &[Transaction]) -> Vec<Vec<u8>> {
+        let mut commitments: Vec<Vec<u8>> = self
+            .utxo_set
+            .iter()
+            .map(|utxo| utxo.commitment.clone())
+            .collect();
+
+        for tx in transactions {
+            for input in tx.inputs() {
+                commitments.retain(|commitment| commitment != &input.commitment);
+            }
+            commitments.extend(tx.outputs().iter().map(|output| output.commitment.clone()));
+        }
+
+        commitments
+    }
+}
+
 /// Mimblewimble state manager
 #[derive(Clone, Debug)]
 pub struct MimblewimbleState {
     /// Protocol parameters
     parameters: Arc<MimblewimbleParameters>,
 
-    /// Current block height
-    block_height: Arc<RwLock<u64>>,
+    /// Chain state, updated atomically so readers never see a partial block
+    chain: Arc<RwLock<Chain>>,
 
-    /// UTXO set (pruned)
-    utxo_set: Arc<RwLock<Vec<Commitment>>>,
+    /// Pending transactions
+    mempool: Arc<Mempool>,
 
-    /// Kernel set
-    kernel_set: Arc<RwLock<Vec<Kernel>>>,
+    /// Recently decoded commitment and excess points
+    point_cache: Option<Arc<PointCache>>,
+
+    /// Transactions that passed their stateless checks, shared by the
+    /// mempool and block validation
+    verification_cache: Option<Arc<VerificationCache>>,
+
+    /// Clock for block timestamps
+    time_source: Arc<dyn TimeSource>,
 }
 
 impl MimblewimbleState {
@@ -52,43 +434,123 @@ impl MimblewimbleState {
     pub fn new(parameters: MimblewimbleParameters) -> Result<Self> {
         parameters.validate()?;
 
-        Ok(Self {
+        Ok(Self::with_chain(parameters, Chain::new()))
+    }
+
+    /// Create a state around an existing chain
+    fn with_chain(parameters: MimblewimbleParameters, chain: Chain) -> Self {
+        let point_cache = NonZeroUsize::new(parameters.point_cache_size)
+            .map(|capacity| Arc::new(PointCache::new(capacity)));
+        let verification_cache = NonZeroUsize::new(parameters.verification_cache_size)
+            .map(|capacity| Arc::new(VerificationCache::new(capacity)));
+
+        Self {
             parameters: Arc::new(parameters),
-            block_height: Arc::new(RwLock::new(0)),
-            utxo_set: Arc::new(RwLock::new(Vec::new())),
-            kernel_set: Arc::new(RwLock::new(Vec::new())),
-        })
+            chain: Arc::new(RwLock::new(chain)),
+            mempool: Arc::new(Mempool::new()),
+            point_cache,
+            verification_cache,
+            time_source: Arc::new(SystemTimeSource),
+        }
+    }
+
+    /// Read the time from `time_source` instead of the system clock
+    pub fn with_time_source(mut self, time_source: Arc<dyn TimeSource>) -> Self {
+        self.time_source = time_source;
+        self
     }
 
     /// Add a transaction to the state
+    ///
+    /// Coinbase transactions are only valid inside a block, where the reward
+    /// they claim is checked, and are rejected with `InvalidTransaction`.
     pub fn add_transaction(&self, transaction: &Transaction) -> Result<()> {
-        // Verify transaction
-        self.verify_transaction(transaction)?;
+        if transaction.is_coinbase() {
+            return Err(MimblewimbleError::InvalidTransaction);
+        }
 
-        // Add inputs to UTXO set (remove spent outputs)
-        let mut utxo_set = self.utxo_set.write();
-        for input in &transaction.inputs {
-            utxo_set.retain(|utxo| utxo.commitment != input.commitment);
+        let mut chain = self.chain.write();
+        if !self.verify_against(transaction, &chain)? {
+            return Err(MimblewimbleError::InvalidTransaction);
         }
 
-        // Add outputs to UTXO set
-        for output in &transaction.outputs {
-            utxo_set.push(output.clone());
+        chain.apply(transaction, TxEffect::of(transaction));
+        Ok(())
+    }
+
+    /// Add a batch of transactions to the state
+    ///
+    /// Every transaction is verified against the state left by the ones
+    /// before it, all under a single acquisition of the state lock. Invalid
+    /// and coinbase transactions are skipped and their indices returned.
+    pub fn add_transactions(&self, transactions: &[Transaction]) -> Result<Vec<usize>> {
+        let mut chain = self.chain.write();
+
+        let mut rejected = Vec::new();
+        for (index, transaction) in transactions.iter().enumerate() {
+            if transaction.is_coinbase()
+                || !matches!(self.verify_against(transaction, &chain), Ok(true))
+            {
+                rejected.push(index);
+                continue;
+            }
+
+            chain.apply(transaction, TxEffect::of(transaction));
         }
 
-        // Add kernel
-        let mut kernel_set = self.kernel_set.write();
-        kernel_set.push(transaction.kernel.clone());
+        Ok(rejected)
+    }
 
-        Ok(())
+    /// Simulate a transaction without applying it
+    ///
+    /// Returns the UTXO set delta the transaction would produce, or
+    /// `InvalidTransaction` if it would be rejected.
+    pub fn simulate(&self, transaction: &Transaction) -> Result<TxEffect> {
+        if !self.verify_transaction(transaction)? {
+            return Err(MimblewimbleError::InvalidTransaction);
+        }
+
+        Ok(TxEffect::of(transaction))
     }
 
     /// Verify a transaction
     pub fn verify_transaction(&self, transaction: &Transaction) -> Result<bool> {
+        let chain = self.chain.read();
+        self.verify_against(transaction, &chain)
+    }
+
+    /// Verify a transaction against a given chain state
+    fn verify_against(&self, transaction: &Transaction, chain: &Chain) -> Result<bool> {
+        // Verify commitments are well-formed points
+        if !transaction
+            .inputs()
+            .iter()
+            .chain(transaction.outputs())
+            .all(Commitment::has_valid_length)
+        {
+            return Err(MimblewimbleError::InvalidCommitment);
+        }
+
+        // Verify inputs are in their single canonical order
+        if !transaction.has_sorted_inputs() {
+            return Err(MimblewimbleError::InvalidTransaction);
+        }
+
+        // Verify an anchored transaction is on the anchor's side of any split
+        for anchor in transaction
+            .kernels()
+            .iter()
+            .filter_map(|k| k.anchor.as_ref())
+        {
+            if !chain.is_recent_block(anchor, self.parameters.anchor_window) {
+                return Ok(false);
+            }
+        }
+
         // Verify inputs exist in UTXO set
-        let utxo_set = self.utxo_set.read();
-        for input in &transaction.inputs {
-            if !utxo_set
+        for input in transaction.inputs() {
+            if !chain
+                .utxo_set
                 .iter()
                 .any(|utxo| utxo.commitment == input.commitment)
             {
@@ -96,23 +558,66 @@ impl MimblewimbleState {
             }
         }
 
-        // Verify balance: sum(inputs) = sum(outputs) + fee
-        let input_sum = transaction.inputs.iter().map(|i| i.value).sum::<u64>();
-        let output_sum = transaction.outputs.iter().map(|o| o.value).sum::<u64>();
+        // Verify the fee covers the transaction weight
+        if !transaction.is_coinbase()
+            && transaction.fee() < transaction.minimum_fee(&self.parameters)
+        {
+            return Ok(false);
+        }
+
+        // The remaining checks do not depend on the chain state
+        if self.is_verified(transaction)? {
+            return Ok(true);
+        }
 
-        if input_sum != output_sum + transaction.fee {
+        // Verify balance: sum(outputs) - sum(inputs) + fee*H = sum(excesses)
+        // Coinbase transactions mint the block reward, checked per block
+        let balanced = match &self.point_cache {
+            Some(cache) => transaction.is_balanced_with(cache),
+            None => transaction.is_balanced(),
+        };
+        if !transaction.is_coinbase() && !balanced {
             return Ok(false);
         }
 
-        // Verify range proofs
-        for output in &transaction.outputs {
-            if !output.range_proof.verify(&self.parameters)? {
+        // Verify kernel count and range proofs
+        match transaction.validate(&self.parameters) {
+            Ok(()) => {}
+            Err(MimblewimbleError::InvalidTransaction)
+            | Err(MimblewimbleError::InvalidRangeProof(_)) => return Ok(false),
+            // Proofs bound to another commitment fail with ProofVerificationFailed
+            Err(e) => return Err(e),
+        }
+
+        // Verify kernel proofs
+        for kernel in transaction.kernels() {
+            if !kernel.verify(&self.parameters)? {
                 return Ok(false);
             }
         }
 
-        // Verify kernel proof
-        transaction.kernel.verify(&self.parameters)
+        if let Some(cache) = &self.verification_cache {
+            cache.insert(transaction)?;
+        }
+        Ok(true)
+    }
+
+    /// Check whether a transaction already passed its stateless checks
+    fn is_verified(&self, transaction: &Transaction) -> Result<bool> {
+        match &self.verification_cache {
+            Some(cache) => cache.contains(transaction),
+            None => Ok(false),
+        }
+    }
+
+    /// Lowest block height at which a transaction's kernel locks are satisfied
+    ///
+    /// Relative locks count from the creation height of the referenced
+    /// input, which must be unspent. Returns `None` if a lock can never be
+    /// satisfied.
+    pub fn maturity_height(&self, transaction: &Transaction) -> Option<u64> {
+        let chain = self.chain.read();
+        transaction.maturity_height(|commitment| chain.output_heights.get(commitment).copied())
     }
 
     /// Create a new block
@@ -123,25 +628,19 @@ impl MimblewimbleState {
         }
 
         // Create block header
-        let block_height = *self.block_height.read();
-
-        // Get current timestamp with proper error handling
-        let timestamp = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .map(|d| d.as_secs())
-            .unwrap_or_else(|_| {
-                // Fallback to a reasonable default if system time is before UNIX_EPOCH
-                // This should never happen in practice
-                0
-            });
-
+        let chain = self.chain.read();
         let header = BlockHeader {
             version: MIMBLEWIMBLE_VERSION,
-            height: block_height,
-            timestamp,
-            previous_hash: vec![0; 32],
-            merkle_root: self.compute_merkle_root(&transactions)?,
+            height: chain.block_height,
+            timestamp: self.time_source.now()?.max(chain.min_timestamp()),
+            previous_hash: chain.tip_hash()?,
+            merkle_root: block::compute_merkle_root(&transactions)?,
+            output_root: chain.projected_output_root(&transactions),
+            kernel_root: chain.projected_kernel_root(&transactions),
+            difficulty: pow::next_difficulty(&chain.headers, &self.parameters),
+            nonce: 0,
         };
+        drop(chain);
 
         Ok(Block {
             header,
@@ -149,72 +648,974 @@ impl MimblewimbleState {
         })
     }
 
-    /// Compute merkle root of transactions
-    fn compute_merkle_root(&self, transactions: &[Transaction]) -> Result<Vec<u8>> {
-        use hex;
-        use sha2::{Digest, Sha512};
+    /// Build a block template for external miners
+    ///
+    /// Selects transactions from the mempool, adds a coinbase paying the
+    /// block reward plus fees to an output blinded by `recipient_key`, and
+    /// fills in every header field except the nonce. The timestamp is the
+    /// current time, raised if needed to exceed the median time past.
+    pub fn block_template(&self, recipient_key: &[u8]) -> Result<BlockTemplate> {
+        let height = self.block_height();
+        let selected = self.mempool.select(
+            self.parameters
+                .max_block_size
+                .saturating_sub(COINBASE_RESERVED_SIZE),
+        );
+
+        let fees = block::total_fees(&selected).ok_or(MimblewimbleError::InvalidTransaction)?;
+
+        let mut transactions = vec![Transaction::coinbase(
+            height,
+            fees,
+            recipient_key,
+            &self.parameters,
+        )?];
+        transactions.extend(selected);
+
+        let chain = self.chain.read();
+        if chain.block_height != height {
+            return Err(MimblewimbleError::StaleBlock);
+        }
+        let header = BlockHeader {
+            version: MIMBLEWIMBLE_VERSION,
+            height,
+            timestamp: self.time_source.now()?.max(chain.min_timestamp()),
+            previous_hash: chain.tip_hash()?,
+            merkle_root: block::compute_merkle_root(&transactions)?,
+            output_root: chain.projected_output_root(&transactions),
+            kernel_root: chain.projected_kernel_root(&transactions),
+            difficulty: pow::next_difficulty(&chain.headers, &self.parameters),
+            nonce: 0,
+        };
+        drop(chain);
+
+        Ok(BlockTemplate {
+            header,
+            transactions,
+        })
+    }
+
+    /// Submit a mined block
+    ///
+    /// A block already in the chain is reported as a duplicate; any other
+    /// block is accepted under the rules of `verify_block`.
+    pub fn submit_block(&self, block: Block) -> Result<SubmitResult> {
+        let hash = block.hash()?;
+        if self.chain.read().block_heights.contains_key(&hash) {
+            return Ok(SubmitResult::Duplicate);
+        }
+
+        let height = block.header.height;
+        self.accept_block(block)?;
+
+        Ok(SubmitResult::Accepted { height, hash })
+    }
+
+    /// Verify a block against the chain tip without applying it
+    ///
+    /// Runs under a single read of the state: checks the header against the
+    /// tip (height, previous hash, version, timestamp, difficulty and proof
+    /// of work), the block's wire size and its merkle, kernel and output
+    /// roots, then each transaction's stateless rules and inputs in block
+    /// order. Range proofs and kernels are verified in one pass over the
+    /// whole block, except for transactions in the verification cache,
+    /// followed by a single check of the block's excess sum.
+    pub fn verify_block(&self, block: &Block) -> Result<()> {
+        let chain = self.chain.read();
+        self.validate_block(&chain, block)
+    }
+
+    /// Check a block against `chain`, the single rule set shared by
+    /// `verify_block` and `accept_block`
+    fn validate_block(&self, chain: &Chain, block: &Block) -> Result<()> {
+        let header = &block.header;
+        let transactions = &block.transactions;
+
+        if !header.has_valid_hash_lengths() {
+            return Err(MimblewimbleError::InvalidBlock);
+        }
+
+        if header.previous_hash != chain.tip_hash()? || header.height != chain.block_height {
+            return Err(MimblewimbleError::StaleBlock);
+        }
+
+        let minimum_version = chain.headers.last().map_or(0, |tip| tip.version);
+        if header.version > MIMBLEWIMBLE_VERSION || header.version < minimum_version {
+            return Err(MimblewimbleError::UnsupportedVersion(header.version));
+        }
+
+        if block.wire_size()? > self.parameters.max_block_size {
+            return Err(MimblewimbleError::InvalidBlock);
+        }
+
+        let min_timestamp = chain.min_timestamp();
+        let max_timestamp = self
+            .time_source
+            .now()?
+            .saturating_add(self.parameters.max_future_drift);
+        if header.timestamp < min_timestamp || header.timestamp > max_timestamp {
+            return Err(MimblewimbleError::InvalidBlock);
+        }
+
+        if header.difficulty != pow::next_difficulty(&chain.headers, &self.parameters)
+            || !pow::validate_pow(header, &self.parameters)
+        {
+            return Err(MimblewimbleError::InvalidProofOfWork);
+        }
 
-        if transactions.is_empty() {
-            return Ok(vec![0; 32]);
+        if header.merkle_root != block::compute_merkle_root(transactions)?
+            || header.kernel_root != chain.projected_kernel_root(transactions)
+            || header.output_root != chain.projected_output_root(transactions)
+        {
+            return Err(MimblewimbleError::InvalidBlock);
         }
 
-        let mut hashes: Vec<Vec<u8>> = transactions
+        // Stateless rules and inputs, in block order so a transaction may
+        // only spend outputs created earlier in the block
+        let mut unspent: HashSet<&[u8]> = chain
+            .utxo_set
             .iter()
-            .map(|tx| {
-                let mut hasher = Sha512::new();
-                hasher.update(serde_json::to_vec(tx).unwrap_or_default());
-                hex::encode(hasher.finalize()).into_bytes()
-            })
+            .map(|utxo| utxo.commitment.as_slice())
             .collect();
+        let mut fees = 0u64;
+        for tx in transactions {
+            if self.parameters.enforce_canonical_order && !tx.is_canonical() {
+                return Err(MimblewimbleError::InvalidBlock);
+            }
+
+            if !tx
+                .inputs()
+                .iter()
+                .chain(tx.outputs())
+                .all(Commitment::has_valid_length)
+            {
+                return Err(MimblewimbleError::InvalidCommitment);
+            }
+
+            let created_at = |commitment: &[u8]| chain.output_heights.get(commitment).copied();
+            if !tx.has_sorted_inputs()
+                || tx
+                    .maturity_height(created_at)
+                    .is_none_or(|mature| mature > header.height)
+            {
+                return Err(MimblewimbleError::InvalidTransaction);
+            }
+
+            for anchor in tx.kernels().iter().filter_map(|k| k.anchor.as_ref()) {
+                if !chain.is_recent_block(anchor, self.parameters.anchor_window) {
+                    return Err(MimblewimbleError::InvalidTransaction);
+                }
+            }
+
+            if !tx.is_coinbase() {
+                if tx.fee() < tx.minimum_fee(&self.parameters) {
+                    return Err(MimblewimbleError::InvalidTransaction);
+                }
+                fees = fees
+                    .checked_add(tx.fee())
+                    .ok_or(MimblewimbleError::InvalidTransaction)?;
+            }
 
-        while hashes.len() > 1 {
-            let mut next_level = Vec::new();
-            for i in (0..hashes.len()).step_by(2) {
-                let mut hasher = Sha512::new();
-                hasher.update(&hashes[i]);
-                if i + 1 < hashes.len() {
-                    hasher.update(&hashes[i + 1]);
-                } else {
-                    hasher.update(&hashes[i]);
+            tx.validate_structure(&self.parameters)?;
+
+            for input in tx.inputs() {
+                if !unspent.remove(input.commitment.as_slice()) {
+                    return Err(MimblewimbleError::InvalidTransaction);
                 }
-                next_level.push(hex::encode(hasher.finalize()).into_bytes());
             }
-            hashes = next_level;
+            unspent.extend(
+                tx.outputs()
+                    .iter()
+                    .map(|output| output.commitment.as_slice()),
+            );
         }
 
-        Ok(hashes[0].clone())
-    }
+        // Range proofs and kernels across the whole block, skipping
+        // transactions verified before, e.g. on entering the mempool
+        let verified = transactions
+            .iter()
+            .map(|tx| self.is_verified(tx))
+            .collect::<Result<Vec<_>>>()?;
+        for (index, (output, verified)) in transactions
+            .iter()
+            .zip(&verified)
+            .flat_map(|(tx, verified)| tx.outputs().iter().map(move |output| (output, *verified)))
+            .enumerate()
+        {
+            if !verified
+                && !output
+                    .range_proof
+                    .verify(&output.commitment, &self.parameters)?
+            {
+                return Err(MimblewimbleError::InvalidRangeProof(index));
+            }
+        }
+        for (index, (kernel, verified)) in transactions
+            .iter()
+            .zip(&verified)
+            .flat_map(|(tx, verified)| tx.kernels().iter().map(move |kernel| (kernel, *verified)))
+            .enumerate()
+        {
+            if !verified && !kernel.verify(&self.parameters)? {
+                return Err(MimblewimbleError::InvalidKernelSignature(index));
+            }
+        }
 
-    /// Get current block height
-    pub fn block_height(&self) -> u64 {
-        *self.block_height.read()
-    }
+        // At most one coinbase, claiming exactly the reward plus fees
+        let coinbases = transactions.iter().filter(|tx| tx.is_coinbase()).count();
+        if coinbases > 1 || !block.has_valid_coinbase(&self.parameters)? {
+            return Err(MimblewimbleError::InvalidBlock);
+        }
 
-    /// Get UTXO set size
-    pub fn utxo_set_size(&self) -> usize {
-        self.utxo_set.read().len()
-    }
+        // Without a coinbase the fees are burned; with one it claims them
+        // alongside the reward
+        let balanced = if coinbases == 1 {
+            block.is_balanced(self.parameters.block_reward(header.height))
+        } else {
+            block.is_balanced_with_fees(fees)
+        };
+        if !balanced {
+            return Err(MimblewimbleError::BalanceMismatch);
+        }
 
-    /// Get kernel set size
-    pub fn kernel_set_size(&self) -> usize {
-        self.kernel_set.read().len()
+        Ok(())
     }
 
-    /// Get parameters
-    pub fn parameters(&self) -> Arc<MimblewimbleParameters> {
-        Arc::clone(&self.parameters)
+    /// Validate a block against the chain tip and apply it
+    ///
+    /// Runs the same checks as `verify_block`. The state lock is held from
+    /// validation until the block is fully applied, so readers never observe
+    /// a partially applied block.
+    pub fn accept_block(&self, block: Block) -> Result<()> {
+        let mut chain = self.chain.write();
+        self.validate_block(&chain, &block)?;
+
+        let included = block
+            .transactions
+            .iter()
+            .map(Transaction::hash)
+            .collect::<Result<Vec<_>>>()?;
+        let minted = if block.transactions.iter().any(Transaction::is_coinbase) {
+            block.expected_coinbase_value(&self.parameters)?
+        } else {
+            0
+        };
+        let delta = block.utxo_delta()?;
+        let hash = block.header.hash()?;
+
+        for tx in &block.transactions {
+            chain.apply(tx, TxEffect::of(tx));
+        }
+        chain.supply = chain.supply.saturating_add(minted);
+
+        chain.output_index.insert(block.header.height, delta);
+        let output_root = chain.output_root();
+        chain.output_roots.push(Some(output_root));
+        let kernel_count = chain.kernel_set.len();
+        chain.kernel_counts.push(Some(kernel_count));
+        let height = chain.block_height;
+        chain.block_heights.insert(hash, height);
+        chain.headers.push(block.header);
+        chain.bodies.push(Some(block.transactions));
+        chain.block_height += 1;
+
+        drop(chain);
+        self.mempool.remove_included(&included);
+        self.mempool.revalidate(self);
+        self.mempool.promote_deferred(self);
+
+        Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Capture a consistent view of the height, UTXO set and kernel set
+    pub fn snapshot(&self) -> StateSnapshot {
+        let chain = self.chain.read();
+        StateSnapshot {
+            height: chain.block_height,
+            utxo_set: chain.utxo_set.clone(),
+            output_heights: chain
+                .utxo_set
+                .iter()
+                .map(|utxo| chain.output_heights.get(&utxo.commitment).copied())
+                .collect(),
+            kernel_set: chain.kernel_set.kernels().cloned().collect(),
+        }
+    }
 
-    #[test]
-    fn test_mimblewimble_state_creation() {
-        let params = MimblewimbleParameters::default();
-        let state = MimblewimbleState::new(params);
-        assert!(state.is_ok());
+    /// Package the chain state into a checkpoint bundle
+    pub fn export_checkpoint(&self) -> CheckpointBundle {
+        let chain = self.chain.read();
+        CheckpointBundle {
+            headers: chain.headers.clone(),
+            utxo_set: chain.utxo_set.clone(),
+            output_heights: chain
+                .utxo_set
+                .iter()
+                .map(|utxo| {
+                    chain
+                        .output_heights
+                        .get(&utxo.commitment)
+                        .copied()
+                        .unwrap_or_default()
+                })
+                .collect(),
+            kernel_set: chain.kernel_set.kernels().cloned().collect(),
+            kernel_set_hash: crypto::point_to_bytes(&chain.kernel_set_hash),
+            output_root: chain.output_root(),
+            supply: chain.supply,
+            total_offset: crypto::scalar_to_bytes(&chain.total_offset),
+        }
+    }
+
+    /// Create a state from a checkpoint bundle
+    ///
+    /// The header chain is verified, and the UTXO and kernel sets must match
+    /// the output and kernel roots of the tip header. Kernels that still carry
+    /// a signature are verified, and the UTXO set must hold exactly the
+    /// bundle's supply, which may not exceed the rewards paid up to the tip.
+    /// Block bodies are not part of a checkpoint, so blocks below the
+    /// checkpoint cannot be served.
+    pub fn import_checkpoint(
+        bundle: CheckpointBundle,
+        parameters: MimblewimbleParameters,
+    ) -> Result<Self> {
+        parameters.validate()?;
+        block::verify_header_chain(&bundle.headers, &parameters)?;
+
+        let mut kernel_set_hash = ProjectivePoint::IDENTITY;
+        let mut kernel_mmr = MerkleMountainRange::new();
+        for kernel in &bundle.kernel_set {
+            // Pruned kernels are bound by the kernel root alone
+            if !kernel.signature.is_empty() && !matches!(kernel.verify(&parameters), Ok(true)) {
+                return Err(MimblewimbleError::InvalidCheckpoint);
+            }
+            kernel_set_hash += kernel_set_element(&kernel.excess);
+            kernel_mmr.push(kernel.excess.clone());
+        }
+        let total_excess = crypto::sum_points(
+            bundle
+                .kernel_set
+                .iter()
+                .map(|kernel| kernel.excess.as_slice()),
+        )
+        .map_err(|_| MimblewimbleError::InvalidCheckpoint)?;
+        if bundle.kernel_set_hash != crypto::point_to_bytes(&kernel_set_hash) {
+            return Err(MimblewimbleError::InvalidCheckpoint);
+        }
+
+        // The sets must be the ones the tip header commits to
+        let (output_root, kernel_root) = match bundle.headers.last() {
+            Some(tip) => (tip.output_root.clone(), tip.kernel_root.clone()),
+            None => (
+                Chain::new().output_root(),
+                MerkleMountainRange::new().root(),
+            ),
+        };
+        if bundle.output_root != output_root || kernel_mmr.root() != kernel_root {
+            return Err(MimblewimbleError::InvalidCheckpoint);
+        }
+
+        let height = bundle.headers.len() as u64;
+        if u128::from(bundle.supply) > parameters.total_supply(height) {
+            return Err(MimblewimbleError::InvalidCheckpoint);
+        }
+
+        // Relative locks depend on when each output was created
+        if bundle.output_heights.len() != bundle.utxo_set.len()
+            || bundle
+                .output_heights
+                .iter()
+                .any(|created| *created >= height)
+        {
+            return Err(MimblewimbleError::InvalidCheckpoint);
+        }
+        let output_heights = bundle
+            .utxo_set
+            .iter()
+            .map(|utxo| utxo.commitment.clone())
+            .zip(bundle.output_heights)
+            .collect();
+
+        // Only the tip's output root is verified by the bundle
+        let mut output_roots = vec![None; bundle.headers.len()];
+        if let Some(tip) = output_roots.last_mut() {
+            *tip = Some(bundle.output_root.clone());
+        }
+        let mut kernel_counts = vec![None; bundle.headers.len()];
+        if let Some(tip) = kernel_counts.last_mut() {
+            *tip = Some(bundle.kernel_set.len());
+        }
+
+        let block_heights = bundle
+            .headers
+            .iter()
+            .zip(0..)
+            .map(|(header, height)| Ok((header.hash()?, height)))
+            .collect::<Result<_>>()?;
+
+        let chain = Chain {
+            block_height: height,
+            utxo_set: bundle.utxo_set,
+            kernel_set: KernelLog::from_kernels(bundle.kernel_set),
+            kernel_set_hash,
+            kernel_mmr,
+            total_excess,
+            total_offset: crypto::scalar_from_bytes(&bundle.total_offset)
+                .map_err(|_| MimblewimbleError::InvalidCheckpoint)?,
+            supply: bundle.supply,
+            kernel_counts,
+            bodies: vec![None; bundle.headers.len()],
+            headers: bundle.headers,
+            block_heights,
+            output_index: BTreeMap::new(),
+            output_roots,
+            output_heights,
+        };
+        if chain.output_root() != bundle.output_root {
+            return Err(MimblewimbleError::InvalidCheckpoint);
+        }
+
+        let state = Self::with_chain(parameters, chain);
+        let supply = state.supply();
+        state
+            .verify_total_excess(supply)
+            .map_err(|_| MimblewimbleError::InvalidCheckpoint)?;
+        Ok(state)
+    }
+
+    /// Unspent outputs per kernel, or zero while there are no kernels
+    ///
+    /// Every transaction adds at least one kernel, so a ratio well above the
+    /// typical outputs per transaction suggests dust accumulating unspent.
+    pub fn utxo_kernel_ratio(&self) -> f64 {
+        let chain = self.chain.read();
+        ratio(chain.utxo_set.len(), chain.kernel_set.len())
+    }
+
+    /// Report operational metrics
+    pub fn metrics(&self) -> StateMetrics {
+        let chain = self.chain.read();
+        StateMetrics {
+            block_height: chain.block_height,
+            utxo_count: chain.utxo_set.len(),
+            kernel_count: chain.kernel_set.len(),
+            utxo_kernel_ratio: ratio(chain.utxo_set.len(), chain.kernel_set.len()),
+            mempool_size: self.mempool.size(),
+            memory_bytes: chain.approximate_memory(),
+        }
+    }
+
+    /// Export accepted headers in `from_height..to_height` for SPV clients
+    ///
+    /// Fails with `InvalidParameter` if the range is empty, extends past the
+    /// tip or spans more than `MAX_EXPORTED_HEADERS` headers.
+    pub fn export_headers(&self, from_height: u64, to_height: u64) -> Result<Vec<BlockHeader>> {
+        let chain = self.chain.read();
+        if from_height >= to_height
+            || to_height > chain.block_height
+            || to_height - from_height > MAX_EXPORTED_HEADERS
+        {
+            return Err(MimblewimbleError::InvalidParameter);
+        }
+
+        Ok(chain.headers[from_height as usize..to_height as usize].to_vec())
+    }
+
+    /// Outputs created and spent by the block at a height
+    ///
+    /// Only blocks accepted by this node are indexed, not those adopted
+    /// from a checkpoint, and the index is pruned along with block bodies.
+    pub fn outputs_at_height(&self, height: u64) -> Option<UtxoDelta> {
+        self.chain.read().output_index.get(&height).cloned()
+    }
+
+    /// Get an accepted block by height
+    ///
+    /// Returns `None` for heights whose body was pruned or adopted from a
+    /// checkpoint; their headers remain available through `get_header`.
+    pub fn get_block(&self, height: u64) -> Option<Block> {
+        let index = usize::try_from(height).ok()?;
+        let chain = self.chain.read();
+        let header = chain.headers.get(index)?.clone();
+        let transactions = chain.bodies.get(index)?.clone()?;
+        Some(Block {
+            header,
+            transactions,
+        })
+    }
+
+    /// Get an accepted block header by height
+    pub fn get_header(&self, height: u64) -> Option<BlockHeader> {
+        let index = usize::try_from(height).ok()?;
+        self.chain.read().headers.get(index).cloned()
+    }
+
+    /// Discard block bodies more than `pruning_interval` blocks below the tip
+    ///
+    /// Their entries in the output index go with them. Headers, the UTXO
+    /// set and the kernel set are kept, so the chain state is unaffected.
+    /// Returns the number of bodies discarded; nothing is pruned unless
+    /// `pruning_enabled` is set.
+    pub fn prune_block_bodies(&self) -> usize {
+        if !self.parameters.pruning_enabled {
+            return 0;
+        }
+
+        let mut chain = self.chain.write();
+        let retained = usize::try_from(self.parameters.pruning_interval).unwrap_or(usize::MAX);
+        let horizon = chain.bodies.len().saturating_sub(retained);
+        let index = chain.output_index.split_off(&(horizon as u64));
+        chain.output_index = index;
+        chain.bodies[..horizon]
+            .iter_mut()
+            .filter_map(Option::take)
+            .count()
+    }
+
+    /// Discard the signatures of kernels from blocks below `horizon`
+    ///
+    /// Excesses are kept, and with them `total_excess` and the no-inflation
+    /// checks, as are the kernel log's entry hashes. Pruned kernels can no
+    /// longer be verified on their own. Returns the number of signatures
+    /// discarded; nothing is pruned unless `pruning_enabled` is set, or below
+    /// an imported checkpoint's tip.
+    pub fn prune_kernels(&self, horizon: u64) -> usize {
+        if !self.parameters.pruning_enabled {
+            return 0;
+        }
+
+        let mut chain = self.chain.write();
+        let blocks = usize::try_from(horizon)
+            .unwrap_or(usize::MAX)
+            .min(chain.kernel_counts.len());
+        let count = chain.kernel_counts[..blocks]
+            .iter()
+            .rev()
+            .find_map(|count| *count)
+            .unwrap_or(0);
+        chain.kernel_set.prune_signatures(count)
+    }
+
+    /// Output set root after the block at `height` was applied
+    ///
+    /// Membership proofs against a past state verify against this root.
+    /// Heights below an imported checkpoint are unknown.
+    pub fn output_root_at(&self, height: u64) -> Option<Vec<u8>> {
+        let index = usize::try_from(height).ok()?;
+        self.chain.read().output_roots.get(index)?.clone()
+    }
+
+    /// Serve a range of an accepted block's transactions
+    pub fn serve_block_txs(&self, block_hash: &[u8], start: u32, count: u32) -> Result<NetMessage> {
+        let chain = self.chain.read();
+        let height = *chain
+            .block_heights
+            .get(block_hash)
+            .ok_or(MimblewimbleError::BlockNotFound)?;
+
+        let body = chain
+            .bodies
+            .get(height as usize)
+            .and_then(Option::as_ref)
+            .ok_or(MimblewimbleError::BlockNotFound)?;
+        let begin = (start as usize).min(body.len());
+        let end = begin.saturating_add(count as usize).min(body.len());
+
+        Ok(NetMessage::BlockTxs {
+            block_hash: block_hash.to_vec(),
+            start,
+            total: body.len() as u32,
+            transactions: body[begin..end].to_vec(),
+        })
+    }
+
+    /// Coins held by the UTXO set: block rewards claimed minus fees burned
+    pub fn supply(&self) -> u64 {
+        self.chain.read().supply
+    }
+
+    /// Verify that the UTXO set holds exactly `expected_supply` coins
+    ///
+    /// Checks `sum(utxo commitments) == expected_supply*H + total_offset*G +
+    /// sum(kernel excesses)`, which holds only if no transaction created
+    /// coins out of thin air.
+    pub fn verify_no_inflation(&self, expected_supply: u64) -> Result<()> {
+        let chain = self.chain.read();
+
+        let utxo_sum =
+            crypto::sum_points(chain.utxo_set.iter().map(|utxo| utxo.commitment.as_slice()))
+                .map_err(|_| MimblewimbleError::InvalidCommitment)?;
+        let excess_sum = crypto::sum_points(
+            chain
+                .kernel_set
+                .kernels()
+                .map(|kernel| kernel.excess.as_slice()),
+        )
+        .map_err(|_| MimblewimbleError::InvalidKernel)?;
+
+        if utxo_sum != crypto::commit(expected_supply, &chain.total_offset) + excess_sum {
+            return Err(MimblewimbleError::BalanceMismatch);
+        }
+
+        Ok(())
+    }
+
+    /// Verify the global balance against the running kernel excess sum
+    ///
+    /// Checks `sum(utxo commitments) - total_excess == expected_supply*H +
+    /// total_offset*G` without revisiting the kernel set.
+    pub fn verify_total_excess(&self, expected_supply: u64) -> Result<()> {
+        let chain = self.chain.read();
+
+        let utxo_sum =
+            crypto::sum_points(chain.utxo_set.iter().map(|utxo| utxo.commitment.as_slice()))
+                .map_err(|_| MimblewimbleError::InvalidCommitment)?;
+
+        if utxo_sum - chain.total_excess != crypto::commit(expected_supply, &chain.total_offset) {
+            return Err(MimblewimbleError::BalanceMismatch);
+        }
+
+        Ok(())
+    }
+
+    /// Apply a UTXO delta to the state
+    ///
+    /// Added outputs get the length and range proof checks of block outputs
+    /// and the delta's kernels must verify. The outputs must balance against
+    /// the kernels, the offset and the block reward or burned fees, and the
+    /// supply moves by the same amount. Added outputs are recorded as created
+    /// at the delta's height. Returns the removed outputs, which are needed to
+    /// revert the delta.
+    pub fn apply_delta(&self, delta: &UtxoDelta) -> Result<Vec<RemovedOutput>> {
+        for (index, output) in delta.added.iter().enumerate() {
+            if !output.has_valid_length() {
+                return Err(MimblewimbleError::InvalidCommitment);
+            }
+            if !output
+                .range_proof
+                .verify(&output.commitment, &self.parameters)?
+            {
+                return Err(MimblewimbleError::InvalidRangeProof(index));
+            }
+        }
+        for (index, kernel) in delta.kernels.iter().enumerate() {
+            if !kernel.verify(&self.parameters)? {
+                return Err(MimblewimbleError::InvalidKernelSignature(index));
+            }
+        }
+        let (minted, burned) = self.delta_supply_change(delta)?;
+        let offset = crypto::scalar_from_bytes(&delta.offset)?;
+
+        let mut chain = self.chain.write();
+
+        let mut removed = Vec::with_capacity(delta.removed.len());
+        for commitment in &delta.removed {
+            let utxo = chain
+                .utxo_set
+                .iter()
+                .find(|utxo| &utxo.commitment == commitment)
+                .ok_or(MimblewimbleError::UtxoNotFound)?;
+            removed.push(RemovedOutput {
+                output: utxo.clone(),
+                created_at: chain.output_heights.get(commitment).copied(),
+            });
+        }
+
+        let mut added = HashSet::new();
+        if delta.added.iter().any(|output| {
+            !added.insert(output.commitment.as_slice())
+                || chain
+                    .utxo_set
+                    .iter()
+                    .any(|utxo| utxo.commitment == output.commitment)
+        }) {
+            return Err(MimblewimbleError::DoubleSpend);
+        }
+
+        let added_sum = transaction::sum_commitments(&delta.added)
+            .map_err(|_| MimblewimbleError::InvalidCommitment)?;
+        let removed_sum = crypto::sum_points(
+            removed
+                .iter()
+                .map(|removed| removed.output.commitment.as_slice()),
+        )
+        .map_err(|_| MimblewimbleError::InvalidCommitment)?;
+        let excess_sum =
+            crypto::sum_points(delta.kernels.iter().map(|kernel| kernel.excess.as_slice()))
+                .map_err(|_| MimblewimbleError::InvalidKernel)?;
+        if added_sum + crypto::commit(burned, &Scalar::ZERO)
+            != removed_sum + crypto::commit(minted, &offset) + excess_sum
+        {
+            return Err(MimblewimbleError::BalanceMismatch);
+        }
+        let supply = chain
+            .supply
+            .checked_add(minted)
+            .and_then(|supply| supply.checked_sub(burned))
+            .ok_or(MimblewimbleError::BalanceMismatch)?;
+
+        chain
+            .utxo_set
+            .retain(|utxo| !delta.removed.contains(&utxo.commitment));
+        for commitment in &delta.removed {
+            chain.output_heights.remove(commitment);
+        }
+        for output in &delta.added {
+            chain
+                .output_heights
+                .insert(output.commitment.clone(), delta.height);
+        }
+        chain.utxo_set.extend(delta.added.iter().cloned());
+        for kernel in &delta.kernels {
+            chain.append_kernel(kernel);
+        }
+        chain.total_offset += offset;
+        chain.supply = supply;
+
+        Ok(removed)
+    }
+
+    /// Revert a previously applied UTXO delta
+    ///
+    /// `removed` must be the outputs returned when the delta was applied, and
+    /// the delta's kernels must be the last ones in the kernel set. Rewinding
+    /// the chain also clears the verification cache.
+    pub fn revert_delta(&self, delta: &UtxoDelta, removed: &[RemovedOutput]) -> Result<()> {
+        let restores_removed = removed.len() == delta.removed.len()
+            && removed
+                .iter()
+                .zip(&delta.removed)
+                .all(|(removed, commitment)| &removed.output.commitment == commitment);
+        if !restores_removed {
+            return Err(MimblewimbleError::InvalidParameter);
+        }
+        let (minted, burned) = self.delta_supply_change(delta)?;
+        let offset = crypto::scalar_from_bytes(&delta.offset)?;
+
+        let mut chain = self.chain.write();
+        if !delta.added.iter().all(|output| {
+            chain
+                .utxo_set
+                .iter()
+                .any(|utxo| utxo.commitment == output.commitment)
+        }) {
+            return Err(MimblewimbleError::UtxoNotFound);
+        }
+
+        let kernel_count = chain
+            .kernel_set
+            .len()
+            .checked_sub(delta.kernels.len())
+            .filter(|&count| {
+                chain
+                    .kernel_set
+                    .kernels()
+                    .skip(count)
+                    .map(|kernel| &kernel.excess)
+                    .eq(delta.kernels.iter().map(|kernel| &kernel.excess))
+            })
+            .ok_or(MimblewimbleError::InvalidParameter)?;
+
+        chain.utxo_set.retain(|utxo| {
+            !delta
+                .added
+                .iter()
+                .any(|output| output.commitment == utxo.commitment)
+        });
+        for output in &delta.added {
+            chain.output_heights.remove(&output.commitment);
+        }
+        for RemovedOutput { output, created_at } in removed {
+            if let Some(created_at) = created_at {
+                chain
+                    .output_heights
+                    .insert(output.commitment.clone(), *created_at);
+            }
+            chain.utxo_set.push(output.clone());
+        }
+        chain.truncate_kernels(kernel_count);
+        chain.total_offset -= offset;
+        chain.supply = chain.supply.saturating_add(burned).saturating_sub(minted);
+
+        if let Some(cache) = &self.verification_cache {
+            cache.clear();
+        }
+        Ok(())
+    }
+
+    /// Coins a delta mints and burns: the block reward if it carries a
+    /// coinbase kernel, otherwise the fees of its kernels
+    fn delta_supply_change(&self, delta: &UtxoDelta) -> Result<(u64, u64)> {
+        if delta
+            .kernels
+            .iter()
+            .any(|kernel| kernel.features == kernel::KernelFeatures::Coinbase)
+        {
+            return Ok((self.parameters.block_reward(delta.height), 0));
+        }
+
+        let fees = delta
+            .kernels
+            .iter()
+            .try_fold(0u64, |sum, kernel| sum.checked_add(kernel.fee))
+            .ok_or(MimblewimbleError::InvalidBlock)?;
+        Ok((0, fees))
+    }
+
+    /// Acceptable timestamps for the next block, inclusive
+    ///
+    /// The minimum is one second past the median time of the recent headers
+    /// and the maximum is `max_future_drift` past the state's time source.
+    pub fn valid_timestamp_range(&self) -> Result<(u64, u64)> {
+        let min = self.chain.read().min_timestamp();
+        let max = self
+            .time_source
+            .now()?
+            .saturating_add(self.parameters.max_future_drift);
+        Ok((min, max))
+    }
+
+    /// Verify the hash chain of the kernel log
+    pub fn verify_kernel_log(&self) -> Result<()> {
+        self.chain.read().kernel_set.verify()
+    }
+
+    /// Get current block height
+    pub fn block_height(&self) -> u64 {
+        self.chain.read().block_height
+    }
+
+    /// Check whether the chain has caught up with the best known peer height
+    pub fn is_synced(&self, best_known_height: u64) -> bool {
+        self.block_height() >= best_known_height
+    }
+
+    /// Fraction of the best known peer height reached, between 0 and 1
+    pub fn sync_progress(&self, best_known_height: u64) -> f64 {
+        if best_known_height == 0 {
+            return 1.0;
+        }
+        (self.block_height() as f64 / best_known_height as f64).min(1.0)
+    }
+
+    /// Get UTXO set size
+    pub fn utxo_set_size(&self) -> usize {
+        self.chain.read().utxo_set.len()
+    }
+
+    /// Size the UTXO set would have after applying a block, without applying it
+    ///
+    /// Counts the block's spends and new outputs. The block itself is not
+    /// validated.
+    pub fn projected_utxo_size(&self, block: &Block) -> usize {
+        self.chain
+            .read()
+            .projected_output_set(&block.transactions)
+            .len()
+    }
+
+    /// Check whether an output is in the UTXO set
+    pub fn is_unspent(&self, commitment: &[u8]) -> bool {
+        self.chain
+            .read()
+            .utxo_set
+            .iter()
+            .any(|utxo| utxo.commitment == commitment)
+    }
+
+    /// Get the root of the current UTXO set
+    pub fn utxo_root(&self) -> Vec<u8> {
+        block::compute_output_root(&self.utxo_set_commitments())
+    }
+
+    /// Prove that an output is in the current UTXO set
+    ///
+    /// The proof verifies against `utxo_root` and the output root of the
+    /// header that produced the current state.
+    pub fn prove_membership(&self, commitment: &[u8]) -> Result<MembershipProof> {
+        block::output_mmr(&self.utxo_set_commitments())
+            .prove_membership(commitment)
+            .ok_or(MimblewimbleError::UtxoNotFound)
+    }
+
+    /// Commitments of the current UTXO set
+    fn utxo_set_commitments(&self) -> Vec<Vec<u8>> {
+        self.chain
+            .read()
+            .utxo_set
+            .iter()
+            .map(|utxo| utxo.commitment.clone())
+            .collect()
+    }
+
+    /// Sorted, distinct commitments of the current UTXO set
+    ///
+    /// Carries no values or proofs, for reconciling UTXO sets with light
+    /// clients.
+    pub fn utxo_commitments(&self) -> Vec<Vec<u8>> {
+        let mut commitments = self.utxo_set_commitments();
+        commitments.sort();
+        commitments.dedup();
+        commitments
+    }
+
+    /// Hash of `utxo_commitments`, for comparing UTXO sets
+    pub fn utxo_commitments_hash(&self) -> Result<Vec<u8>> {
+        serialization::hash(&self.utxo_commitments())
+    }
+
+    /// Get kernel set size
+    pub fn kernel_set_size(&self) -> usize {
+        self.chain.read().kernel_set.len()
+    }
+
+    /// Get the kernel set hash
+    ///
+    /// The hash is a running sum of kernel excesses hashed to the curve, so
+    /// two nodes with the same kernels agree on it regardless of the order
+    /// they were applied.
+    pub fn kernel_set_hash(&self) -> Vec<u8> {
+        hex::encode(crypto::point_to_bytes(&self.chain.read().kernel_set_hash)).into_bytes()
+    }
+
+    /// Get parameters
+    pub fn parameters(&self) -> Arc<MimblewimbleParameters> {
+        Arc::clone(&self.parameters)
+    }
+
+    /// Get the mempool
+    pub fn mempool(&self) -> &Mempool {
+        &self.mempool
+    }
+}
+
+/// Element of the kernel set hash contributed by one kernel excess
+///
+/// The set hash is the sum of these points: an elliptic curve multiset
+/// hash, whose collisions are as hard to find as discrete logarithms.
+fn kernel_set_element(excess: &[u8]) -> ProjectivePoint {
+    crypto::hash_to_point(KERNEL_SET_TAG, &[excess])
+}
+
+/// Ratio of two counts, or zero if the denominator is zero
+fn ratio(numerator: usize, denominator: usize) -> f64 {
+    if denominator == 0 {
+        0.0
+    } else {
+        numerator as f64 / denominator as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kernel::KernelFeatures;
+
+    #[test]
+    fn test_mimblewimble_state_creation() {
+        let params = MimblewimbleParameters::default();
+        let state = MimblewimbleState::new(params);
+        assert!(state.is_ok());
     }
 
     #[test]
@@ -223,4 +1624,1761 @@ mod tests {
         let state = MimblewimbleState::new(params).unwrap();
         assert_eq!(state.block_height(), 0);
     }
+
+    pub(crate) fn output(value: u64, blinding: u8) -> Commitment {
+        let params = MimblewimbleParameters::default();
+        Commitment::create(value, vec![blinding; 32], &params).unwrap()
+    }
+
+    pub(crate) fn state_with_outputs(outputs: &[Commitment]) -> MimblewimbleState {
+        let state = MimblewimbleState::new(MimblewimbleParameters::default()).unwrap();
+        state.chain.write().utxo_set.extend_from_slice(outputs);
+        state
+    }
+
+    /// Build a balanced transaction with a kernel signed by its excess
+    pub(crate) fn balanced_tx(
+        inputs: Vec<Commitment>,
+        outputs: Vec<Commitment>,
+        fee: u64,
+    ) -> Transaction {
+        balanced_tx_on(&MimblewimbleParameters::default(), inputs, outputs, fee)
+    }
+
+    /// Build a balanced transaction signed for the network of `parameters`
+    pub(crate) fn balanced_tx_on(
+        parameters: &MimblewimbleParameters,
+        inputs: Vec<Commitment>,
+        outputs: Vec<Commitment>,
+        fee: u64,
+    ) -> Transaction {
+        let blindings = |commitments: &[Commitment]| {
+            commitments
+                .iter()
+                .map(|c| crypto::scalar_from_bytes(&c.blinding).unwrap())
+                .collect::<Vec<_>>()
+        };
+        let excess = wallet::blinding_sum(&blindings(&inputs), &blindings(&outputs), Scalar::ZERO);
+        let mut kernel = Kernel::new(KernelFeatures::Plain, fee, 0, vec![], vec![]);
+        kernel
+            .sign(&crypto::scalar_to_bytes(&excess), parameters)
+            .unwrap();
+        Transaction::new(1, inputs, outputs, kernel, fee)
+    }
+
+    #[test]
+    fn test_simulate_matches_application() {
+        let params = MimblewimbleParameters::default();
+        let state = MimblewimbleState::new(params).unwrap();
+        let funding = output(1000, 1);
+        state.chain.write().utxo_set.push(funding.clone());
+
+        let tx = balanced_tx(vec![funding.clone()], vec![output(990, 2)], 10);
+
+        let effect = state.simulate(&tx).unwrap();
+        assert_eq!(effect.spent, vec![funding.commitment.clone()]);
+        assert_eq!(effect.created, tx.outputs());
+        assert_eq!(effect.fee, 10);
+
+        // Simulation must not touch the state
+        assert_eq!(state.utxo_set_size(), 1);
+        assert_eq!(state.kernel_set_size(), 0);
+
+        state.add_transaction(&tx).unwrap();
+        let utxo_set = state.snapshot().utxo_set;
+        assert!(!utxo_set
+            .iter()
+            .any(|u| effect.spent.contains(&u.commitment)));
+        assert_eq!(utxo_set, effect.created);
+    }
+
+    #[test]
+    fn test_minimum_fee_is_exact() -> Result<()> {
+        let params = MimblewimbleParameters {
+            min_fee_per_weight: 2,
+            ..Default::default()
+        };
+        let state = MimblewimbleState::new(params.clone())?;
+        let funding = output(1000, 1);
+        state.chain.write().utxo_set.push(funding.clone());
+
+        let spend = |fee: u64| balanced_tx(vec![funding.clone()], vec![output(1000 - fee, 2)], fee);
+
+        let fee = spend(0).minimum_fee(&params);
+        assert_eq!(
+            fee,
+            2 * (transaction::INPUT_WEIGHT
+                + transaction::OUTPUT_WEIGHT
+                + transaction::KERNEL_WEIGHT)
+        );
+        assert!(state.verify_transaction(&spend(fee))?);
+        assert!(!state.verify_transaction(&spend(fee - 1))?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_commitment_length_is_checked() {
+        let funding = output(1000, 1);
+        let state = state_with_outputs(std::slice::from_ref(&funding));
+
+        for length in [16, 64] {
+            let mut change = output(990, 2);
+            change.commitment.truncate(length);
+            let kernel = Kernel::new(KernelFeatures::Plain, 10, 0, vec![1; 32], vec![2; 64]);
+            let tx = Transaction::new(1, vec![funding.clone()], vec![change], kernel, 10);
+            assert!(matches!(
+                state.verify_transaction(&tx),
+                Err(MimblewimbleError::InvalidCommitment)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_simulate_rejects_invalid() {
+        let params = MimblewimbleParameters::default();
+        let state = MimblewimbleState::new(params).unwrap();
+
+        let kernel = Kernel::new(
+            kernel::KernelFeatures::Plain,
+            10,
+            0,
+            vec![1; 32],
+            vec![2; 64],
+        );
+        let tx = Transaction::new(1, vec![output(1000, 1)], vec![output(990, 2)], kernel, 10);
+
+        assert!(matches!(
+            state.simulate(&tx),
+            Err(MimblewimbleError::InvalidTransaction)
+        ));
+    }
+
+    #[test]
+    fn test_coinbase_cannot_be_added_outside_a_block() -> Result<()> {
+        let state = MimblewimbleState::new(MimblewimbleParameters::default())?;
+        let coinbase = Transaction::coinbase(0, 1_000_000, &[1; 32], &state.parameters())?;
+        assert!(matches!(
+            state.add_transaction(&coinbase),
+            Err(MimblewimbleError::InvalidTransaction)
+        ));
+        assert_eq!(state.utxo_set_size(), 0);
+        assert_eq!(state.kernel_set_size(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_transactions_skips_invalid() -> Result<()> {
+        let (first, second) = (output(1000, 1), output(500, 2));
+        let state = state_with_outputs(&[first.clone(), second.clone()]);
+
+        let spend = |input: &Commitment, fee: u64, blinding: u8| {
+            let change = output(input.value - fee, blinding);
+            balanced_tx(vec![input.clone()], vec![change], fee)
+        };
+        let valid = spend(&first, 10, 3);
+        let unknown_input = spend(&output(700, 9), 10, 4);
+        let spends_earlier_output = spend(&valid.outputs()[0], 20, 5);
+        let also_valid = spend(&second, 5, 6);
+        let coinbase = Transaction::coinbase(0, 0, &[7; 32], &state.parameters())?;
+
+        let rejected = state.add_transactions(&[
+            valid.clone(),
+            unknown_input,
+            spends_earlier_output.clone(),
+            coinbase,
+            also_valid.clone(),
+        ])?;
+        assert_eq!(rejected, vec![1, 3]);
+
+        assert_eq!(state.kernel_set_size(), 3);
+        let utxo_set = state.snapshot().utxo_set;
+        assert_eq!(
+            utxo_set,
+            vec![
+                spends_earlier_output.outputs()[0].clone(),
+                also_valid.outputs()[0].clone()
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_swapped_range_proofs_are_rejected() -> Result<()> {
+        let funding = output(1000, 1);
+        let state = state_with_outputs(std::slice::from_ref(&funding));
+
+        let mut tx = balanced_tx(vec![funding], vec![output(600, 2), output(390, 3)], 10);
+        assert!(state.verify_transaction(&tx)?);
+
+        let first = tx.outputs()[0].range_proof.clone();
+        tx.outputs_mut()[0].range_proof = tx.outputs()[1].range_proof.clone();
+        tx.outputs_mut()[1].range_proof = first;
+        assert!(matches!(
+            state.verify_transaction(&tx),
+            Err(MimblewimbleError::ProofVerificationFailed)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_block_template_mines_into_valid_block() -> Result<()> {
+        let funding = output(1000, 1);
+        let state = state_with_outputs(std::slice::from_ref(&funding));
+
+        let tx = balanced_tx(vec![funding], vec![output(990, 2)], 10);
+        state.mempool().add(tx.clone(), &state)?;
+
+        let template = state.block_template(&[3; 32])?;
+        assert_eq!(template.transactions.len(), 2);
+        assert_eq!(template.header.height, 0);
+        assert_eq!(
+            template.header.difficulty,
+            state.parameters().initial_difficulty
+        );
+
+        let coinbase = &template.transactions[0];
+        assert!(coinbase.inputs().is_empty());
+        assert!(coinbase.is_coinbase());
+        assert_eq!(
+            coinbase.outputs()[0].value,
+            state.parameters().block_reward(0) + tx.fee()
+        );
+
+        let mut header = template.header.clone();
+        pow::mine(&mut header, &state.parameters())?;
+        let block = template.into_block(header.nonce);
+
+        assert!(pow::validate_pow(&block.header, &state.parameters()));
+        assert_eq!(
+            block.header.merkle_root,
+            block::compute_merkle_root(&block.transactions)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_coinbase_with_inputs_is_rejected() -> Result<()> {
+        let funding = output(1000, 1);
+        let state = MimblewimbleState {
+            parameters: Arc::new(MimblewimbleParameters::regtest()),
+            ..state_with_outputs(std::slice::from_ref(&funding))
+        };
+
+        let valid = state.block_template(&[3; 32])?.into_block(0);
+        assert!(valid.transactions[0].inputs().is_empty());
+
+        let mut block = valid.clone();
+        block.transactions[0].inputs_mut().push(funding);
+        block.header.merkle_root = block::compute_merkle_root(&block.transactions)?;
+        block.header.output_root = state
+            .chain
+            .read()
+            .projected_output_root(&block.transactions);
+        assert!(matches!(
+            state.accept_block(block),
+            Err(MimblewimbleError::InvalidTransaction)
+        ));
+
+        state.accept_block(valid)?;
+        assert_eq!(state.block_height(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_expected_coinbase_value() -> Result<()> {
+        let funding = output(1000, 1);
+        let state = MimblewimbleState {
+            parameters: Arc::new(MimblewimbleParameters::regtest()),
+            ..state_with_outputs(std::slice::from_ref(&funding))
+        };
+        let params = state.parameters();
+        let spend = balanced_tx_on(&params, vec![funding], vec![output(990, 2)], 10);
+        state.mempool().add(spend.clone(), &state)?;
+
+        // Mining and validation agree on the coinbase value
+        let block = state.block_template(&[3; 32])?.into_block(0);
+        let expected = block.expected_coinbase_value(&params)?;
+        assert_eq!(expected, params.block_reward(0) + 10);
+        assert_eq!(block.transactions[0].outputs()[0].value, expected);
+        assert!(block.has_valid_coinbase(&params)?);
+
+        // A coinbase claiming one unit more is rejected
+        let greedy = Transaction::coinbase(0, 11, &[3; 32], &params)?;
+        let wrong = state.create_block(vec![greedy, spend])?;
+        assert!(!wrong.has_valid_coinbase(&params)?);
+        assert!(matches!(
+            state.accept_block(wrong),
+            Err(MimblewimbleError::InvalidBlock)
+        ));
+
+        state.accept_block(block)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_coinbase_passes_block_validation() -> Result<()> {
+        let state = MimblewimbleState::new(MimblewimbleParameters::regtest())?;
+        for key in 1..=2u8 {
+            state.accept_block(state.block_template(&[key; 32])?.into_block(0))?;
+        }
+
+        let params = state.parameters();
+        let coinbase = Transaction::coinbase(2, 0, &[7; 32], &params)?;
+        assert!(coinbase.inputs().is_empty());
+        assert_eq!(coinbase.outputs()[0].value, params.block_reward(2));
+        coinbase.verify_range_proofs(&params)?;
+        coinbase.verify_kernels(&params)?;
+
+        let mut block = state.block_template(&[9; 32])?.into_block(0);
+        block.transactions = vec![coinbase];
+        {
+            let chain = state.chain.read();
+            block.header.merkle_root = block::compute_merkle_root(&block.transactions)?;
+            block.header.output_root = chain.projected_output_root(&block.transactions);
+            block.header.kernel_root = chain.projected_kernel_root(&block.transactions);
+        }
+        state.accept_block(block)?;
+        assert_eq!(state.block_height(), 3);
+        Ok(())
+    }
+
+    fn mined_block(state: &MimblewimbleState, recipient_key: &[u8]) -> Result<Block> {
+        let template = state.block_template(recipient_key)?;
+        let mut header = template.header.clone();
+        pow::mine(&mut header, &state.parameters())?;
+        Ok(template.into_block(header.nonce))
+    }
+
+    #[test]
+    fn test_submit_mined_block() -> Result<()> {
+        let state = MimblewimbleState::new(MimblewimbleParameters::default())?;
+        let block = mined_block(&state, &[3; 32])?;
+        let hash = block.hash()?;
+
+        assert_eq!(
+            state.submit_block(block.clone())?,
+            SubmitResult::Accepted { height: 0, hash }
+        );
+        assert_eq!(state.block_height(), 1);
+        assert_eq!(state.utxo_set_size(), 1);
+        assert_eq!(state.kernel_set_size(), 1);
+        state.verify_kernel_log()?;
+
+        assert_eq!(state.submit_block(block)?, SubmitResult::Duplicate);
+        Ok(())
+    }
+
+    #[test]
+    fn test_submit_block_below_target() -> Result<()> {
+        let state = MimblewimbleState::new(MimblewimbleParameters::default())?;
+        let template = state.block_template(&[3; 32])?;
+
+        let nonce = (0..)
+            .find(|nonce| {
+                let header = BlockHeader {
+                    nonce: *nonce,
+                    ..template.header.clone()
+                };
+                !pow::validate_pow(&header, &state.parameters())
+            })
+            .unwrap();
+
+        assert!(matches!(
+            state.submit_block(template.into_block(nonce)),
+            Err(MimblewimbleError::InvalidProofOfWork)
+        ));
+        assert_eq!(state.block_height(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_submit_stale_block() -> Result<()> {
+        let state = MimblewimbleState::new(MimblewimbleParameters::default())?;
+        let first = mined_block(&state, &[3; 32])?;
+        let second = mined_block(&state, &[4; 32])?;
+
+        state.submit_block(first)?;
+        assert!(matches!(
+            state.submit_block(second),
+            Err(MimblewimbleError::StaleBlock)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_kernel_set_hash_is_order_independent() -> Result<()> {
+        let txs: Vec<Transaction> = (1..=3)
+            .map(|n| balanced_tx(vec![], vec![output(0, n)], 0))
+            .collect();
+
+        let forward = MimblewimbleState::new(MimblewimbleParameters::default())?;
+        let backward = MimblewimbleState::new(MimblewimbleParameters::default())?;
+        let empty_hash = forward.kernel_set_hash();
+
+        for tx in &txs {
+            forward.add_transaction(tx)?;
+        }
+        for tx in txs.iter().rev() {
+            backward.add_transaction(tx)?;
+        }
+
+        assert_eq!(forward.kernel_set_hash(), backward.kernel_set_hash());
+        assert_ne!(forward.kernel_set_hash(), empty_hash);
+        Ok(())
+    }
+
+    #[test]
+    fn test_intra_block_spend_ordering() -> Result<()> {
+        let funding = output(1000, 1);
+        let intermediate = output(990, 2);
+
+        let params = MimblewimbleParameters::regtest();
+        let first = balanced_tx_on(
+            &params,
+            vec![funding.clone()],
+            vec![intermediate.clone()],
+            10,
+        );
+        let second = balanced_tx_on(&params, vec![intermediate], vec![output(980, 3)], 10);
+
+        // Spending an output created later in the block is rejected
+        let state = MimblewimbleState {
+            parameters: Arc::new(params),
+            ..state_with_outputs(std::slice::from_ref(&funding))
+        };
+        let reversed = state.create_block(vec![second.clone(), first.clone()])?;
+        assert!(matches!(
+            state.accept_block(reversed),
+            Err(MimblewimbleError::InvalidTransaction)
+        ));
+        assert_eq!(state.block_height(), 0);
+        assert_eq!(state.utxo_set_size(), 1);
+
+        // Spending an output created earlier in the block is accepted
+        let ordered = state.create_block(vec![first, second])?;
+        state.accept_block(ordered)?;
+        assert_eq!(state.block_height(), 1);
+        assert_eq!(state.utxo_set_size(), 1);
+        assert_eq!(state.chain.read().utxo_set[0].value, 980);
+        Ok(())
+    }
+
+    #[test]
+    fn test_utxo_delta_round_trip() -> Result<()> {
+        let miner = MimblewimbleState::new(MimblewimbleParameters::regtest())?;
+        let params = miner.parameters();
+        miner.submit_block(miner.block_template(&[1; 32])?.into_block(0))?;
+        let coinbase = miner.outputs_at_height(0).unwrap().added[0].clone();
+        let spend = balanced_tx_on(
+            &params,
+            vec![coinbase.clone()],
+            vec![output(coinbase.value - 10, 5)],
+            10,
+        );
+        miner.mempool().add(spend, &miner)?;
+        miner.submit_block(miner.block_template(&[2; 32])?.into_block(0))?;
+        let (first, second) = (
+            miner.outputs_at_height(0).unwrap(),
+            miner.outputs_at_height(1).unwrap(),
+        );
+
+        // Locked to two blocks after the coinbase was created
+        let excess =
+            crypto::scalar_from_bytes(&[6; 32])? - crypto::scalar_from_bytes(&coinbase.blinding)?;
+        let mut kernel = Kernel::new(KernelFeatures::CombinedLocked, 10, 0, vec![], vec![])
+            .with_relative_lock(coinbase.commitment.clone(), 2);
+        kernel.sign(&crypto::scalar_to_bytes(&excess), &params)?;
+        let locked = Transaction::new(
+            1,
+            vec![coinbase.clone()],
+            vec![output(coinbase.value - 10, 6)],
+            kernel,
+            10,
+        );
+
+        // A node following by deltas matches the miner's state
+        let follower = MimblewimbleState::new(MimblewimbleParameters::regtest())?;
+        let (root, kernel_set_hash) = (follower.utxo_root(), follower.kernel_set_hash());
+        let removed_first = follower.apply_delta(&first)?;
+        assert!(removed_first.is_empty());
+        assert_eq!(follower.maturity_height(&locked), Some(2));
+        follower.verify_no_inflation(follower.supply())?;
+
+        let removed_second = follower.apply_delta(&second)?;
+        assert_eq!(removed_second[0].created_at, Some(0));
+        assert_eq!(follower.utxo_root(), miner.utxo_root());
+        assert_eq!(follower.kernel_set_hash(), miner.kernel_set_hash());
+        assert_eq!(follower.supply(), miner.supply());
+        follower.verify_no_inflation(miner.supply())?;
+        follower.verify_total_excess(miner.supply())?;
+        assert_eq!(follower.maturity_height(&locked), None);
+
+        // Applying twice fails: the removed output is gone
+        assert!(follower.apply_delta(&second).is_err());
+
+        // Reverting restores the spent output with its creation height
+        follower.revert_delta(&second, &removed_second)?;
+        assert_eq!(follower.maturity_height(&locked), Some(2));
+        follower.revert_delta(&first, &removed_first)?;
+        assert_eq!(follower.utxo_root(), root);
+        assert_eq!(follower.kernel_set_hash(), kernel_set_hash);
+        assert_eq!(follower.supply(), 0);
+        follower.verify_no_inflation(0)?;
+
+        // Outputs are checked like block outputs, and must balance
+        let mut forged = first.clone();
+        forged.added[0].range_proof.proof_data.truncate(4);
+        assert!(matches!(
+            follower.apply_delta(&forged),
+            Err(MimblewimbleError::InvalidRangeProof(0))
+        ));
+        let mut forged = first.clone();
+        forged.kernels.clear();
+        assert!(matches!(
+            follower.apply_delta(&forged),
+            Err(MimblewimbleError::BalanceMismatch)
+        ));
+        assert_eq!(follower.utxo_set_size(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_verification_cache_skips_mempool_transactions() -> Result<()> {
+        let clock = Arc::new(ManualTimeSource::new(1_700_000_000));
+        let state = MimblewimbleState::new(MimblewimbleParameters::regtest())?
+            .with_time_source(clock.clone());
+        let params = state.parameters();
+        state.accept_block(state.block_template(&[1; 32])?.into_block(0))?;
+        clock.advance(1);
+
+        let coinbase = state.outputs_at_height(0).unwrap().added[0].clone();
+        let tx = TransactionBuilder::new(&params)
+            .input(coinbase.clone())
+            .add_recipient(coinbase.value - 10, &[2; 32])
+            .fee(10)
+            .build()?;
+        state.mempool().add(tx, &state)?;
+        let block = state.block_template(&[3; 32])?.into_block(0);
+        let cache = state.verification_cache.as_ref().unwrap();
+
+        // Only the coinbase, unseen by the mempool, is verified again
+        let hits = cache.hits();
+        state.verify_block(&block)?;
+        assert_eq!(cache.hits(), hits + 1);
+
+        // A relayed variant with another signature is not vouched for
+        let mut forged = block.clone();
+        forged.transactions[1].kernels_mut()[0].signature[crypto::POINT_LENGTH] ^= 1;
+        forged.header.merkle_root = block::compute_merkle_root(&forged.transactions)?;
+        assert!(matches!(
+            state.verify_block(&forged),
+            Err(MimblewimbleError::InvalidKernelSignature(1))
+        ));
+
+        // Rewinding the chain empties the cache, and the block still verifies
+        let tx = balanced_tx_on(&params, vec![], vec![output(0, 4)], 0);
+        let delta = UtxoDelta {
+            height: 1,
+            added: tx.outputs().to_vec(),
+            removed: vec![],
+            kernels: tx.kernels().to_vec(),
+            offset: tx.offset().to_vec(),
+        };
+        let removed = state.apply_delta(&delta)?;
+        state.revert_delta(&delta, &removed)?;
+        assert!(cache.is_empty());
+        let hits = cache.hits();
+        state.verify_block(&block)?;
+        assert_eq!(cache.hits(), hits);
+        Ok(())
+    }
+
+    #[test]
+    fn test_output_root_at_height() -> Result<()> {
+        let state = MimblewimbleState::new(MimblewimbleParameters::regtest())?;
+        assert_eq!(state.output_root_at(0), None);
+
+        let mut roots = Vec::new();
+        for key in 1..=3u8 {
+            state.accept_block(state.block_template(&[key; 32])?.into_block(0))?;
+            roots.push(state.utxo_root());
+        }
+
+        for (height, root) in roots.iter().enumerate() {
+            assert_eq!(state.output_root_at(height as u64).as_ref(), Some(root));
+        }
+        assert_ne!(roots[0], roots[2]);
+        assert_eq!(state.output_root_at(3), None);
+
+        // A proof taken at height 1 still verifies against that height's root
+        let first = state.get_block(0).unwrap().transactions[0].outputs()[0].clone();
+        let proof = block::output_mmr(&[
+            first.commitment.clone(),
+            state.get_block(1).unwrap().transactions[0].outputs()[0]
+                .commitment
+                .clone(),
+        ])
+        .prove_membership(&first.commitment)
+        .unwrap();
+        assert!(mmr::verify_membership(&proof, &roots[1]));
+        assert!(!mmr::verify_membership(&proof, &roots[2]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_utxo_membership_proofs() -> Result<()> {
+        let (a, b, c) = (output(100, 1), output(200, 2), output(300, 3));
+        let state = state_with_outputs(&[a.clone(), b.clone(), c]);
+        let root = state.utxo_root();
+
+        let proof = state.prove_membership(&b.commitment)?;
+        assert!(mmr::verify_membership(&proof, &root));
+
+        // A light client checks a transaction's inputs against the root alone
+        let kernel = Kernel::new(KernelFeatures::Plain, 0, 0, vec![1; 32], vec![2; 64]);
+        let tx = Transaction::new(1, vec![a.clone(), b], vec![], kernel, 0);
+        let proofs = vec![state.prove_membership(&a.commitment)?, proof];
+        assert!(tx.verify_input_proofs(&proofs, &root));
+        assert!(!tx.verify_input_proofs(&proofs[..1], &root));
+
+        assert!(matches!(
+            state.prove_membership(&output(400, 4).commitment),
+            Err(MimblewimbleError::UtxoNotFound)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_regtest_accepts_nonce_zero() -> Result<()> {
+        let state = MimblewimbleState::new(MimblewimbleParameters::regtest())?;
+        let block = state.block_template(&[3; 32])?.into_block(0);
+
+        assert!(matches!(
+            state.submit_block(block)?,
+            SubmitResult::Accepted { height: 0, .. }
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_partial_block_download() -> Result<()> {
+        let funding: Vec<Commitment> = (1..=3).map(|n| output(1000, n)).collect();
+        let state = state_with_outputs(&funding);
+        for (n, input) in funding.iter().enumerate() {
+            let change = output(990, 10 + n as u8);
+            state
+                .mempool()
+                .add(balanced_tx(vec![input.clone()], vec![change], 10), &state)?;
+        }
+
+        let block = mined_block(&state, &[3; 32])?;
+        state.submit_block(block.clone())?;
+        assert_eq!(block.transaction_count(), 4);
+
+        let hash = block.hash()?;
+        let mut partial = net::PartialBlock::new(block.header.clone(), 4, &state.parameters())?;
+        for (start, count) in [(0, 2), (2, 2)] {
+            let request = NetMessage::GetBlockTxs {
+                block_hash: hash.clone(),
+                start,
+                count,
+            };
+            let (block_hash, start, count) = match NetMessage::decode(&request.encode()?)? {
+                NetMessage::GetBlockTxs {
+                    block_hash,
+                    start,
+                    count,
+                } => (block_hash, start, count),
+                other => panic!("unexpected message: {:?}", other),
+            };
+
+            let response = state.serve_block_txs(&block_hash, start, count)?;
+            match NetMessage::decode(&response.encode()?)? {
+                NetMessage::BlockTxs {
+                    block_hash,
+                    start,
+                    total,
+                    transactions,
+                } => {
+                    assert_eq!(total, 4);
+                    assert!(!partial.is_complete());
+                    // Ranges of another block are not mixed in
+                    assert!(matches!(
+                        partial.add_range(&[7; 128], start as usize, transactions.clone()),
+                        Err(MimblewimbleError::InvalidParameter)
+                    ));
+                    partial.add_range(&block_hash, start as usize, transactions)?;
+                }
+                other => panic!("unexpected message: {:?}", other),
+            }
+        }
+
+        assert!(partial.is_complete());
+        let reassembled = partial.finish()?;
+        assert_eq!(
+            block::compute_merkle_root(&reassembled.transactions)?,
+            block.header.merkle_root
+        );
+        assert_eq!(reassembled.hash()?, hash);
+        Ok(())
+    }
+
+    #[test]
+    fn test_snapshot_during_block_application() -> Result<()> {
+        let state = MimblewimbleState::new(MimblewimbleParameters::regtest())?;
+        let blocks = 20;
+
+        // Coinbase-only blocks add exactly one output and one kernel each
+        let miner = {
+            let state = state.clone();
+            std::thread::spawn(move || -> Result<()> {
+                for n in 0..blocks {
+                    let block = state.block_template(&[n as u8 + 1; 32])?.into_block(0);
+                    state.submit_block(block)?;
+                }
+                Ok(())
+            })
+        };
+
+        loop {
+            let snapshot = state.snapshot();
+            assert_eq!(snapshot.utxo_set.len() as u64, snapshot.height);
+            assert_eq!(snapshot.kernel_set.len() as u64, snapshot.height);
+            if snapshot.height == blocks || miner.is_finished() {
+                break;
+            }
+        }
+
+        miner.join().unwrap()?;
+        assert_eq!(state.snapshot().height, blocks);
+        Ok(())
+    }
+
+    #[test]
+    fn test_concurrent_readers_see_whole_blocks() -> Result<()> {
+        let state = MimblewimbleState::new(MimblewimbleParameters::regtest())?;
+        let blocks = 10;
+
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let state = state.clone();
+                std::thread::spawn(move || loop {
+                    let snapshot = state.snapshot();
+
+                    // The UTXO set is exactly the coinbase outputs of the
+                    // blocks counted by the height
+                    let expected: Vec<Commitment> = (0..snapshot.height)
+                        .flat_map(|height| state.get_block(height).unwrap().transactions)
+                        .flat_map(|tx| tx.outputs().to_vec())
+                        .collect();
+                    assert_eq!(snapshot.utxo_set, expected);
+
+                    if snapshot.height == blocks {
+                        break;
+                    }
+                })
+            })
+            .collect();
+
+        for n in 0..blocks {
+            let block = state.block_template(&[n as u8 + 1; 32])?.into_block(0);
+            state.submit_block(block)?;
+        }
+
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_block_version_gating() -> Result<()> {
+        let state = MimblewimbleState::new(MimblewimbleParameters::regtest())?;
+
+        let mut future = state.block_template(&[1; 32])?.into_block(0);
+        future.header.version = MIMBLEWIMBLE_VERSION + 1;
+        assert!(matches!(
+            state.accept_block(future),
+            Err(MimblewimbleError::UnsupportedVersion(_))
+        ));
+
+        state.accept_block(state.block_template(&[1; 32])?.into_block(0))?;
+        assert_eq!(state.block_height(), 1);
+
+        // A version below the tip's is a downgrade
+        let mut older = state.block_template(&[2; 32])?.into_block(0);
+        older.header.version = MIMBLEWIMBLE_VERSION - 1;
+        assert!(matches!(
+            state.accept_block(older),
+            Err(MimblewimbleError::UnsupportedVersion(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_block() -> Result<()> {
+        let state = MimblewimbleState::new(MimblewimbleParameters::regtest())?;
+        let params = state.parameters();
+        let template =
+            |key: u8| -> Result<Block> { Ok(state.block_template(&[key; 32])?.into_block(0)) };
+        for key in 1..=3u8 {
+            state.submit_block(template(key)?)?;
+        }
+        for height in 0..3 {
+            let coinbase = state.outputs_at_height(height).unwrap().added[0].clone();
+            let change = output(coinbase.value - 10, 10 + height as u8);
+            state.mempool().add(
+                balanced_tx_on(&params, vec![coinbase], vec![change], 10),
+                &state,
+            )?;
+        }
+
+        let block = template(4)?;
+        assert_eq!(block.transactions.len(), 4);
+        state.verify_block(&block)?;
+
+        let mut stale = block.clone();
+        stale.header.height += 1;
+        assert!(matches!(
+            state.verify_block(&stale),
+            Err(MimblewimbleError::StaleBlock)
+        ));
+
+        // Tampered transactions change the merkle root; fix it up to reach
+        // the transaction checks
+        let tampered = |edit: &dyn Fn(&mut Block)| -> Result<Block> {
+            let mut block = block.clone();
+            edit(&mut block);
+            block.header.merkle_root = block::compute_merkle_root(&block.transactions)?;
+            Ok(block)
+        };
+        let bad_kernel = tampered(&|block| {
+            let signature = &mut block.transactions[1].kernels_mut()[0].signature;
+            signature[crypto::POINT_LENGTH + 1] ^= 1;
+        })?;
+        assert!(matches!(
+            state.verify_block(&bad_kernel),
+            Err(MimblewimbleError::InvalidKernelSignature(1))
+        ));
+        let bad_proof = tampered(&|block| {
+            block.transactions[2].outputs_mut()[0].range_proof = RangeProof::new(vec![0; 4]);
+        })?;
+        assert!(matches!(
+            state.verify_block(&bad_proof),
+            Err(MimblewimbleError::InvalidRangeProof(2))
+        ));
+
+        // Verification leaves the state untouched, and the block still applies
+        assert_eq!(state.block_height(), 3);
+        state.submit_block(block)?;
+        assert_eq!(state.block_height(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_block_size_limit_uses_wire_size() -> Result<()> {
+        let block = MimblewimbleState::new(MimblewimbleParameters::regtest())?
+            .block_template(&[1; 32])?
+            .into_block(0);
+        let size = block.wire_size()?;
+        assert_eq!(size, serialization::to_vec(&block)?.len());
+
+        let limited = |max_block_size: usize| {
+            MimblewimbleState::new(MimblewimbleParameters {
+                max_transaction_size: max_block_size,
+                max_block_size,
+                ..MimblewimbleParameters::regtest()
+            })
+        };
+        assert!(matches!(
+            limited(size - 1)?.accept_block(block.clone()),
+            Err(MimblewimbleError::InvalidBlock)
+        ));
+        limited(size)?.accept_block(block)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_wrong_length_hashes_are_rejected() -> Result<()> {
+        let state = MimblewimbleState::new(MimblewimbleParameters::regtest())?;
+        state.accept_block(state.block_template(&[1; 32])?.into_block(0))?;
+
+        let block = state.block_template(&[2; 32])?.into_block(0);
+        assert_eq!(block.header.previous_hash.len(), block::HASH_LENGTH);
+        for length in [5, block::HASH_LENGTH - 1, 500] {
+            let mut wrong = block.clone();
+            wrong.header.previous_hash.resize(length, 0);
+            assert!(matches!(
+                state.accept_block(wrong.clone()),
+                Err(MimblewimbleError::InvalidBlock)
+            ));
+            assert!(matches!(
+                state.verify_block(&wrong),
+                Err(MimblewimbleError::InvalidBlock)
+            ));
+
+            let mut wrong = block.clone();
+            wrong.header.merkle_root.resize(length, 0);
+            assert!(matches!(
+                state.accept_block(wrong),
+                Err(MimblewimbleError::InvalidBlock)
+            ));
+        }
+
+        state.accept_block(block)?;
+        assert_eq!(state.block_height(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_block_with_wrong_kernel_root_is_rejected() -> Result<()> {
+        let state = MimblewimbleState::new(MimblewimbleParameters::regtest())?;
+        state.accept_block(state.block_template(&[1; 32])?.into_block(0))?;
+
+        let block = state.block_template(&[2; 32])?.into_block(0);
+        let mut wrong = block.clone();
+        wrong.header.kernel_root = state.chain.read().kernel_mmr.root();
+        assert!(matches!(
+            state.accept_block(wrong),
+            Err(MimblewimbleError::InvalidBlock)
+        ));
+
+        state.accept_block(block)?;
+        assert_eq!(state.block_height(), 2);
+        assert_eq!(
+            state.chain.read().kernel_mmr.root(),
+            state.get_block(1).unwrap().header.kernel_root
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_block_with_wrong_merkle_root_is_rejected() -> Result<()> {
+        let state = MimblewimbleState::new(MimblewimbleParameters::regtest())?;
+        state.accept_block(state.block_template(&[1; 32])?.into_block(0))?;
+
+        // Swap in another coinbase, keeping every root but the merkle root valid
+        let block = state.block_template(&[2; 32])?.into_block(0);
+        let mut wrong = block.clone();
+        wrong.transactions = state.block_template(&[3; 32])?.transactions;
+        wrong.header.kernel_root = state
+            .chain
+            .read()
+            .projected_kernel_root(&wrong.transactions);
+        wrong.header.output_root = state
+            .chain
+            .read()
+            .projected_output_root(&wrong.transactions);
+        assert!(matches!(
+            state.verify_block(&wrong),
+            Err(MimblewimbleError::InvalidBlock)
+        ));
+        assert!(matches!(
+            state.accept_block(wrong.clone()),
+            Err(MimblewimbleError::InvalidBlock)
+        ));
+
+        // The same transactions pass once the header commits to them
+        wrong.header.merkle_root = block::compute_merkle_root(&wrong.transactions)?;
+        state.accept_block(wrong)?;
+        assert_eq!(state.block_height(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_block_requires_canonical_order() -> Result<()> {
+        let funding = output(1000, 1);
+        let state = MimblewimbleState {
+            parameters: Arc::new(MimblewimbleParameters::regtest()),
+            ..state_with_outputs(std::slice::from_ref(&funding))
+        };
+
+        let mut tx = balanced_tx_on(
+            &state.parameters(),
+            vec![funding],
+            vec![output(600, 2), output(390, 3)],
+            10,
+        );
+        tx.sort_canonical();
+        tx.outputs_mut().reverse();
+        assert!(!tx.is_canonical());
+        state.mempool().insert_unchecked(tx);
+
+        let mut block = state.block_template(&[4; 32])?.into_block(0);
+        assert!(matches!(
+            state.accept_block(block.clone()),
+            Err(MimblewimbleError::InvalidBlock)
+        ));
+
+        block.transactions[1].sort_canonical();
+        block.header.merkle_root = block::compute_merkle_root(&block.transactions)?;
+        state.accept_block(block)?;
+        assert_eq!(state.block_height(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_accept_block_applies_verify_block_rules() -> Result<()> {
+        let state = MimblewimbleState::new(MimblewimbleParameters::regtest())?;
+        let params = state.parameters();
+
+        // A timestamp beyond the allowed drift
+        let mut future = state.block_template(&[1; 32])?.into_block(0);
+        future.header.timestamp += params.max_future_drift + 60;
+        assert!(state.verify_block(&future).is_err());
+        assert!(state.accept_block(future).is_err());
+
+        // A second coinbase minting another reward
+        let mut block = state.block_template(&[1; 32])?.into_block(0);
+        block
+            .transactions
+            .push(Transaction::coinbase(0, 0, &[2; 32], &params)?);
+        block.header.merkle_root = block::compute_merkle_root(&block.transactions)?;
+        block.header.kernel_root = state
+            .chain
+            .read()
+            .projected_kernel_root(&block.transactions);
+        block.header.output_root = state
+            .chain
+            .read()
+            .projected_output_root(&block.transactions);
+        assert!(matches!(
+            state.verify_block(&block),
+            Err(MimblewimbleError::InvalidBlock)
+        ));
+        assert!(matches!(
+            state.accept_block(block),
+            Err(MimblewimbleError::InvalidBlock)
+        ));
+
+        assert_eq!(state.block_height(), 0);
+        state.accept_block(state.block_template(&[1; 32])?.into_block(0))?;
+        assert_eq!(state.block_height(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_duplicate_inputs_are_rejected() -> Result<()> {
+        let funding = output(1000, 1);
+        let state = MimblewimbleState {
+            parameters: Arc::new(MimblewimbleParameters::regtest()),
+            ..state_with_outputs(std::slice::from_ref(&funding))
+        };
+
+        // Spending the same output twice would balance against twice its value
+        let tx = balanced_tx_on(
+            &state.parameters(),
+            vec![funding.clone(), funding],
+            vec![output(1990, 3)],
+            10,
+        );
+        assert!(tx.is_balanced());
+        assert!(!tx.has_sorted_inputs());
+        assert!(matches!(
+            state.verify_transaction(&tx),
+            Err(MimblewimbleError::InvalidTransaction)
+        ));
+        state.mempool().insert_unchecked(tx);
+
+        let block = state.block_template(&[4; 32])?.into_block(0);
+        assert!(matches!(
+            state.verify_block(&block),
+            Err(MimblewimbleError::InvalidBlock | MimblewimbleError::InvalidTransaction)
+        ));
+        assert!(matches!(
+            state.accept_block(block),
+            Err(MimblewimbleError::InvalidBlock | MimblewimbleError::InvalidTransaction)
+        ));
+        assert_eq!(state.block_height(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_unsorted_inputs_are_rejected() -> Result<()> {
+        let funding = [output(600, 1), output(400, 2)];
+        let state = state_with_outputs(&funding);
+
+        let mut tx = balanced_tx(funding.to_vec(), vec![output(990, 5)], 10);
+        tx.sort_canonical();
+        tx.inputs_mut().reverse();
+        assert!(matches!(
+            state.verify_transaction(&tx),
+            Err(MimblewimbleError::InvalidTransaction)
+        ));
+
+        tx.sort_canonical();
+        assert!(state.verify_transaction(&tx)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_manual_time_source() -> Result<()> {
+        let clock = Arc::new(ManualTimeSource::new(1_700_000_000));
+        let state = MimblewimbleState::new(MimblewimbleParameters::regtest())?
+            .with_time_source(clock.clone());
+        let drift = state.parameters().max_future_drift;
+
+        let block = state.create_block(vec![])?;
+        assert_eq!(block.header.timestamp, 1_700_000_000);
+        assert_eq!(state.valid_timestamp_range()?.1, 1_700_000_000 + drift);
+        state.verify_block(&block)?;
+        state.accept_block(block)?;
+
+        clock.advance(60);
+        let template = state.block_template(&[1; 32])?;
+        assert_eq!(template.header.timestamp, 1_700_000_060);
+
+        // A block from past the allowed drift is rejected until the clock
+        // catches up
+        let mut early = template.into_block(0);
+        early.header.timestamp = 1_700_000_060 + drift + 1;
+        assert!(state.verify_block(&early).is_err());
+        clock.set(1_700_000_061);
+        state.verify_block(&early)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_submit_block_enforces_timestamp_rules() -> Result<()> {
+        let clock = Arc::new(ManualTimeSource::new(1_700_000_000));
+        let state = MimblewimbleState::new(MimblewimbleParameters::regtest())?
+            .with_time_source(clock.clone());
+        let drift = state.parameters().max_future_drift;
+        state.submit_block(state.block_template(&[1; 32])?.into_block(0))?;
+
+        // A block no later than the median time past is rejected
+        let mut stale = state.block_template(&[2; 32])?.into_block(0);
+        stale.header.timestamp = 1_700_000_000;
+        assert!(matches!(
+            state.submit_block(stale),
+            Err(MimblewimbleError::InvalidBlock)
+        ));
+
+        // So is one from beyond the allowed drift
+        let mut early = state.block_template(&[2; 32])?.into_block(0);
+        early.header.timestamp = 1_700_000_000 + drift + 1;
+        assert!(matches!(
+            state.submit_block(early),
+            Err(MimblewimbleError::InvalidBlock)
+        ));
+        assert_eq!(state.block_height(), 1);
+
+        // Blocks accepted by submit_block always form a valid header chain
+        let template = state.block_template(&[2; 32])?;
+        assert_eq!(template.header.timestamp, 1_700_000_001);
+        state.submit_block(template.into_block(0))?;
+        block::verify_header_chain(&state.export_headers(0, 2)?, &state.parameters())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_valid_timestamp_range() -> Result<()> {
+        let state = MimblewimbleState::new(MimblewimbleParameters::regtest())?;
+        let drift = state.parameters().max_future_drift;
+
+        let before = SystemTimeSource.now()?;
+        let (min, max) = state.valid_timestamp_range()?;
+        assert_eq!(min, 0);
+        assert!(max >= before + drift && max <= SystemTimeSource.now()? + drift);
+
+        // Out-of-order timestamps 10, 50, 60, 55, 58 have median 55
+        for timestamp in [10, 50, 60, 55, 58] {
+            let mut block = state.block_template(&[timestamp as u8; 32])?.into_block(0);
+            block.header.timestamp = timestamp;
+            state.accept_block(block)?;
+        }
+        assert_eq!(state.valid_timestamp_range()?.0, 56);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_progress() -> Result<()> {
+        let state = MimblewimbleState::new(MimblewimbleParameters::regtest())?;
+        assert!(state.is_synced(0));
+        assert_eq!(state.sync_progress(0), 1.0);
+        assert!(!state.is_synced(4));
+        assert_eq!(state.sync_progress(4), 0.0);
+
+        for key in 1..=2u8 {
+            state.accept_block(state.block_template(&[key; 32])?.into_block(0))?;
+        }
+        assert!(!state.is_synced(4));
+        assert_eq!(state.sync_progress(4), 0.5);
+        assert!(state.is_synced(2));
+        assert_eq!(state.sync_progress(2), 1.0);
+
+        // A peer behind us does not push progress past complete
+        assert!(state.is_synced(1));
+        assert_eq!(state.sync_progress(1), 1.0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_anchored_transactions() -> Result<()> {
+        let funding = output(1000, 1);
+        let state = MimblewimbleState {
+            parameters: Arc::new(MimblewimbleParameters {
+                anchor_window: 2,
+                ..MimblewimbleParameters::regtest()
+            }),
+            ..state_with_outputs(std::slice::from_ref(&funding))
+        };
+        state.accept_block(state.block_template(&[2; 32])?.into_block(0))?;
+        let tip = state.chain.read().tip_hash()?;
+
+        let change = output(990, 3);
+        let excess = crypto::scalar_from_bytes(&change.blinding)?
+            - crypto::scalar_from_bytes(&funding.blinding)?;
+        let anchored = |anchor: Vec<u8>| -> Result<Transaction> {
+            let mut kernel =
+                Kernel::new(KernelFeatures::Plain, 10, 0, vec![], vec![]).with_anchor(anchor);
+            kernel.sign(&crypto::scalar_to_bytes(&excess), &state.parameters())?;
+            Ok(Transaction::new(
+                1,
+                vec![funding.clone()],
+                vec![change.clone()],
+                kernel,
+                10,
+            ))
+        };
+
+        let tx = anchored(vec![7; 128])?;
+        assert_eq!(tx.anchor(), Some(&[7; 128][..]));
+        assert!(!state.verify_transaction(&tx)?);
+
+        let mut tx = anchored(tip.clone())?;
+        assert!(state.verify_transaction(&tx)?);
+
+        // The anchor is signed, so a relay cannot strip or rewrite it
+        tx.kernels_mut()[0].anchor = None;
+        assert!(!state.verify_transaction(&tx)?);
+
+        // Anchors expire once the block falls out of the window
+        let tx = anchored(tip)?;
+        state.accept_block(state.block_template(&[4; 32])?.into_block(0))?;
+        assert!(state.verify_transaction(&tx)?);
+        state.accept_block(state.block_template(&[5; 32])?.into_block(0))?;
+        assert!(!state.verify_transaction(&tx)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_metrics_reflect_state() -> Result<()> {
+        let state = MimblewimbleState::new(MimblewimbleParameters::regtest())?;
+        let empty = state.metrics();
+        assert_eq!(empty.block_height, 0);
+        assert_eq!(empty.memory_bytes, 0);
+
+        state.submit_block(state.block_template(&[1; 32])?.into_block(0))?;
+        let coinbase = state.chain.read().utxo_set[0].clone();
+        let change = output(coinbase.value - 10, 2);
+        state.mempool().add(
+            balanced_tx_on(&state.parameters(), vec![coinbase], vec![change], 10),
+            &state,
+        )?;
+
+        let metrics = state.metrics();
+        assert_eq!(
+            metrics,
+            StateMetrics {
+                block_height: 1,
+                utxo_count: 1,
+                kernel_count: 1,
+                utxo_kernel_ratio: 1.0,
+                mempool_size: 1,
+                memory_bytes: metrics.memory_bytes,
+            }
+        );
+        assert!(metrics.memory_bytes > empty.memory_bytes);
+
+        let json = serde_json::to_string(&metrics).unwrap();
+        assert!(json.contains("\"mempool_size\":1"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_utxo_kernel_ratio() -> Result<()> {
+        let state = MimblewimbleState::new(MimblewimbleParameters::regtest())?;
+        assert_eq!(state.utxo_kernel_ratio(), 0.0);
+        state.submit_block(state.block_template(&[1; 32])?.into_block(0))?;
+        assert_eq!(state.utxo_kernel_ratio(), 1.0);
+
+        // Splitting the coinbase in four adds three outputs and one kernel
+        let params = state.parameters();
+        let coinbase = state.chain.read().utxo_set[0].clone();
+        let quarter = (coinbase.value - 10) / 4;
+        let mut outputs: Vec<Commitment> = (2..=4).map(|n| output(quarter, n)).collect();
+        outputs.push(output(coinbase.value - 10 - 3 * quarter, 5));
+        let mut split = balanced_tx_on(&params, vec![coinbase], outputs, 10);
+        split.sort_canonical();
+        state.mempool().add(split, &state)?;
+        state.submit_block(state.block_template(&[7; 32])?.into_block(0))?;
+
+        let metrics = state.metrics();
+        assert_eq!((metrics.utxo_count, metrics.kernel_count), (5, 3));
+        assert_eq!(state.utxo_kernel_ratio(), 5.0 / 3.0);
+        assert_eq!(metrics.utxo_kernel_ratio, 5.0 / 3.0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_export_headers() -> Result<()> {
+        let state = MimblewimbleState::new(MimblewimbleParameters::regtest())?;
+        for n in 0..5u8 {
+            state.submit_block(state.block_template(&[n + 1; 32])?.into_block(0))?;
+        }
+
+        let headers = state.export_headers(1, 4)?;
+        assert_eq!(headers.len(), 3);
+        assert_eq!(headers[0].height, 1);
+        for pair in headers.windows(2) {
+            assert_eq!(pair[1].previous_hash, pair[0].hash()?);
+        }
+
+        assert!(state.export_headers(3, 3).is_err());
+        assert!(state.export_headers(4, 2).is_err());
+        assert!(state.export_headers(0, 6).is_err());
+        assert_eq!(state.export_headers(0, 5)?.len(), 5);
+        Ok(())
+    }
+
+    #[test]
+    fn test_checkpoint_round_trip() -> Result<()> {
+        let params = MimblewimbleParameters::regtest();
+        let state = MimblewimbleState::new(params.clone())?;
+        for n in 0..3u8 {
+            state.submit_block(state.block_template(&[n + 1; 32])?.into_block(0))?;
+        }
+
+        let bytes = serialization::to_vec(&state.export_checkpoint())?;
+        let imported =
+            MimblewimbleState::import_checkpoint(serialization::from_slice(&bytes)?, params)?;
+        assert_eq!(imported.block_height(), 3);
+        assert_eq!(imported.snapshot().utxo_set, state.snapshot().utxo_set);
+        assert_eq!(imported.kernel_set_hash(), state.kernel_set_hash());
+
+        // The imported state extends the chain like the original
+        let block = state.block_template(&[9; 32])?.into_block(0);
+        imported.submit_block(block)?;
+        assert_eq!(imported.block_height(), 4);
+        assert!(imported.get_block(0).is_none());
+        assert!(imported.get_block(3).is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_checkpoint_rejects_kernel_set_hash_mismatch() -> Result<()> {
+        let params = MimblewimbleParameters::regtest();
+        let state = MimblewimbleState::new(params.clone())?;
+        state.submit_block(state.block_template(&[1; 32])?.into_block(0))?;
+
+        let mut bundle = state.export_checkpoint();
+        bundle.kernel_set_hash[0] ^= 1;
+        assert!(matches!(
+            MimblewimbleState::import_checkpoint(bundle, params),
+            Err(MimblewimbleError::InvalidCheckpoint)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_checkpoint_must_match_tip_header() -> Result<()> {
+        let params = MimblewimbleParameters::regtest();
+        let state = MimblewimbleState::new(params.clone())?;
+        for key in 1..=2u8 {
+            state.submit_block(state.block_template(&[key; 32])?.into_block(0))?;
+        }
+        let bundle = state.export_checkpoint();
+        assert_eq!(bundle.supply, 2 * params.block_reward(0));
+        let imported = MimblewimbleState::import_checkpoint(bundle.clone(), params.clone())?;
+        assert_eq!(imported.supply(), state.supply());
+
+        let rejected = |bundle: CheckpointBundle| {
+            matches!(
+                MimblewimbleState::import_checkpoint(bundle, params.clone()),
+                Err(MimblewimbleError::InvalidCheckpoint)
+            )
+        };
+
+        // A minted output, with the bundle's own root recomputed
+        let mut forged = bundle.clone();
+        forged.utxo_set.push(output(1_000_000, 9));
+        forged.output_root = block::compute_output_root(
+            &forged
+                .utxo_set
+                .iter()
+                .map(|utxo| utxo.commitment.clone())
+                .collect::<Vec<_>>(),
+        );
+        assert!(rejected(forged));
+
+        // A dropped kernel, with the bundle's own kernel set hash recomputed
+        let mut forged = bundle.clone();
+        forged.kernel_set.pop();
+        let kernel_set_hash = forged
+            .kernel_set
+            .iter()
+            .map(|kernel| kernel_set_element(&kernel.excess))
+            .sum();
+        forged.kernel_set_hash = crypto::point_to_bytes(&kernel_set_hash);
+        assert!(rejected(forged));
+
+        // A tampered kernel signature
+        let mut forged = bundle.clone();
+        forged.kernel_set[0].signature[0] ^= 1;
+        assert!(rejected(forged));
+
+        // A supply the UTXO set does not hold, or the chain never paid
+        let mut forged = bundle.clone();
+        forged.supply += 1;
+        assert!(rejected(forged));
+        let mut forged = bundle;
+        forged.supply = u64::MAX;
+        assert!(rejected(forged));
+        Ok(())
+    }
+
+    #[test]
+    fn test_output_index_by_height() -> Result<()> {
+        let state = MimblewimbleState::new(MimblewimbleParameters::regtest())?;
+        for n in 0..2u8 {
+            state.submit_block(state.block_template(&[n + 1; 32])?.into_block(0))?;
+        }
+        let first = state.outputs_at_height(0).unwrap();
+        assert_eq!(first.added.len(), 1);
+        assert!(first.removed.is_empty());
+
+        // Spend the first coinbase in the third block
+        let coinbase = first.added[0].clone();
+        let change = output(coinbase.value - 10, 2);
+        let tx = balanced_tx_on(
+            &state.parameters(),
+            vec![coinbase.clone()],
+            vec![change.clone()],
+            10,
+        );
+        state.mempool().add(tx, &state)?;
+        state.submit_block(state.block_template(&[3; 32])?.into_block(0))?;
+
+        let third = state.outputs_at_height(2).unwrap();
+        assert_eq!(third.height, 2);
+        assert_eq!(third.removed, vec![coinbase.commitment]);
+        assert_eq!(third.added.len(), 2);
+        assert!(third.added.contains(&change));
+
+        assert_eq!(state.outputs_at_height(1).unwrap().added.len(), 1);
+        assert!(state.outputs_at_height(3).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_combined_lock_requires_both_conditions() -> Result<()> {
+        let state = MimblewimbleState::new(MimblewimbleParameters::regtest())?;
+        let params = state.parameters();
+        state.submit_block(state.block_template(&[1; 32])?.into_block(0))?;
+
+        // Locked to height 1 and to three blocks after the coinbase
+        let coinbase = state.outputs_at_height(0).unwrap().added[0].clone();
+        let fee = 10;
+        let change_blinding = vec![2; 32];
+        let excess = crypto::scalar_from_bytes(&change_blinding)?
+            - crypto::scalar_from_bytes(&coinbase.blinding)?;
+        let mut kernel = Kernel::new(KernelFeatures::CombinedLocked, fee, 1, vec![], vec![])
+            .with_relative_lock(coinbase.commitment.clone(), 3);
+        kernel.sign(&crypto::scalar_to_bytes(&excess), &params)?;
+        let change = Commitment::create(coinbase.value - fee, change_blinding, &params)?;
+        let tx = Transaction::new(1, vec![coinbase], vec![change], kernel, fee);
+        assert_eq!(state.maturity_height(&tx), Some(3));
+
+        // At height 1 the absolute lock is satisfied but the relative one is not
+        let early = state.create_block(vec![tx.clone()])?;
+        assert_eq!(early.header.height, 1);
+        assert!(matches!(
+            state.accept_block(early),
+            Err(MimblewimbleError::InvalidTransaction)
+        ));
+
+        // The mempool holds it until both are satisfied
+        let id = state.mempool().add(tx, &state)?;
+        assert_eq!(state.mempool().deferred_size(), 1);
+        state.submit_block(state.block_template(&[3; 32])?.into_block(0))?;
+        state.submit_block(state.block_template(&[4; 32])?.into_block(0))?;
+        assert!(state.mempool().contains(&id));
+
+        let block = state.block_template(&[5; 32])?.into_block(0);
+        assert_eq!(block.header.height, 3);
+        assert_eq!(block.transaction_count(), 2);
+        state.submit_block(block)?;
+        assert!(state.mempool().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_relative_lock_survives_checkpoint() -> Result<()> {
+        let params = MimblewimbleParameters::regtest();
+        let state = MimblewimbleState::new(params.clone())?;
+        for key in 1..=2u8 {
+            state.submit_block(state.block_template(&[key; 32])?.into_block(0))?;
+        }
+
+        // Locked to three blocks after the first coinbase
+        let coinbase = state.outputs_at_height(0).unwrap().added[0].clone();
+        let fee = 10;
+        let change_blinding = vec![2; 32];
+        let excess = crypto::scalar_from_bytes(&change_blinding)?
+            - crypto::scalar_from_bytes(&coinbase.blinding)?;
+        let mut kernel = Kernel::new(KernelFeatures::CombinedLocked, fee, 1, vec![], vec![])
+            .with_relative_lock(coinbase.commitment.clone(), 3);
+        kernel.sign(&crypto::scalar_to_bytes(&excess), &params)?;
+        let change = Commitment::create(coinbase.value - fee, change_blinding, &params)?;
+        let tx = Transaction::new(1, vec![coinbase], vec![change], kernel, fee);
+
+        let bundle = state.export_checkpoint();
+        assert_eq!(bundle.output_heights, [0, 1]);
+        let imported = MimblewimbleState::import_checkpoint(bundle.clone(), params.clone())?;
+        assert_eq!(imported.maturity_height(&tx), Some(3));
+
+        // The imported node accepts the spend once it matures
+        imported.submit_block(imported.block_template(&[3; 32])?.into_block(0))?;
+        imported.accept_block(imported.create_block(vec![tx])?)?;
+        assert_eq!(imported.block_height(), 4);
+
+        // Creation heights must fall below the tip
+        let mut forged = bundle;
+        forged.output_heights[0] = 2;
+        assert!(matches!(
+            MimblewimbleState::import_checkpoint(forged, params),
+            Err(MimblewimbleError::InvalidCheckpoint)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_utxo_commitments() -> Result<()> {
+        let outputs: Vec<Commitment> = (1..=4).map(|n| output(100, n)).collect();
+        let forward = state_with_outputs(&outputs);
+        let mut reversed = outputs.clone();
+        reversed.reverse();
+        reversed.push(outputs[2].clone());
+        let reversed = state_with_outputs(&reversed);
+
+        let commitments = forward.utxo_commitments();
+        assert_eq!(commitments.len(), 4);
+        assert!(commitments.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(reversed.utxo_commitments(), commitments);
+        assert_eq!(
+            reversed.utxo_commitments_hash()?,
+            forward.utxo_commitments_hash()?
+        );
+
+        forward.chain.write().utxo_set.pop();
+        assert_ne!(
+            forward.utxo_commitments_hash()?,
+            reversed.utxo_commitments_hash()?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_absent_range_proofs() -> Result<()> {
+        let funding = output(1000, 1);
+        let mut tx = balanced_tx(vec![funding.clone()], vec![output(990, 2)], 10);
+        tx.outputs_mut()[0].range_proof = RangeProof::absent();
+
+        let state = state_with_outputs(std::slice::from_ref(&funding));
+        assert!(state.parameters().require_range_proofs);
+        assert!(!state.verify_transaction(&tx)?);
+
+        let trusting = MimblewimbleState {
+            parameters: Arc::new(MimblewimbleParameters {
+                require_range_proofs: false,
+                ..MimblewimbleParameters::default()
+            }),
+            ..state_with_outputs(std::slice::from_ref(&funding))
+        };
+        assert!(trusting.verify_transaction(&tx)?);
+
+        // Present proofs are still verified
+        tx.outputs_mut()[0].range_proof = RangeProof::new(vec![0; 4]);
+        assert!(!trusting.verify_transaction(&tx)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_projected_utxo_size() -> Result<()> {
+        let funding = [output(1000, 1), output(2000, 2)];
+        let state = MimblewimbleState {
+            parameters: Arc::new(MimblewimbleParameters::regtest()),
+            ..state_with_outputs(&funding)
+        };
+        let params = state.parameters();
+
+        // One spend splits an output in two, the other replaces its output
+        let mut split = balanced_tx_on(
+            &params,
+            vec![funding[0].clone()],
+            vec![output(500, 3), output(490, 4)],
+            10,
+        );
+        split.sort_canonical();
+        let spend = balanced_tx_on(&params, vec![funding[1].clone()], vec![output(1990, 5)], 10);
+
+        let block = state.create_block(vec![split, spend])?;
+        let projected = state.projected_utxo_size(&block);
+        assert_eq!(projected, 3);
+        assert_eq!(state.utxo_set_size(), 2);
+
+        state.accept_block(block)?;
+        assert_eq!(state.utxo_set_size(), projected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_no_inflation() -> Result<()> {
+        let state = MimblewimbleState::new(MimblewimbleParameters::regtest())?;
+        let params = state.parameters();
+        let reward = params.block_reward(0);
+        state.submit_block(state.block_template(&[1; 32])?.into_block(0))?;
+
+        // Spend the coinbase to a new output, paying a fee to the next miner
+        let coinbase = state.chain.read().utxo_set[0].clone();
+        let fee = 10;
+        let change_blinding = vec![2; 32];
+        let excess = crypto::scalar_from_bytes(&change_blinding)?
+            - crypto::scalar_from_bytes(&coinbase.blinding)?;
+        let mut kernel = Kernel::new(KernelFeatures::Plain, fee, 0, vec![], vec![]);
+        kernel.sign(&crypto::scalar_to_bytes(&excess), &params)?;
+        let change = Commitment::create(reward - fee, change_blinding, &params)?;
+        let tx = Transaction::new(1, vec![coinbase], vec![change], kernel, fee);
+        state.mempool().add(tx, &state)?;
+        state.submit_block(state.block_template(&[3; 32])?.into_block(0))?;
+
+        assert_eq!(state.supply(), 2 * reward);
+        state.verify_no_inflation(2 * reward)?;
+        assert!(state.verify_no_inflation(2 * reward + 1).is_err());
+
+        // An output appearing from nowhere breaks the invariant
+        state.chain.write().utxo_set.push(output(1, 4));
+        assert!(matches!(
+            state.verify_no_inflation(2 * reward),
+            Err(MimblewimbleError::BalanceMismatch)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_total_excess_accumulator() -> Result<()> {
+        let state = MimblewimbleState::new(MimblewimbleParameters::regtest())?;
+        let params = state.parameters();
+        let mine = |key: u8| -> Result<()> {
+            let block = state.block_template(&[key; 32])?.into_block(0);
+            state.submit_block(block).map(|_| ())
+        };
+        mine(1)?;
+        mine(2)?;
+
+        // Spend the first coinbase, paying a fee to the third block's miner
+        let coinbase = state.outputs_at_height(0).unwrap().added[0].clone();
+        let tx = balanced_tx_on(
+            &params,
+            vec![coinbase.clone()],
+            vec![output(coinbase.value - 10, 2)],
+            10,
+        );
+        state.mempool().add(tx, &state)?;
+        mine(3)?;
+
+        let chain = state.chain.read();
+        let recomputed =
+            crypto::sum_points(chain.kernel_set.kernels().map(|k| k.excess.as_slice()))?;
+        assert_eq!(chain.total_excess, recomputed);
+        drop(chain);
+
+        let supply = 3 * params.block_reward(0);
+        state.verify_total_excess(supply)?;
+        assert!(matches!(
+            state.verify_total_excess(supply - 1),
+            Err(MimblewimbleError::BalanceMismatch)
+        ));
+
+        // The accumulator survives a checkpoint round trip
+        let imported = MimblewimbleState::import_checkpoint(
+            state.export_checkpoint(),
+            MimblewimbleParameters::regtest(),
+        )?;
+        imported.verify_total_excess(supply)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_prune_block_bodies() -> Result<()> {
+        let state = MimblewimbleState::new(MimblewimbleParameters {
+            pruning_interval: 2,
+            ..MimblewimbleParameters::regtest()
+        })?;
+        for key in 1..=4u8 {
+            state.accept_block(state.block_template(&[key; 32])?.into_block(0))?;
+        }
+        let header = state.get_block(0).unwrap().header;
+        let (utxo_root, kernel_set_hash) = (state.utxo_root(), state.kernel_set_hash());
+
+        assert_eq!(state.prune_block_bodies(), 2);
+        assert_eq!(state.prune_block_bodies(), 0);
+
+        // Old bodies are gone, their headers and the derived state remain
+        assert!(state.get_block(0).is_none());
+        assert!(state.get_block(1).is_none());
+        assert!(state.get_block(2).is_some());
+        assert!(state.outputs_at_height(1).is_none());
+        assert!(state.outputs_at_height(2).is_some());
+        assert_eq!(state.get_header(0), Some(header.clone()));
+        assert!(matches!(
+            state.serve_block_txs(&header.hash()?, 0, 1),
+            Err(MimblewimbleError::BlockNotFound)
+        ));
+        assert_eq!(state.utxo_root(), utxo_root);
+        assert_eq!(state.kernel_set_hash(), kernel_set_hash);
+        assert_eq!(state.utxo_set_size(), 4);
+        state.verify_kernel_log()?;
+
+        // New blocks still build on the pruned chain
+        state.accept_block(state.block_template(&[5; 32])?.into_block(0))?;
+        assert_eq!(state.block_height(), 5);
+        Ok(())
+    }
+
+    #[test]
+    fn test_prune_kernels() -> Result<()> {
+        let params = MimblewimbleParameters::regtest();
+        let state = MimblewimbleState::new(params.clone())?;
+        for key in 1..=4u8 {
+            state.accept_block(state.block_template(&[key; 32])?.into_block(0))?;
+        }
+        let supply = 4 * params.block_reward(0);
+        let kernel_set_hash = state.kernel_set_hash();
+
+        assert_eq!(state.prune_kernels(2), 2);
+        assert_eq!(state.prune_kernels(2), 0);
+
+        // Signatures below the horizon are gone, excesses and sums remain
+        {
+            let chain = state.chain.read();
+            let signed: Vec<bool> = chain
+                .kernel_set
+                .kernels()
+                .map(|kernel| !kernel.signature.is_empty())
+                .collect();
+            assert_eq!(signed, [false, false, true, true]);
+        }
+        assert_eq!(state.kernel_set_hash(), kernel_set_hash);
+        state.verify_no_inflation(supply)?;
+        state.verify_total_excess(supply)?;
+        state.verify_kernel_log()?;
+
+        // The chain keeps growing and later kernels can still be pruned
+        state.accept_block(state.block_template(&[5; 32])?.into_block(0))?;
+        assert_eq!(state.prune_kernels(u64::MAX), 3);
+        state.verify_no_inflation(supply + params.block_reward(0))?;
+        Ok(())
+    }
 }
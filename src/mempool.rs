@@ -0,0 +1,889 @@
+//! Transaction pool for Mimblewimble
+
+use crate::errors::{MimblewimbleError, Result};
+use crate::serialization;
+use crate::transaction::Transaction;
+use crate::MimblewimbleState;
+use lru::LruCache;
+use parking_lot::{Mutex, RwLock};
+use rayon::prelude::*;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::time::Instant;
+
+/// Transaction identifier (transaction hash)
+pub type TxId = Vec<u8>;
+
+/// Peer identifier
+pub type PeerId = String;
+
+/// Transactions a peer may send in a burst by default
+const DEFAULT_PEER_BURST: u32 = 100;
+
+/// Transactions per second a peer's budget refills by default
+const DEFAULT_PEER_RATE: u32 = 10;
+
+/// Most transactions waiting for their lock height by default
+const DEFAULT_MAX_DEFERRED: usize = 1_000;
+
+/// Most peers whose budget is tracked by default
+const DEFAULT_MAX_PEERS: NonZeroUsize = NonZeroUsize::new(10_000).unwrap();
+
+/// Per-peer transaction budget
+#[derive(Debug, Clone, Copy)]
+struct TokenBucket {
+    /// Remaining budget
+    tokens: f64,
+
+    /// Time of the last refill
+    refilled: Instant,
+}
+
+/// Pending transactions and the inputs they spend
+#[derive(Debug, Default)]
+struct Pool {
+    /// Pending transactions by id
+    transactions: HashMap<TxId, Transaction>,
+
+    /// Input commitment to the pending transaction spending it
+    spent_by: HashMap<Vec<u8>, TxId>,
+
+    /// Fingerprint of each pending transaction to its id
+    fingerprints: HashMap<Vec<u8>, TxId>,
+
+    /// Transactions waiting for their lock height, by lock height
+    deferred: BTreeMap<u64, Vec<Transaction>>,
+
+    /// Deferred transaction ids
+    deferred_ids: HashSet<TxId>,
+
+    /// Input commitment to the deferred transaction spending it
+    deferred_spent_by: HashMap<Vec<u8>, TxId>,
+}
+
+/// Mempool of transactions waiting to be mined
+#[derive(Debug)]
+pub struct Mempool {
+    pool: RwLock<Pool>,
+
+    /// Transactions a peer may send in a burst
+    peer_burst: u32,
+
+    /// Transactions per second a peer's budget refills
+    peer_rate: u32,
+
+    /// Remaining budget of the most recently seen peers
+    peer_buckets: Mutex<LruCache<PeerId, TokenBucket>>,
+
+    /// Most transactions waiting for their lock height
+    max_deferred: usize,
+}
+
+impl Default for Mempool {
+    fn default() -> Self {
+        Self::with_rate_limit(DEFAULT_PEER_BURST, DEFAULT_PEER_RATE)
+    }
+}
+
+impl Mempool {
+    /// Create an empty mempool
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create an empty mempool with a per-peer token bucket rate limit
+    pub fn with_rate_limit(burst: u32, per_second: u32) -> Self {
+        Self {
+            pool: RwLock::new(Pool::default()),
+            peer_burst: burst,
+            peer_rate: per_second,
+            peer_buckets: Mutex::new(LruCache::new(DEFAULT_MAX_PEERS)),
+            max_deferred: DEFAULT_MAX_DEFERRED,
+        }
+    }
+
+    /// Limit the number of peers whose budget is tracked
+    ///
+    /// The least recently seen peer is forgotten first and starts again with
+    /// a full budget, which an idle peer would have regained anyway.
+    pub fn with_max_peers(mut self, max_peers: NonZeroUsize) -> Self {
+        self.peer_buckets = Mutex::new(LruCache::new(max_peers));
+        self
+    }
+
+    /// Limit the number of transactions waiting for their lock height
+    pub fn with_max_deferred(mut self, max_deferred: usize) -> Self {
+        self.max_deferred = max_deferred;
+        self
+    }
+
+    /// Add a transaction relayed by a peer, subject to its rate limit
+    ///
+    /// The peer's budget is charged before verification, so invalid
+    /// transactions count against it too.
+    pub fn add_from_peer(
+        &self,
+        peer_id: &str,
+        transaction: Transaction,
+        state: &MimblewimbleState,
+    ) -> Result<TxId> {
+        self.take_token(peer_id, Instant::now())?;
+        self.add(transaction, state)
+    }
+
+    /// Charge one transaction against a peer's budget
+    fn take_token(&self, peer_id: &str, now: Instant) -> Result<()> {
+        let mut buckets = self.peer_buckets.lock();
+        let bucket = buckets.get_or_insert_mut(peer_id.to_string(), || TokenBucket {
+            tokens: f64::from(self.peer_burst),
+            refilled: now,
+        });
+
+        let elapsed = now.saturating_duration_since(bucket.refilled).as_secs_f64();
+        bucket.tokens =
+            (bucket.tokens + elapsed * f64::from(self.peer_rate)).min(f64::from(self.peer_burst));
+        bucket.refilled = now;
+
+        if bucket.tokens < 1.0 {
+            return Err(MimblewimbleError::RateLimited);
+        }
+        bucket.tokens -= 1.0;
+        Ok(())
+    }
+
+    /// Verify a transaction against the state and add it to the pool
+    pub fn add(&self, transaction: Transaction, state: &MimblewimbleState) -> Result<TxId> {
+        Self::check(&transaction, state)?;
+        self.insert(transaction, state)
+    }
+
+    /// Verify and add a batch of transactions
+    ///
+    /// Transactions are verified in parallel, without holding the pool
+    /// lock, and then inserted one at a time. Returns one result per
+    /// transaction, in order.
+    pub fn add_batch(
+        &self,
+        transactions: Vec<Transaction>,
+        state: &MimblewimbleState,
+    ) -> Vec<Result<TxId>> {
+        let checked: Vec<Result<()>> = transactions
+            .par_iter()
+            .map(|transaction| Self::check(transaction, state))
+            .collect();
+
+        transactions
+            .into_iter()
+            .zip(checked)
+            .map(|(transaction, checked)| checked.and_then(|()| self.insert(transaction, state)))
+            .collect()
+    }
+
+    /// Verify a transaction against the state for admission to the pool
+    fn check(transaction: &Transaction, state: &MimblewimbleState) -> Result<()> {
+        // Coinbase transactions are only valid inside a block
+        if transaction.is_coinbase() {
+            return Err(MimblewimbleError::InvalidTransaction);
+        }
+
+        // Blocks reject transactions whose outputs or kernels are out of order
+        if state.parameters().enforce_canonical_order && !transaction.is_canonical() {
+            return Err(MimblewimbleError::InvalidTransaction);
+        }
+
+        if !state.verify_transaction(transaction)? {
+            return Err(MimblewimbleError::InvalidTransaction);
+        }
+
+        // Outputs this node can open are not relayed if they hold nothing
+        if !state.parameters().allow_zero_value_outputs
+            && transaction.has_known_zero_value_output()?
+        {
+            return Err(MimblewimbleError::InvalidTransaction);
+        }
+
+        Ok(())
+    }
+
+    /// Add a verified transaction to the pool, checking for conflicts
+    fn insert(&self, transaction: Transaction, state: &MimblewimbleState) -> Result<TxId> {
+        let id = transaction.hash()?;
+        let maturity = state.maturity_height(&transaction);
+        let mut pool = self.pool.write();
+
+        if pool.transactions.contains_key(&id) {
+            return Ok(id);
+        }
+
+        // A relayed variant of a pending transaction is the same transaction
+        let fingerprint = transaction.fingerprint();
+        if let Some(existing) = pool.fingerprints.get(&fingerprint) {
+            return Ok(existing.clone());
+        }
+
+        // Transactions locked past the next block wait for their locks to
+        // mature, and those that never can are rejected
+        let lock_height = maturity.ok_or(MimblewimbleError::InvalidTransaction)?;
+        if lock_height > state.block_height() {
+            if !state.parameters().defer_locked_transactions {
+                return Err(MimblewimbleError::InvalidTransaction);
+            }
+            if pool.deferred_ids.contains(&id) {
+                return Ok(id);
+            }
+            if transaction.inputs().iter().any(|input| {
+                pool.spent_by.contains_key(&input.commitment)
+                    || pool.deferred_spent_by.contains_key(&input.commitment)
+            }) {
+                return Err(MimblewimbleError::DoubleSpend);
+            }
+            if pool.deferred_ids.len() >= self.max_deferred {
+                return Err(MimblewimbleError::PoolFull);
+            }
+
+            for input in transaction.inputs() {
+                pool.deferred_spent_by
+                    .insert(input.commitment.clone(), id.clone());
+            }
+            pool.deferred_ids.insert(id.clone());
+            pool.deferred
+                .entry(lock_height)
+                .or_default()
+                .push(transaction);
+            return Ok(id);
+        }
+
+        // Only one pending transaction may spend a given output
+        if transaction
+            .inputs()
+            .iter()
+            .any(|input| pool.spent_by.contains_key(&input.commitment))
+        {
+            return Err(MimblewimbleError::DoubleSpend);
+        }
+
+        for input in transaction.inputs() {
+            pool.spent_by.insert(input.commitment.clone(), id.clone());
+        }
+        pool.fingerprints.insert(fingerprint, id.clone());
+        pool.transactions.insert(id.clone(), transaction);
+
+        Ok(id)
+    }
+
+    /// Remove a transaction from the pool
+    pub fn remove(&self, id: &TxId) -> Option<Transaction> {
+        let mut pool = self.pool.write();
+        let transaction = pool.transactions.remove(id)?;
+        for input in transaction.inputs() {
+            if pool.spent_by.get(&input.commitment) == Some(id) {
+                pool.spent_by.remove(&input.commitment);
+            }
+        }
+        let fingerprint = transaction.fingerprint();
+        if pool.fingerprints.get(&fingerprint) == Some(id) {
+            pool.fingerprints.remove(&fingerprint);
+        }
+        Some(transaction)
+    }
+
+    /// Check whether a transaction is in the pool
+    pub fn contains(&self, id: &TxId) -> bool {
+        self.pool.read().transactions.contains_key(id)
+    }
+
+    /// Get a pending transaction
+    pub fn get(&self, id: &TxId) -> Option<Transaction> {
+        self.pool.read().transactions.get(id).cloned()
+    }
+
+    /// Select transactions for a block, highest fee rate first
+    ///
+    /// Transactions are budgeted by their wire size. One that cannot be
+    /// encoded could not be relayed in a block either, so it is skipped.
+    pub fn select(&self, max_size: usize) -> Vec<Transaction> {
+        let pool = self.pool.read();
+
+        let mut candidates: Vec<(u64, usize, &TxId, &Transaction)> = pool
+            .transactions
+            .iter()
+            .filter_map(|(id, tx)| Some((tx.fee(), tx.wire_size().ok()?, id, tx)))
+            .collect();
+
+        // Order by fee per byte (cross-multiplied to stay in integers), then id
+        candidates.sort_by(|a, b| {
+            let lhs = u128::from(b.0) * a.1 as u128;
+            let rhs = u128::from(a.0) * b.1 as u128;
+            lhs.cmp(&rhs).then_with(|| a.2.cmp(b.2))
+        });
+
+        let mut selected = Vec::new();
+        let mut total_size = 0;
+        for (_, size, _, tx) in candidates {
+            if total_size + size > max_size {
+                continue;
+            }
+            total_size += size;
+            selected.push(tx.clone());
+        }
+
+        selected
+    }
+
+    /// List pairs of pending transactions spending a common input
+    ///
+    /// Each pair is reported once, as (indexed spender, conflicting spender).
+    pub fn conflicts(&self) -> Vec<(TxId, TxId)> {
+        let pool = self.pool.read();
+
+        let mut conflicts = Vec::new();
+        for (id, tx) in &pool.transactions {
+            for input in tx.inputs() {
+                if let Some(owner) = pool.spent_by.get(&input.commitment) {
+                    if owner != id {
+                        conflicts.push((owner.clone(), id.clone()));
+                    }
+                }
+            }
+        }
+
+        conflicts.sort();
+        conflicts.dedup();
+        conflicts
+    }
+
+    /// Estimate how many pending transactions a transaction could be
+    /// aggregated with in the next block
+    ///
+    /// Counts the other pending transactions that spend none of its inputs
+    /// and carry no conflicting anchor. Wallets can use this to judge
+    /// whether delaying a transaction would improve its privacy.
+    pub fn aggregation_forecast(&self, transaction: &Transaction) -> usize {
+        let id = transaction.hash().ok();
+        let pool = self.pool.read();
+
+        pool.transactions
+            .iter()
+            .filter(|(other_id, _)| Some(*other_id) != id.as_ref())
+            .filter(|(_, other)| {
+                !other.inputs().iter().any(|input| {
+                    transaction
+                        .inputs()
+                        .iter()
+                        .any(|own| own.commitment == input.commitment)
+                })
+            })
+            .filter(|(_, other)| match (transaction.anchor(), other.anchor()) {
+                (Some(own), Some(anchor)) => own == anchor,
+                _ => true,
+            })
+            .count()
+    }
+
+    /// Insert a transaction without verification or conflict checks
+    #[cfg(test)]
+    pub(crate) fn insert_unchecked(&self, transaction: Transaction) -> TxId {
+        let id = transaction.hash().unwrap();
+        let mut pool = self.pool.write();
+        for input in transaction.inputs() {
+            pool.spent_by
+                .entry(input.commitment.clone())
+                .or_insert_with(|| id.clone());
+        }
+        pool.fingerprints
+            .entry(transaction.fingerprint())
+            .or_insert_with(|| id.clone());
+        pool.transactions.insert(id.clone(), transaction);
+        id
+    }
+
+    /// Remove transactions included in a block
+    pub fn remove_included(&self, ids: &[TxId]) {
+        for id in ids {
+            self.remove(id);
+        }
+    }
+
+    /// Recheck pending transactions against the state, evicting invalid ones
+    ///
+    /// Returns the ids of evicted transactions, e.g. those spending outputs
+    /// consumed by a conflicting transaction that was mined.
+    pub fn revalidate(&self, state: &MimblewimbleState) -> Vec<TxId> {
+        let pending: Vec<(TxId, Transaction)> = self
+            .pool
+            .read()
+            .transactions
+            .iter()
+            .map(|(id, tx)| (id.clone(), tx.clone()))
+            .collect();
+
+        let mut evicted: Vec<TxId> = pending
+            .into_iter()
+            .filter(|(_, tx)| !matches!(state.verify_transaction(tx), Ok(true)))
+            .map(|(id, _)| id)
+            .collect();
+        evicted.sort();
+
+        for id in &evicted {
+            self.remove(id);
+        }
+        evicted
+    }
+
+    /// Move deferred transactions whose lock height has been reached into
+    /// the pool
+    ///
+    /// Each is verified again as it is added; returns the ids of those
+    /// admitted.
+    pub fn promote_deferred(&self, state: &MimblewimbleState) -> Vec<TxId> {
+        let ready: Vec<Transaction> = {
+            let mut pool = self.pool.write();
+            let later = pool
+                .deferred
+                .split_off(&state.block_height().saturating_add(1));
+            let ready: Vec<Transaction> = std::mem::replace(&mut pool.deferred, later)
+                .into_values()
+                .flatten()
+                .collect();
+            for transaction in &ready {
+                if let Ok(id) = transaction.hash() {
+                    pool.deferred_ids.remove(&id);
+                }
+                for input in transaction.inputs() {
+                    pool.deferred_spent_by.remove(&input.commitment);
+                }
+            }
+            ready
+        };
+
+        ready
+            .into_iter()
+            .filter_map(|tx| self.add(tx, state).ok())
+            .collect()
+    }
+
+    /// Write pending and deferred transactions to a file
+    pub fn persist(&self, path: impl AsRef<Path>) -> Result<()> {
+        let transactions: Vec<Transaction> = {
+            let pool = self.pool.read();
+            let mut pending: Vec<(&TxId, &Transaction)> = pool.transactions.iter().collect();
+            pending.sort_by(|a, b| a.0.cmp(b.0));
+            pending
+                .into_iter()
+                .map(|(_, tx)| tx)
+                .chain(pool.deferred.values().flatten())
+                .cloned()
+                .collect()
+        };
+
+        std::fs::write(path, serialization::to_vec(&transactions)?)
+            .map_err(|e| MimblewimbleError::IoError(e.to_string()))
+    }
+
+    /// Restore transactions written by `persist`
+    ///
+    /// Each is verified against the current state as it is added, and
+    /// those no longer valid are dropped. Returns the ids of the restored
+    /// transactions.
+    pub fn load(&self, path: impl AsRef<Path>, state: &MimblewimbleState) -> Result<Vec<TxId>> {
+        let bytes = std::fs::read(path).map_err(|e| MimblewimbleError::IoError(e.to_string()))?;
+        let transactions: Vec<Transaction> = serialization::from_slice(&bytes)?;
+
+        Ok(transactions
+            .into_iter()
+            .filter_map(|tx| self.add(tx, state).ok())
+            .collect())
+    }
+
+    /// Number of transactions waiting for their lock height
+    pub fn deferred_size(&self) -> usize {
+        self.pool.read().deferred_ids.len()
+    }
+
+    /// Number of pending transactions
+    pub fn size(&self) -> usize {
+        self.pool.read().transactions.len()
+    }
+
+    /// Check whether the pool is empty
+    pub fn is_empty(&self) -> bool {
+        self.size() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commitment::Commitment;
+    use crate::crypto;
+    use crate::kernel::{Kernel, KernelFeatures};
+    use crate::tests::{balanced_tx, balanced_tx_on, output, state_with_outputs};
+
+    fn spend(input: &Commitment, fee: u64, blinding: u8) -> Transaction {
+        spend_on(
+            &crate::MimblewimbleParameters::default(),
+            input,
+            fee,
+            blinding,
+        )
+    }
+
+    fn spend_on(
+        parameters: &crate::MimblewimbleParameters,
+        input: &Commitment,
+        fee: u64,
+        blinding: u8,
+    ) -> Transaction {
+        let change = output(input.value - fee, blinding);
+        balanced_tx_on(parameters, vec![input.clone()], vec![change], fee)
+    }
+
+    /// Spend `output(1000, funding)` under a kernel locked until height 2
+    fn locked_spend(state: &MimblewimbleState, funding: u8, blinding: u8) -> Result<Transaction> {
+        let mut locked = spend(&output(1000, funding), 10, blinding);
+        let excess = crypto::scalar_from_bytes(&[blinding; 32])?
+            - crypto::scalar_from_bytes(&[funding; 32])?;
+        locked.kernels_mut()[0] = Kernel::new(KernelFeatures::HeightLocked, 10, 2, vec![], vec![]);
+        locked.kernels_mut()[0].sign(&crypto::scalar_to_bytes(&excess), &state.parameters())?;
+        Ok(locked)
+    }
+
+    #[test]
+    fn test_mempool_add_and_remove() -> Result<()> {
+        let funding = output(1000, 1);
+        let state = state_with_outputs(std::slice::from_ref(&funding));
+        let mempool = Mempool::new();
+
+        // Spending an unknown output is rejected
+        assert!(mempool.add(spend(&output(1000, 9), 10, 2), &state).is_err());
+        assert!(mempool.is_empty());
+
+        let id = mempool.add(spend(&funding, 10, 2), &state)?;
+        assert!(mempool.contains(&id));
+        assert_eq!(mempool.size(), 1);
+
+        assert!(mempool.remove(&id).is_some());
+        assert!(mempool.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_mempool_rejects_known_zero_value_outputs() -> Result<()> {
+        let state = state_with_outputs(&[]);
+        let tx = balanced_tx(vec![], vec![output(0, 1)], 0);
+        assert!(state.verify_transaction(&tx)?);
+        assert!(matches!(
+            state.mempool().add(tx.clone(), &state),
+            Err(MimblewimbleError::InvalidTransaction)
+        ));
+
+        // Relayed without the opening, the value is hidden
+        let received: Transaction = serialization::from_slice(&serialization::to_vec(&tx)?)?;
+        state.mempool().add(received, &state)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_mempool_survives_restart() -> Result<()> {
+        let (kept, spent) = (output(1000, 1), output(1000, 2));
+        let state = state_with_outputs(&[kept.clone(), spent.clone()]);
+        let mempool = Mempool::new();
+        let kept_id = mempool.add(spend(&kept, 10, 3), &state)?;
+        mempool.add(spend(&spent, 10, 4), &state)?;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("mempool.json");
+        mempool.persist(&path)?;
+
+        // After the restart one of the funding outputs has been spent
+        let restarted = state_with_outputs(std::slice::from_ref(&kept));
+        let restored = restarted.mempool().load(&path, &restarted)?;
+        assert_eq!(restored, vec![kept_id.clone()]);
+        assert_eq!(restarted.mempool().size(), 1);
+        assert!(restarted.mempool().contains(&kept_id));
+        Ok(())
+    }
+
+    #[test]
+    fn test_concurrent_batches_insert_each_transaction_once() -> Result<()> {
+        let funding: Vec<Commitment> = (1..=8).map(|n| output(1000, n)).collect();
+        let state = state_with_outputs(&funding);
+        let valid: Vec<Transaction> = funding
+            .iter()
+            .zip(20..)
+            .map(|(input, blinding)| spend(input, 10, blinding))
+            .collect();
+
+        // Overlapping batches, each with an invalid spend and a double spend
+        // of the output its first transaction spends
+        let batches: Vec<Vec<Transaction>> = (0..4)
+            .map(|n| {
+                let mut batch = valid[n..n + 5].to_vec();
+                batch.push(spend(&output(1000, 9), 10, 30));
+                batch.push(spend(&funding[n], 20, 40 + n as u8));
+                batch
+            })
+            .collect();
+
+        let mempool = Mempool::new();
+        let results: Vec<Vec<Result<TxId>>> = std::thread::scope(|scope| {
+            let handles: Vec<_> = batches
+                .iter()
+                .map(|batch| scope.spawn(|| mempool.add_batch(batch.clone(), &state)))
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        for (batch, results) in batches.iter().zip(&results) {
+            for (tx, result) in batch[..5].iter().zip(results) {
+                assert_eq!(result.as_ref().ok(), Some(&tx.hash()?));
+            }
+            assert!(results[5].is_err());
+            assert!(matches!(results[6], Err(MimblewimbleError::DoubleSpend)));
+        }
+
+        // Each valid transaction is held exactly once
+        assert_eq!(mempool.size(), valid.len());
+        for tx in &valid {
+            assert!(mempool.contains(&tx.hash()?));
+        }
+        assert!(mempool.conflicts().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_relayed_variant_is_deduplicated() -> Result<()> {
+        let funding = output(1000, 1);
+        let state = state_with_outputs(std::slice::from_ref(&funding));
+        let tx = spend(&funding, 10, 2);
+
+        // A relay pads the range proof, which still verifies
+        let mut variant = tx.clone();
+        let proof = &mut variant.outputs_mut()[0].range_proof.proof_data;
+        proof.insert(0, 0);
+        assert_ne!(variant.hash()?, tx.hash()?);
+        assert_eq!(variant.fingerprint(), tx.fingerprint());
+        assert!(state.verify_transaction(&variant)?);
+
+        let mempool = Mempool::new();
+        let id = mempool.add(tx.clone(), &state)?;
+        assert_eq!(mempool.add(variant, &state)?, id);
+        assert_eq!(mempool.size(), 1);
+
+        // Removing the transaction frees its fingerprint
+        mempool.remove(&id);
+        assert_eq!(mempool.add(tx, &state)?, id);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mempool_rejects_double_spend() -> Result<()> {
+        let funding = output(1000, 1);
+        let state = state_with_outputs(std::slice::from_ref(&funding));
+
+        let mempool = Mempool::new();
+        mempool.add(spend(&funding, 10, 2), &state)?;
+        assert!(matches!(
+            mempool.add(spend(&funding, 20, 3), &state),
+            Err(MimblewimbleError::DoubleSpend)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_aggregation_forecast() -> Result<()> {
+        let funding: Vec<Commitment> = (1..=5).map(|n| output(1000, n)).collect();
+        let state = state_with_outputs(&funding);
+        let mempool = Mempool::new();
+        assert_eq!(mempool.aggregation_forecast(&spend(&funding[0], 10, 9)), 0);
+
+        let pending: Vec<Transaction> = (0..3).map(|n| spend(&funding[n], 10, 9)).collect();
+        for tx in &pending {
+            mempool.add(tx.clone(), &state)?;
+        }
+
+        // A new spend joins all three, a pending one the other two
+        assert_eq!(mempool.aggregation_forecast(&spend(&funding[3], 10, 9)), 3);
+        assert_eq!(mempool.aggregation_forecast(&pending[0]), 2);
+
+        // A double spend cannot be aggregated with the transaction it conflicts with
+        assert_eq!(mempool.aggregation_forecast(&spend(&funding[0], 20, 9)), 2);
+
+        // Nor with pending transactions bound to a different anchor
+        let mut anchored = spend(&funding[3], 10, 9);
+        anchored.kernels_mut()[0].anchor = Some(vec![1; 128]);
+        mempool.insert_unchecked(anchored);
+        let mut other = spend(&funding[4], 10, 9);
+        other.kernels_mut()[0].anchor = Some(vec![2; 128]);
+        assert_eq!(mempool.aggregation_forecast(&other), 3);
+        other.kernels_mut()[0].anchor = Some(vec![1; 128]);
+        assert_eq!(mempool.aggregation_forecast(&other), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mempool_selects_highest_fee_first() -> Result<()> {
+        let low = output(1000, 1);
+        let high = output(1000, 2);
+        let state = state_with_outputs(&[low.clone(), high.clone()]);
+
+        let mempool = Mempool::new();
+        mempool.add(spend(&low, 1, 3), &state)?;
+        mempool.add(spend(&high, 50, 4), &state)?;
+
+        let selected = mempool.select(usize::MAX);
+        assert_eq!(selected.len(), 2);
+        assert_eq!(selected[0].fee(), 50);
+
+        let one = mempool.select(selected[0].wire_size()?);
+        assert_eq!(one.len(), 1);
+        assert_eq!(one[0].fee(), 50);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mempool_rate_limits_peers() -> Result<()> {
+        let funding: Vec<Commitment> = (1..=4).map(|n| output(1000, n)).collect();
+        let state = state_with_outputs(&funding);
+        let mempool = Mempool::with_rate_limit(2, 1);
+
+        mempool.add_from_peer("noisy", spend(&funding[0], 10, 10), &state)?;
+        mempool.add_from_peer("noisy", spend(&funding[1], 10, 11), &state)?;
+        assert!(matches!(
+            mempool.add_from_peer("noisy", spend(&funding[2], 10, 12), &state),
+            Err(MimblewimbleError::RateLimited)
+        ));
+
+        // Other peers have their own budget
+        mempool.add_from_peer("quiet", spend(&funding[3], 10, 13), &state)?;
+        assert_eq!(mempool.size(), 3);
+
+        // The budget refills over time
+        let later = Instant::now() + std::time::Duration::from_secs(1);
+        mempool.take_token("noisy", later)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_mempool_bounds_tracked_peers() -> Result<()> {
+        let mempool = Mempool::with_rate_limit(1, 0).with_max_peers(NonZeroUsize::new(2).unwrap());
+        let now = Instant::now();
+
+        mempool.take_token("first", now)?;
+        mempool.take_token("second", now)?;
+        assert!(mempool.take_token("second", now).is_err());
+
+        // A third peer displaces the least recently seen one
+        mempool.take_token("third", now)?;
+        assert_eq!(mempool.peer_buckets.lock().len(), 2);
+        assert!(mempool.take_token("second", now).is_err());
+        mempool.take_token("first", now)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_mempool_evicts_transactions_made_stale_by_a_block() -> Result<()> {
+        let (shared, other) = (output(1000, 1), output(1000, 2));
+        let state = MimblewimbleState {
+            parameters: std::sync::Arc::new(crate::MimblewimbleParameters::regtest()),
+            ..state_with_outputs(&[shared.clone(), other.clone()])
+        };
+
+        // The mempool holds one spend of the shared output, a block mines another
+        let pending = state
+            .mempool()
+            .add(spend_on(&state.parameters(), &shared, 10, 3), &state)?;
+        let kept = state
+            .mempool()
+            .add(spend_on(&state.parameters(), &other, 10, 4), &state)?;
+        let mut template = state.block_template(&[5; 32])?;
+        let transactions = vec![
+            template.transactions[0].clone(),
+            spend_on(&state.parameters(), &shared, 20, 6),
+        ];
+        template.header.kernel_root = state.chain.read().projected_kernel_root(&transactions);
+        template.header.merkle_root = crate::block::compute_merkle_root(&transactions)?;
+        template.header.output_root = state.chain.read().projected_output_root(&transactions);
+        state.accept_block(crate::Block {
+            header: template.header,
+            transactions,
+        })?;
+        assert!(!state.mempool().contains(&pending));
+        assert!(state.mempool().contains(&kept));
+        assert!(state.mempool().revalidate(&state).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_mempool_conflicts() -> Result<()> {
+        let (shared, other) = (output(1000, 1), output(1000, 2));
+        let state = state_with_outputs(&[shared.clone(), other.clone()]);
+
+        let mempool = Mempool::new();
+        let first = mempool.add(spend(&shared, 10, 3), &state)?;
+        mempool.add(spend(&other, 10, 4), &state)?;
+        assert!(mempool.conflicts().is_empty());
+
+        let second = mempool.insert_unchecked(spend(&shared, 20, 5));
+        assert_eq!(mempool.conflicts(), vec![(first, second)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_locked_transaction_is_deferred_until_its_height() -> Result<()> {
+        let funding = output(1000, 1);
+        let state = MimblewimbleState {
+            parameters: std::sync::Arc::new(crate::MimblewimbleParameters::regtest()),
+            ..state_with_outputs(std::slice::from_ref(&funding))
+        };
+
+        let id = state.mempool().add(locked_spend(&state, 1, 2)?, &state)?;
+        assert!(!state.mempool().contains(&id));
+        assert_eq!(state.mempool().deferred_size(), 1);
+
+        // Block 0 leaves the next height at 1, still below the lock
+        state.submit_block(state.block_template(&[3; 32])?.into_block(0))?;
+        assert_eq!(state.mempool().deferred_size(), 1);
+
+        // After block 1 the next block may include it
+        state.submit_block(state.block_template(&[4; 32])?.into_block(0))?;
+        assert!(state.mempool().contains(&id));
+        assert_eq!(state.mempool().deferred_size(), 0);
+
+        let block = state.block_template(&[5; 32])?.into_block(0);
+        assert_eq!(block.header.height, 2);
+        assert_eq!(block.transaction_count(), 2);
+        state.submit_block(block)?;
+        assert!(state.mempool().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_deferred_queue_is_bounded_and_conflict_free() -> Result<()> {
+        let state = MimblewimbleState {
+            parameters: std::sync::Arc::new(crate::MimblewimbleParameters::regtest()),
+            ..state_with_outputs(&[output(1000, 1), output(1000, 3)])
+        };
+        let mempool = Mempool::new().with_max_deferred(1);
+
+        let locked = locked_spend(&state, 1, 2)?;
+        let id = mempool.add(locked.clone(), &state)?;
+        assert_eq!(mempool.add(locked, &state)?, id);
+        assert_eq!(mempool.deferred_size(), 1);
+
+        // A second deferred spend of the same output is a double spend
+        assert!(matches!(
+            mempool.add(locked_spend(&state, 1, 4)?, &state),
+            Err(MimblewimbleError::DoubleSpend)
+        ));
+
+        // An unrelated transaction finds the queue full
+        assert!(matches!(
+            mempool.add(locked_spend(&state, 3, 4)?, &state),
+            Err(MimblewimbleError::PoolFull)
+        ));
+        assert_eq!(mempool.deferred_size(), 1);
+        Ok(())
+    }
+}
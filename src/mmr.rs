@@ -0,0 +1,361 @@
+//! Merkle mountain range over output commitments
+//!
+//! The range is a list of perfect binary trees ("peaks") whose sizes are the
+//! powers of two in the leaf count, largest first. The root bags the peaks
+//! from right to left, so a membership proof is the sibling path within the
+//! leaf's peak plus the other peak hashes.
+//!
+//! Only leaf hashes are stored; interior nodes are recomputed from them, so
+//! pruning a leaf's commitment never changes the root.
+
+use crate::errors::MimblewimbleError;
+use hex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha512};
+
+/// Domain prefix for leaf hashes
+const LEAF_PREFIX: u8 = 0;
+
+/// Domain prefix for interior node hashes
+const NODE_PREFIX: u8 = 1;
+
+/// Proof that a commitment is a leaf of a merkle mountain range
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MembershipProof {
+    /// Commitment proven to be present
+    pub commitment: Vec<u8>,
+
+    /// Index of the leaf within its peak
+    pub index: u64,
+
+    /// Sibling hashes from the leaf up to its peak
+    pub path: Vec<Vec<u8>>,
+
+    /// Hashes of all peaks, largest first
+    pub peaks: Vec<Vec<u8>>,
+
+    /// Position of the leaf's peak in `peaks`
+    pub peak: usize,
+}
+
+/// Merkle mountain range
+///
+/// Serializes to its leaf hashes and unpruned commitments. Deserializing
+/// checks that every retained commitment matches its leaf hash.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(try_from = "StoredRange")]
+pub struct MerkleMountainRange {
+    /// Leaf hashes in insertion order
+    leaves: Vec<Vec<u8>>,
+
+    /// Leaf commitments in insertion order, `None` once pruned
+    commitments: Vec<Option<Vec<u8>>>,
+}
+
+/// Serialized form of a merkle mountain range, checked before use
+#[derive(Deserialize)]
+struct StoredRange {
+    leaves: Vec<Vec<u8>>,
+    commitments: Vec<Option<Vec<u8>>>,
+}
+
+impl TryFrom<StoredRange> for MerkleMountainRange {
+    type Error = MimblewimbleError;
+
+    fn try_from(stored: StoredRange) -> Result<Self, Self::Error> {
+        let consistent = stored.leaves.len() == stored.commitments.len()
+            && stored
+                .leaves
+                .iter()
+                .zip(&stored.commitments)
+                .all(|(leaf, commitment)| {
+                    commitment
+                        .as_ref()
+                        .is_none_or(|commitment| &hash_leaf(commitment) == leaf)
+                });
+        if !consistent {
+            return Err(MimblewimbleError::SerializationError(
+                "inconsistent merkle mountain range".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            leaves: stored.leaves,
+            commitments: stored.commitments,
+        })
+    }
+}
+
+impl MerkleMountainRange {
+    /// Create an empty range
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Build a range from commitments in order
+    pub fn from_commitments(commitments: &[Vec<u8>]) -> Self {
+        let mut mmr = Self::new();
+        for commitment in commitments {
+            mmr.push(commitment.clone());
+        }
+        mmr
+    }
+
+    /// Append a commitment
+    pub fn push(&mut self, commitment: Vec<u8>) {
+        self.leaves.push(hash_leaf(&commitment));
+        self.commitments.push(Some(commitment));
+    }
+
+    /// Drop the leaves after the first `len`, as when rewinding the chain
+    pub fn truncate(&mut self, len: usize) {
+        self.leaves.truncate(len);
+        self.commitments.truncate(len);
+    }
+
+    /// Discard a leaf's commitment while keeping its hash
+    ///
+    /// Returns whether an unpruned leaf with the commitment was found. The
+    /// root is unchanged, but the leaf can no longer be proven. A changed
+    /// root would fork the chain, so debug builds assert it.
+    pub fn prune(&mut self, commitment: &[u8]) -> bool {
+        #[cfg(debug_assertions)]
+        let root = self.root();
+
+        let pruned = match self
+            .commitments
+            .iter_mut()
+            .find(|c| c.as_deref() == Some(commitment))
+        {
+            Some(leaf) => {
+                *leaf = None;
+                true
+            }
+            None => false,
+        };
+
+        #[cfg(debug_assertions)]
+        debug_assert_eq!(self.root(), root, "pruning changed the range root");
+        pruned
+    }
+
+    /// Number of leaves
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    /// Check whether the range is empty
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    /// Root hash of the range
+    pub fn root(&self) -> Vec<u8> {
+        bag_peaks(&self.peaks())
+    }
+
+    /// Prove that a commitment is a leaf of the range
+    pub fn prove_membership(&self, commitment: &[u8]) -> Option<MembershipProof> {
+        let position = self
+            .commitments
+            .iter()
+            .position(|c| c.as_deref() == Some(commitment))?;
+
+        let mut start = 0;
+        for (peak, size) in peak_sizes(self.leaves.len()).into_iter().enumerate() {
+            if position < start + size {
+                let index = position - start;
+                return Some(MembershipProof {
+                    commitment: commitment.to_vec(),
+                    index: index as u64,
+                    path: sibling_path(&self.leaves[start..start + size], index),
+                    peaks: self.peaks(),
+                    peak,
+                });
+            }
+            start += size;
+        }
+
+        None
+    }
+
+    /// Hashes of all peaks, largest first
+    fn peaks(&self) -> Vec<Vec<u8>> {
+        let mut start = 0;
+        peak_sizes(self.leaves.len())
+            .into_iter()
+            .map(|size| {
+                let peak = tree_root(&self.leaves[start..start + size]);
+                start += size;
+                peak
+            })
+            .collect()
+    }
+}
+
+/// Verify a membership proof against a range root
+pub fn verify_membership(proof: &MembershipProof, root: &[u8]) -> bool {
+    let peak = match proof.peaks.get(proof.peak) {
+        Some(peak) => peak,
+        None => return false,
+    };
+
+    if proof.path.len() >= 64 || proof.index >> proof.path.len() != 0 {
+        return false;
+    }
+
+    let mut hash = hash_leaf(&proof.commitment);
+    for (level, sibling) in proof.path.iter().enumerate() {
+        hash = if proof.index >> level & 1 == 0 {
+            hash_node(&hash, sibling)
+        } else {
+            hash_node(sibling, &hash)
+        };
+    }
+
+    &hash == peak && bag_peaks(&proof.peaks) == root
+}
+
+/// Sizes of the peaks for a leaf count, largest first
+fn peak_sizes(leaf_count: usize) -> Vec<usize> {
+    (0..usize::BITS)
+        .rev()
+        .map(|bit| 1usize << bit)
+        .filter(|size| leaf_count & size != 0)
+        .collect()
+}
+
+/// Root of a perfect binary tree of leaf hashes
+fn tree_root(leaves: &[Vec<u8>]) -> Vec<u8> {
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| hash_node(&pair[0], &pair[1]))
+            .collect();
+    }
+    level.pop().unwrap_or_default()
+}
+
+/// Sibling hashes from a leaf up to the root of a perfect binary tree
+fn sibling_path(leaves: &[Vec<u8>], mut index: usize) -> Vec<Vec<u8>> {
+    let mut path = Vec::new();
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        path.push(level[index ^ 1].clone());
+        level = level
+            .chunks(2)
+            .map(|pair| hash_node(&pair[0], &pair[1]))
+            .collect();
+        index /= 2;
+    }
+    path
+}
+
+/// Combine peaks from right to left into the range root
+fn bag_peaks(peaks: &[Vec<u8>]) -> Vec<u8> {
+    let mut peaks = peaks.iter().rev();
+    let last = match peaks.next() {
+        Some(last) => last.clone(),
+        None => return hash_node(&[], &[]),
+    };
+    peaks.fold(last, |acc, peak| hash_node(peak, &acc))
+}
+
+/// Hash a leaf commitment
+fn hash_leaf(commitment: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha512::new();
+    hasher.update([LEAF_PREFIX]);
+    hasher.update(commitment);
+    hex::encode(hasher.finalize()).into_bytes()
+}
+
+/// Hash two child nodes
+fn hash_node(left: &[u8], right: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha512::new();
+    hasher.update([NODE_PREFIX]);
+    hasher.update(left);
+    hasher.update(right);
+    hex::encode(hasher.finalize()).into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_membership_proofs() {
+        let commitments: Vec<Vec<u8>> = (0..7u8).map(|n| vec![n; 67]).collect();
+        let mmr = MerkleMountainRange::from_commitments(&commitments);
+        let root = mmr.root();
+
+        for commitment in &commitments {
+            let proof = mmr.prove_membership(commitment).unwrap();
+            assert!(verify_membership(&proof, &root));
+        }
+
+        // Absent commitments have no proof, and forged proofs fail
+        assert!(mmr.prove_membership(&[9; 67]).is_none());
+        let mut forged = mmr.prove_membership(&commitments[3]).unwrap();
+        forged.commitment = vec![9; 67];
+        assert!(!verify_membership(&forged, &root));
+
+        let mut grown = mmr.clone();
+        grown.push(vec![9; 67]);
+        let stale = mmr.prove_membership(&commitments[3]).unwrap();
+        assert!(!verify_membership(&stale, &grown.root()));
+    }
+
+    #[test]
+    fn test_pruning_preserves_root() {
+        let commitments: Vec<Vec<u8>> = (0..11u8).map(|n| vec![n; 67]).collect();
+        let mut mmr = MerkleMountainRange::from_commitments(&commitments);
+        let root = mmr.root();
+
+        // Leaves across every peak, including the single-leaf one
+        for index in [0, 5, 10, 7, 1] {
+            assert!(mmr.prune(&commitments[index]));
+            assert_eq!(mmr.root(), root);
+            assert!(mmr.prove_membership(&commitments[index]).is_none());
+        }
+
+        let proof = mmr.prove_membership(&commitments[6]).unwrap();
+        assert!(verify_membership(&proof, &root));
+
+        // Growing a pruned range matches growing the unpruned one
+        let mut unpruned = MerkleMountainRange::from_commitments(&commitments);
+        mmr.push(vec![11; 67]);
+        unpruned.push(vec![11; 67]);
+        assert_eq!(mmr.root(), unpruned.root());
+    }
+
+    #[test]
+    fn test_round_trip_with_pruned_leaves() -> crate::errors::Result<()> {
+        let commitments: Vec<Vec<u8>> = (0..7u8).map(|n| vec![n; 67]).collect();
+        let mut mmr = MerkleMountainRange::from_commitments(&commitments);
+        let root = mmr.root();
+
+        assert!(mmr.prune(&commitments[1]));
+        assert!(mmr.prune(&commitments[4]));
+        assert!(!mmr.prune(&commitments[4]));
+
+        let bytes = crate::serialization::to_vec(&mmr)?;
+        let reloaded: MerkleMountainRange = crate::serialization::from_slice(&bytes)?;
+        assert_eq!(reloaded.len(), 7);
+        assert_eq!(reloaded.root(), root);
+
+        // Pruned leaves cannot be proven, the rest still can
+        assert!(reloaded.prove_membership(&commitments[1]).is_none());
+        let proof = reloaded.prove_membership(&commitments[5]).unwrap();
+        assert!(verify_membership(&proof, &root));
+
+        // Retained commitments must match their leaf hashes
+        let mut corrupted: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        corrupted["commitments"][0] = serde_json::json!(vec![9u8; 67]);
+        assert!(crate::serialization::from_slice::<MerkleMountainRange>(
+            &serde_json::to_vec(&corrupted).unwrap()
+        )
+        .is_err());
+        Ok(())
+    }
+}
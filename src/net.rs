@@ -0,0 +1,396 @@
+//! Network messages for Mimblewimble
+//!
+//! Messages are framed with a 4-byte big-endian length prefix followed by
+//! the JSON-encoded message. With the `tokio` feature, `FramedReader` and
+//! `FramedWriter` carry frames over async streams.
+
+use crate::block::{self, Block, BlockHeader};
+use crate::errors::{MimblewimbleError, Result};
+use crate::parameters::MimblewimbleParameters;
+use crate::serialization;
+use crate::transaction::Transaction;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "tokio")]
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Length of the frame length prefix (bytes)
+pub const FRAME_PREFIX_LENGTH: usize = 4;
+
+/// Maximum encoded message size (bytes)
+pub const MAX_FRAME_SIZE: usize = 32_000_000;
+
+/// Size of the smallest encoded transaction (bytes)
+pub const MIN_TRANSACTION_SIZE: usize = 59;
+
+/// Network message
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum NetMessage {
+    /// Request a range of a block's transactions
+    GetBlockTxs {
+        /// Hash of the requested block
+        block_hash: Vec<u8>,
+
+        /// Index of the first transaction
+        start: u32,
+
+        /// Number of transactions
+        count: u32,
+    },
+
+    /// A range of a block's transactions
+    BlockTxs {
+        /// Hash of the block
+        block_hash: Vec<u8>,
+
+        /// Index of the first transaction
+        start: u32,
+
+        /// Total number of transactions in the block
+        total: u32,
+
+        /// Transactions in the range
+        transactions: Vec<Transaction>,
+    },
+}
+
+impl NetMessage {
+    /// Encode the message into a length-prefixed frame
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let payload = serialization::to_vec(self)?;
+
+        if payload.len() > MAX_FRAME_SIZE {
+            return Err(MimblewimbleError::SerializationError(
+                "message exceeds maximum frame size".to_string(),
+            ));
+        }
+
+        let mut frame = Vec::with_capacity(FRAME_PREFIX_LENGTH + payload.len());
+        frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&payload);
+        Ok(frame)
+    }
+
+    /// Decode a message from a complete length-prefixed frame
+    pub fn decode(frame: &[u8]) -> Result<Self> {
+        if frame.len() < FRAME_PREFIX_LENGTH {
+            return Err(MimblewimbleError::SerializationError(
+                "truncated frame".to_string(),
+            ));
+        }
+
+        let (prefix, payload) = frame.split_at(FRAME_PREFIX_LENGTH);
+        let mut length = [0u8; FRAME_PREFIX_LENGTH];
+        length.copy_from_slice(prefix);
+        if u32::from_be_bytes(length) as usize != payload.len() {
+            return Err(MimblewimbleError::SerializationError(
+                "frame length mismatch".to_string(),
+            ));
+        }
+
+        let message: Self = serialization::from_slice(payload)?;
+        message.validate_encoding()?;
+        Ok(message)
+    }
+
+    /// Check the point encodings of any carried transactions
+    fn validate_encoding(&self) -> Result<()> {
+        match self {
+            NetMessage::GetBlockTxs { .. } => Ok(()),
+            NetMessage::BlockTxs { transactions, .. } => transactions
+                .iter()
+                .try_for_each(Transaction::validate_encoding),
+        }
+    }
+}
+
+/// Reader of length-prefixed messages from an async stream
+#[cfg(feature = "tokio")]
+#[derive(Debug)]
+pub struct FramedReader<R> {
+    reader: R,
+}
+
+#[cfg(feature = "tokio")]
+impl<R: AsyncRead + Unpin> FramedReader<R> {
+    /// Wrap an async reader
+    pub fn new(reader: R) -> Self {
+        Self { reader }
+    }
+
+    /// Read the next message, or `None` if the stream ended between frames
+    pub async fn read_message(&mut self) -> Result<Option<NetMessage>> {
+        let mut frame = vec![0u8; FRAME_PREFIX_LENGTH];
+        match self.reader.read_exact(&mut frame).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(MimblewimbleError::IoError(e.to_string())),
+        }
+
+        let mut length = [0u8; FRAME_PREFIX_LENGTH];
+        length.copy_from_slice(&frame);
+        let length = u32::from_be_bytes(length) as usize;
+        if length > MAX_FRAME_SIZE {
+            return Err(MimblewimbleError::SerializationError(
+                "message exceeds maximum frame size".to_string(),
+            ));
+        }
+
+        frame.resize(FRAME_PREFIX_LENGTH + length, 0);
+        self.reader
+            .read_exact(&mut frame[FRAME_PREFIX_LENGTH..])
+            .await
+            .map_err(|e| MimblewimbleError::IoError(e.to_string()))?;
+
+        NetMessage::decode(&frame).map(Some)
+    }
+
+    /// Unwrap the underlying reader
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+/// Writer of length-prefixed messages to an async stream
+#[cfg(feature = "tokio")]
+#[derive(Debug)]
+pub struct FramedWriter<W> {
+    writer: W,
+}
+
+#[cfg(feature = "tokio")]
+impl<W: AsyncWrite + Unpin> FramedWriter<W> {
+    /// Wrap an async writer
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    /// Write a message as a single frame and flush it
+    pub async fn write_message(&mut self, message: &NetMessage) -> Result<()> {
+        let frame = message.encode()?;
+        self.writer
+            .write_all(&frame)
+            .await
+            .map_err(|e| MimblewimbleError::IoError(e.to_string()))?;
+        self.writer
+            .flush()
+            .await
+            .map_err(|e| MimblewimbleError::IoError(e.to_string()))
+    }
+
+    /// Unwrap the underlying writer
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Block being reassembled from transaction ranges
+#[derive(Debug, Clone)]
+pub struct PartialBlock {
+    /// Header of the block
+    header: BlockHeader,
+
+    /// Hash of the header, which ranges must name
+    block_hash: Vec<u8>,
+
+    /// Received transactions by index
+    transactions: Vec<Option<Transaction>>,
+}
+
+impl PartialBlock {
+    /// Start reassembling a block with a known header and transaction count
+    ///
+    /// The count comes from a peer, so it may not exceed the transactions a
+    /// block of `max_block_size` can hold.
+    pub fn new(
+        header: BlockHeader,
+        total: usize,
+        parameters: &MimblewimbleParameters,
+    ) -> Result<Self> {
+        if total > parameters.max_block_size / MIN_TRANSACTION_SIZE {
+            return Err(MimblewimbleError::InvalidBlock);
+        }
+
+        Ok(Self {
+            block_hash: header.hash()?,
+            header,
+            transactions: vec![None; total],
+        })
+    }
+
+    /// Add a received range of transactions of the block `block_hash`
+    pub fn add_range(
+        &mut self,
+        block_hash: &[u8],
+        start: usize,
+        transactions: Vec<Transaction>,
+    ) -> Result<()> {
+        if block_hash != self.block_hash {
+            return Err(MimblewimbleError::InvalidParameter);
+        }
+
+        let end = start
+            .checked_add(transactions.len())
+            .filter(|end| *end <= self.transactions.len())
+            .ok_or(MimblewimbleError::InvalidParameter)?;
+
+        for (slot, tx) in self.transactions[start..end].iter_mut().zip(transactions) {
+            *slot = Some(tx);
+        }
+        Ok(())
+    }
+
+    /// Check whether every transaction has been received
+    pub fn is_complete(&self) -> bool {
+        self.transactions.iter().all(Option::is_some)
+    }
+
+    /// Finish reassembly, checking the transactions against the merkle root
+    pub fn finish(self) -> Result<Block> {
+        let transactions: Vec<Transaction> = self
+            .transactions
+            .into_iter()
+            .collect::<Option<_>>()
+            .ok_or(MimblewimbleError::InvalidBlock)?;
+
+        if block::compute_merkle_root(&transactions)? != self.header.merkle_root {
+            return Err(MimblewimbleError::InvalidBlock);
+        }
+
+        Ok(Block {
+            header: self.header,
+            transactions,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_round_trip() -> Result<()> {
+        let message = NetMessage::GetBlockTxs {
+            block_hash: vec![7; 128],
+            start: 3,
+            count: 10,
+        };
+
+        let frame = message.encode()?;
+        match NetMessage::decode(&frame)? {
+            NetMessage::GetBlockTxs {
+                block_hash,
+                start,
+                count,
+            } => {
+                assert_eq!(block_hash, vec![7; 128]);
+                assert_eq!((start, count), (3, 10));
+            }
+            other => panic!("unexpected message: {:?}", other),
+        }
+
+        assert!(NetMessage::decode(&frame[..frame.len() - 1]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_rejects_malformed_points() -> Result<()> {
+        let params = crate::MimblewimbleParameters::default();
+        let coinbase = Transaction::coinbase(0, 0, &[3; 32], &params)?;
+        let message = |transactions: Vec<Transaction>| NetMessage::BlockTxs {
+            block_hash: vec![7; 128],
+            start: 0,
+            total: 1,
+            transactions,
+        };
+
+        assert!(NetMessage::decode(&message(vec![coinbase.clone()]).encode()?).is_ok());
+
+        // Correct length, but not a point on the curve
+        let mut malformed = coinbase.clone();
+        malformed.outputs_mut()[0].commitment = vec![0xff; crate::crypto::POINT_LENGTH];
+        assert!(matches!(
+            NetMessage::decode(&message(vec![malformed]).encode()?),
+            Err(MimblewimbleError::InvalidCommitment)
+        ));
+
+        let mut malformed = coinbase;
+        malformed.kernels_mut()[0].excess = vec![0xff; crate::crypto::POINT_LENGTH];
+        assert!(matches!(
+            NetMessage::decode(&message(vec![malformed]).encode()?),
+            Err(MimblewimbleError::InvalidKernel)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_partial_block_caps_transaction_count() -> Result<()> {
+        let emptiest: Transaction = serialization::from_slice(
+            br#"{"version":0,"inputs":[],"outputs":[],"kernels":[],"fee":0}"#,
+        )?;
+        assert_eq!(
+            serialization::serialized_size(&emptiest)?,
+            MIN_TRANSACTION_SIZE
+        );
+
+        let params = MimblewimbleParameters::default();
+        let header = BlockHeader {
+            version: 1,
+            height: 0,
+            timestamp: 0,
+            previous_hash: vec![0; block::HASH_LENGTH],
+            merkle_root: vec![0; block::HASH_LENGTH],
+            output_root: vec![0; block::HASH_LENGTH],
+            kernel_root: vec![0; block::HASH_LENGTH],
+            difficulty: 1,
+            nonce: 0,
+        };
+        let most = params.max_block_size / MIN_TRANSACTION_SIZE;
+        assert!(PartialBlock::new(header.clone(), most, &params).is_ok());
+        assert!(matches!(
+            PartialBlock::new(header, most + 1, &params),
+            Err(MimblewimbleError::InvalidBlock)
+        ));
+        Ok(())
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_framed_duplex_round_trip() -> Result<()> {
+        // A tiny pipe buffer forces frames to be split across many reads
+        let (client, server) = tokio::io::duplex(7);
+        let mut writer = FramedWriter::new(client);
+        let mut reader = FramedReader::new(server);
+
+        let messages: Vec<NetMessage> = (0..5)
+            .map(|n| NetMessage::GetBlockTxs {
+                block_hash: vec![n; 128],
+                start: u32::from(n),
+                count: 10,
+            })
+            .collect();
+
+        let sent = messages.clone();
+        let sender = tokio::spawn(async move {
+            for message in &sent {
+                writer.write_message(message).await?;
+            }
+            Ok::<_, MimblewimbleError>(())
+        });
+
+        for n in 0..messages.len() as u8 {
+            match reader.read_message().await? {
+                Some(NetMessage::GetBlockTxs {
+                    block_hash, start, ..
+                }) => {
+                    assert_eq!(block_hash, vec![n; 128]);
+                    assert_eq!(start, u32::from(n));
+                }
+                other => panic!("unexpected message: {:?}", other),
+            }
+        }
+
+        sender.await.unwrap()?;
+        assert!(reader.read_message().await?.is_none());
+        Ok(())
+    }
+}
@@ -1,11 +1,57 @@
 //! Mimblewimble protocol parameters
 
 use crate::errors::{MimblewimbleError, Result};
+use crate::pow::{self, PowAlgorithm, PowMode};
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// Network a chain belongs to
+///
+/// Kernel signatures commit to the network, so transactions cannot be
+/// replayed from one network onto another.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum Network {
+    /// Main network
+    #[default]
+    Mainnet,
+
+    /// Public test network
+    Testnet,
+
+    /// Local regression test chains
+    Regtest,
+}
+
+impl Network {
+    /// Byte identifying the network in signed messages
+    pub fn id(self) -> u8 {
+        match self {
+            Network::Mainnet => 0,
+            Network::Testnet => 1,
+            Network::Regtest => 2,
+        }
+    }
+}
+
+/// Handling of fractional block rewards after a halving
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum RewardRounding {
+    /// Every block in era `k` pays `initial >> k`, discarding the fraction
+    #[default]
+    Truncate,
+
+    /// Fractions carry to the next block, so block `i` of era `k` pays
+    /// `(((i + 1) * initial) >> k) - ((i * initial) >> k)` and the era emits
+    /// `(interval * initial) >> k` in total
+    CarryRemainder,
+}
 
 /// Mimblewimble protocol parameters
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MimblewimbleParameters {
+    /// Network the chain belongs to
+    pub network: Network,
+
     /// Commitment scheme identifier
     pub commitment_scheme: String,
 
@@ -21,6 +67,9 @@ pub struct MimblewimbleParameters {
     /// Target block time (seconds)
     pub target_block_time: u64,
 
+    /// Maximum time a block timestamp may lie ahead of the local clock (seconds)
+    pub max_future_drift: u64,
+
     /// Difficulty adjustment interval (blocks)
     pub difficulty_adjustment_interval: u64,
 
@@ -32,25 +81,113 @@ pub struct MimblewimbleParameters {
 
     /// Maximum UTXO set size
     pub max_utxo_set_size: usize,
+
+    /// Maximum number of kernels aggregated into one transaction
+    pub max_kernels_per_tx: usize,
+
+    /// Require outputs and kernels of block transactions in canonical order
+    pub enforce_canonical_order: bool,
+
+    /// Create outputs as switch commitments
+    pub use_switch_commitments: bool,
+
+    /// Minimum fee per unit of transaction weight (zero disables the check)
+    pub min_fee_per_weight: u64,
+
+    /// Maximum fee of a single kernel (`None` disables the check)
+    pub max_fee: Option<u64>,
+
+    /// Admit to the mempool outputs known to commit to a value of zero
+    pub allow_zero_value_outputs: bool,
+
+    /// Reject outputs whose range proof is absent, for nodes that do not
+    /// trust another component to have verified them
+    pub require_range_proofs: bool,
+
+    /// Maximum outputs per input of non-coinbase transactions, counting at
+    /// least one input (zero disables the check)
+    pub max_output_to_input_ratio: usize,
+
+    /// Hold transactions locked past the next block in the mempool until
+    /// their lock height, rather than rejecting them
+    pub defer_locked_transactions: bool,
+
+    /// Most recent blocks a transaction may anchor to, counting the tip
+    pub anchor_window: u64,
+
+    /// Decoded points cached for balance verification (zero disables the cache)
+    pub point_cache_size: usize,
+
+    /// Transactions whose verification is cached between the mempool and
+    /// block validation (zero disables the cache)
+    pub verification_cache_size: usize,
+
+    /// Difficulty of the first blocks before any retarget
+    pub initial_difficulty: u64,
+
+    /// Block reward before the first halving
+    pub initial_block_reward: u64,
+
+    /// Reward halving interval (blocks)
+    pub reward_halving_interval: u64,
+
+    /// Handling of fractional rewards left by halving an odd reward
+    pub reward_rounding: RewardRounding,
+
+    /// Proof of work enforcement mode
+    pub pow_mode: PowMode,
+
+    /// Proof of work algorithm, configured in code rather than serialized
+    #[serde(skip, default = "pow::default_algorithm")]
+    pub pow_algorithm: Arc<dyn PowAlgorithm>,
 }
 
 impl Default for MimblewimbleParameters {
     fn default() -> Self {
         Self {
+            network: Network::Mainnet,
             commitment_scheme: "pedersen".to_string(),
             range_proof_bits: 64,
             max_transaction_size: 1_000_000, // 1 MB
             max_block_size: 10_000_000,      // 10 MB
             target_block_time: 30,
+            max_future_drift: 720,
             difficulty_adjustment_interval: 2016,
             pruning_enabled: true,
             pruning_interval: 10_000,
             max_utxo_set_size: 1_000_000,
+            max_kernels_per_tx: 1_000,
+            enforce_canonical_order: true,
+            use_switch_commitments: false,
+            min_fee_per_weight: 0,
+            max_fee: None,
+            allow_zero_value_outputs: false,
+            require_range_proofs: true,
+            max_output_to_input_ratio: 100,
+            defer_locked_transactions: true,
+            anchor_window: 2_880,
+            point_cache_size: 65_536,
+            verification_cache_size: 16_384,
+            initial_difficulty: 1_000,
+            initial_block_reward: 5_000_000_000,
+            reward_halving_interval: 4_200_000,
+            reward_rounding: RewardRounding::Truncate,
+            pow_mode: PowMode::Real,
+            pow_algorithm: pow::default_algorithm(),
         }
     }
 }
 
 impl MimblewimbleParameters {
+    /// Parameters for regtest chains, which accept any proof of work nonce
+    pub fn regtest() -> Self {
+        Self {
+            network: Network::Regtest,
+            pow_mode: PowMode::Trivial,
+            ..Self::default()
+        }
+    }
+
     /// Validate parameters
     pub fn validate(&self) -> Result<()> {
         if self.range_proof_bits < 32 {
@@ -65,6 +202,10 @@ impl MimblewimbleParameters {
             return Err(MimblewimbleError::InvalidParameter);
         }
 
+        if self.anchor_window == 0 {
+            return Err(MimblewimbleError::InvalidParameter);
+        }
+
         if self.target_block_time == 0 {
             return Err(MimblewimbleError::InvalidParameter);
         }
@@ -73,8 +214,59 @@ impl MimblewimbleParameters {
             return Err(MimblewimbleError::InvalidParameter);
         }
 
+        if self.max_kernels_per_tx == 0 {
+            return Err(MimblewimbleError::InvalidParameter);
+        }
+
+        if self.initial_difficulty == 0 {
+            return Err(MimblewimbleError::InvalidParameter);
+        }
+
+        if self.reward_halving_interval == 0 {
+            return Err(MimblewimbleError::InvalidParameter);
+        }
+
         Ok(())
     }
+
+    /// Block reward at a given height
+    pub fn block_reward(&self, height: u64) -> u64 {
+        let halvings = height / self.reward_halving_interval;
+        if halvings >= 64 {
+            return 0;
+        }
+
+        match self.reward_rounding {
+            RewardRounding::Truncate => self.initial_block_reward >> halvings,
+            RewardRounding::CarryRemainder => {
+                let index = u128::from(height % self.reward_halving_interval);
+                let initial = u128::from(self.initial_block_reward);
+                ((((index + 1) * initial) >> halvings) - ((index * initial) >> halvings)) as u64
+            }
+        }
+    }
+
+    /// Total reward paid by all blocks below `height`
+    ///
+    /// Each complete era `k` emits `interval * (initial >> k)` when
+    /// truncating and `(interval * initial) >> k` when carrying remainders;
+    /// the final partial era emits the same for its block count.
+    pub fn total_supply(&self, height: u64) -> u128 {
+        let interval = u128::from(self.reward_halving_interval);
+        let initial = u128::from(self.initial_block_reward);
+        let emitted = |blocks: u128, halvings: u64| match self.reward_rounding {
+            RewardRounding::Truncate => blocks * (initial >> halvings),
+            RewardRounding::CarryRemainder => (blocks * initial) >> halvings,
+        };
+
+        let eras = height / self.reward_halving_interval;
+        let partial = u128::from(height % self.reward_halving_interval);
+        let complete: u128 = (0..eras.min(64)).map(|k| emitted(interval, k)).sum();
+        if eras >= 64 {
+            return complete;
+        }
+        complete + emitted(partial, eras)
+    }
 }
 
 #[cfg(test)]
@@ -95,4 +287,59 @@ mod tests {
         params.range_proof_bits = 16;
         assert!(params.validate().is_err());
     }
+
+    #[test]
+    fn test_block_reward_halving() {
+        let params = MimblewimbleParameters::default();
+        let interval = params.reward_halving_interval;
+
+        assert_eq!(params.block_reward(0), params.initial_block_reward);
+        assert_eq!(
+            params.block_reward(interval - 1),
+            params.initial_block_reward
+        );
+        assert_eq!(
+            params.block_reward(interval),
+            params.initial_block_reward / 2
+        );
+        assert_eq!(params.block_reward(interval * 64), 0);
+    }
+
+    #[test]
+    fn test_total_supply_matches_summed_rewards() {
+        for rounding in [RewardRounding::Truncate, RewardRounding::CarryRemainder] {
+            // An odd reward leaves a fraction at every halving
+            let params = MimblewimbleParameters {
+                initial_block_reward: 7,
+                reward_halving_interval: 4,
+                reward_rounding: rounding,
+                ..Default::default()
+            };
+
+            let mut summed = 0u128;
+            for height in 0..20 {
+                assert_eq!(params.total_supply(height), summed);
+                summed += u128::from(params.block_reward(height));
+            }
+        }
+
+        let carry = MimblewimbleParameters {
+            initial_block_reward: 7,
+            reward_halving_interval: 4,
+            reward_rounding: RewardRounding::CarryRemainder,
+            ..Default::default()
+        };
+        // Era 1 pays 3.5 per block: 3, 4, 3, 4 rather than 3, 3, 3, 3
+        let era: Vec<u64> = (4..8).map(|h| carry.block_reward(h)).collect();
+        assert_eq!(era, vec![3, 4, 3, 4]);
+        assert_eq!(carry.total_supply(8), 28 + 14);
+        assert_eq!(
+            MimblewimbleParameters {
+                reward_rounding: RewardRounding::Truncate,
+                ..carry.clone()
+            }
+            .total_supply(8),
+            28 + 12
+        );
+    }
 }
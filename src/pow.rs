@@ -0,0 +1,255 @@
+//! Proof of work for Mimblewimble blocks
+
+use crate::block::BlockHeader;
+use crate::errors::{MimblewimbleError, Result};
+use crate::parameters::MimblewimbleParameters;
+use crate::serialization;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha512};
+use std::fmt;
+use std::sync::Arc;
+
+/// Proof of work enforcement mode
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum PowMode {
+    /// Enforce the difficulty target
+    #[default]
+    Real,
+
+    /// Accept any nonce (regtest chains)
+    Trivial,
+}
+
+/// Proof of work algorithm
+///
+/// Integrators plug in other algorithms by setting
+/// `MimblewimbleParameters::pow_algorithm`.
+pub trait PowAlgorithm: fmt::Debug + Send + Sync {
+    /// Check that a header's nonce solves the puzzle at its difficulty
+    fn verify(&self, header: &BlockHeader) -> bool;
+}
+
+/// Default algorithm: the SHA-512 header hash prefix must meet the target
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Sha512Pow;
+
+impl PowAlgorithm for Sha512Pow {
+    fn verify(&self, header: &BlockHeader) -> bool {
+        let hash = match pow_hash(header) {
+            Ok(hash) => hash,
+            Err(_) => return false,
+        };
+        let mut prefix = [0u8; 8];
+        prefix.copy_from_slice(&hash[..8]);
+
+        u64::from_be_bytes(prefix) <= target(header.difficulty)
+    }
+}
+
+/// Default proof of work algorithm
+pub fn default_algorithm() -> Arc<dyn PowAlgorithm> {
+    Arc::new(Sha512Pow)
+}
+
+/// Maximum factor by which difficulty may change in one adjustment
+const MAX_ADJUSTMENT_FACTOR: u64 = 4;
+
+/// Maximum factor by which a block time counted in a retarget may differ
+/// from the target block time
+const MAX_BLOCK_TIME_FACTOR: u64 = 6;
+
+/// Compute the proof of work hash of a header
+pub fn pow_hash(header: &BlockHeader) -> Result<Vec<u8>> {
+    let mut hasher = Sha512::new();
+    hasher.update(serialization::to_vec(header)?);
+    Ok(hasher.finalize().to_vec())
+}
+
+/// Compute the hash target for a difficulty
+pub fn target(difficulty: u64) -> u64 {
+    u64::MAX / difficulty.max(1)
+}
+
+/// Check that a header's proof of work meets its difficulty
+///
+/// Delegates to the configured `PowAlgorithm` unless the chain uses
+/// trivial proof of work.
+pub fn validate_pow(header: &BlockHeader, parameters: &MimblewimbleParameters) -> bool {
+    if header.difficulty == 0 {
+        return false;
+    }
+
+    if parameters.pow_mode == PowMode::Trivial {
+        return true;
+    }
+
+    parameters.pow_algorithm.verify(header)
+}
+
+/// Search for a nonce satisfying the header's difficulty
+pub fn mine(header: &mut BlockHeader, parameters: &MimblewimbleParameters) -> Result<()> {
+    for nonce in 0..=u64::MAX {
+        header.nonce = nonce;
+        if validate_pow(header, parameters) {
+            return Ok(());
+        }
+    }
+
+    Err(MimblewimbleError::InvalidBlock)
+}
+
+/// Compute the difficulty for the block following `headers`
+///
+/// Difficulty is retargeted every `difficulty_adjustment_interval` blocks
+/// from the average block time over the last interval, limited to a factor
+/// of four in either direction. Each block time is clamped to within a factor
+/// of six of the target, so a single bogus timestamp cannot skew the average.
+pub fn next_difficulty(headers: &[BlockHeader], parameters: &MimblewimbleParameters) -> u64 {
+    let last = match headers.last() {
+        Some(last) => last,
+        None => return parameters.initial_difficulty,
+    };
+
+    let interval = parameters.difficulty_adjustment_interval as usize;
+    if !headers.len().is_multiple_of(interval) || headers.len() < 2 {
+        return last.difficulty;
+    }
+
+    let window = &headers[headers.len().saturating_sub(interval + 1)..];
+    let min_delta = (parameters.target_block_time / MAX_BLOCK_TIME_FACTOR).max(1);
+    let max_delta = parameters
+        .target_block_time
+        .saturating_mul(MAX_BLOCK_TIME_FACTOR);
+    let deltas: Vec<u64> = window
+        .windows(2)
+        .map(|pair| {
+            pair[1]
+                .timestamp
+                .saturating_sub(pair[0].timestamp)
+                .clamp(min_delta, max_delta)
+        })
+        .collect();
+    let average = (deltas.iter().sum::<u64>() / deltas.len() as u64).max(1);
+
+    let adjusted = u128::from(last.difficulty) * u128::from(parameters.target_block_time)
+        / u128::from(average);
+    let min = (last.difficulty / MAX_ADJUSTMENT_FACTOR).max(1);
+    let max = last.difficulty.saturating_mul(MAX_ADJUSTMENT_FACTOR);
+
+    u64::try_from(adjusted).unwrap_or(u64::MAX).clamp(min, max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(height: u64, timestamp: u64, difficulty: u64) -> BlockHeader {
+        BlockHeader {
+            version: 1,
+            height,
+            timestamp,
+            previous_hash: vec![0; 32],
+            merkle_root: vec![0; 32],
+            output_root: vec![0; 32],
+            kernel_root: vec![0; 32],
+            difficulty,
+            nonce: 0,
+        }
+    }
+
+    #[test]
+    fn test_mine_and_validate() -> Result<()> {
+        let params = MimblewimbleParameters::default();
+        let mut header = header(0, 0, 1000);
+        mine(&mut header, &params)?;
+        assert!(validate_pow(&header, &params));
+
+        header.difficulty = 0;
+        assert!(!validate_pow(&header, &params));
+        Ok(())
+    }
+
+    #[test]
+    fn test_trivial_pow_accepts_any_nonce() -> Result<()> {
+        let mainnet = MimblewimbleParameters::default();
+        let regtest = MimblewimbleParameters::regtest();
+
+        // Practically unreachable target
+        let mut header = header(0, 0, u64::MAX);
+        assert!(validate_pow(&header, &regtest));
+        assert!(!validate_pow(&header, &mainnet));
+
+        mine(&mut header, &regtest)?;
+        assert_eq!(header.nonce, 0);
+        Ok(())
+    }
+
+    /// Accepts exactly one nonce regardless of the hash
+    #[derive(Debug)]
+    struct FixedNonce(u64);
+
+    impl PowAlgorithm for FixedNonce {
+        fn verify(&self, header: &BlockHeader) -> bool {
+            header.nonce == self.0
+        }
+    }
+
+    #[test]
+    fn test_validate_pow_uses_configured_algorithm() -> Result<()> {
+        let default = MimblewimbleParameters::default();
+        let mut header = header(0, 0, 1000);
+        mine(&mut header, &default)?;
+        assert_eq!(validate_pow(&header, &default), Sha512Pow.verify(&header));
+        assert!(validate_pow(&header, &default));
+
+        let mock = MimblewimbleParameters {
+            pow_algorithm: Arc::new(FixedNonce(7)),
+            ..Default::default()
+        };
+        header.difficulty = u64::MAX;
+        mine(&mut header, &mock)?;
+        assert_eq!(header.nonce, 7);
+        assert!(validate_pow(&header, &mock));
+        assert!(!validate_pow(&header, &default));
+        Ok(())
+    }
+
+    #[test]
+    fn test_difficulty_retarget() {
+        let params = MimblewimbleParameters {
+            difficulty_adjustment_interval: 4,
+            ..Default::default()
+        };
+        let target_time = params.target_block_time;
+
+        // Blocks arriving twice as fast as the target double the difficulty
+        let fast: Vec<BlockHeader> = (0..4)
+            .map(|h| header(h, h * target_time / 2, 1000))
+            .collect();
+        assert_eq!(next_difficulty(&fast, &params), 2000);
+
+        // Off-interval heights keep the current difficulty
+        assert_eq!(next_difficulty(&fast[..3], &params), 1000);
+        assert_eq!(next_difficulty(&[], &params), params.initial_difficulty);
+    }
+
+    #[test]
+    fn test_retarget_clamps_bogus_timestamps() {
+        let params = MimblewimbleParameters {
+            difficulty_adjustment_interval: 4,
+            ..Default::default()
+        };
+        let target_time = params.target_block_time;
+
+        // One timestamp a day ahead counts as six target block times, and
+        // the following block's negative delta as the minimum
+        let mut headers: Vec<BlockHeader> =
+            (0..4).map(|h| header(h, h * target_time, 1000)).collect();
+        headers[2].timestamp += 86_400;
+        let deltas = target_time + 6 * target_time + target_time / 6;
+        assert_eq!(
+            next_difficulty(&headers, &params),
+            1000 * target_time / (deltas / 3)
+        );
+    }
+}
@@ -1,8 +1,9 @@
 //! Proofs for Mimblewimble
 
+use crate::errors::Result;
+use crate::serialization;
 use hex;
 use serde::{Deserialize, Serialize};
-use serde_json;
 use sha2::{Digest, Sha512};
 
 /// Proof structure
@@ -35,11 +36,11 @@ impl Proof {
     }
 
     /// Get proof hash
-    pub fn hash(&self) -> Vec<u8> {
+    pub fn hash(&self) -> Result<Vec<u8>> {
         let mut hasher = Sha512::new();
         hasher.update(&self.data);
-        hasher.update(serde_json::to_vec(&self.proof_type).unwrap_or_default());
-        hex::encode(hasher.finalize()).into_bytes()
+        hasher.update(serialization::to_vec(&self.proof_type)?);
+        Ok(hex::encode(hasher.finalize()).into_bytes())
     }
 }
 
@@ -56,7 +57,7 @@ mod tests {
     #[test]
     fn test_proof_hash() {
         let proof = Proof::new(vec![1; 64], ProofType::Range);
-        let hash = proof.hash();
+        let hash = proof.hash().unwrap();
         assert!(!hash.is_empty());
     }
 }
@@ -1,12 +1,24 @@
 //! Range proofs for Mimblewimble
 
-use crate::errors::Result;
+use crate::errors::{MimblewimbleError, Result};
 use crate::parameters::MimblewimbleParameters;
 use hex;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha512};
 
+/// Length of a hex-encoded SHA-512 digest
+const DIGEST_HEX_LENGTH: usize = 128;
+
+/// Length of the encrypted value carried by a rewindable proof
+const REWIND_LENGTH: usize = 8;
+
+/// Domain tag for rewind masks
+const REWIND_TAG: &[u8] = b"silver-mimblewimble/rewind";
+
 /// Range proof
+///
+/// A proof with no data is absent: the output arrived without one, to be
+/// accepted only when `require_range_proofs` is off.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct RangeProof {
     /// Proof data
@@ -19,46 +31,191 @@ impl RangeProof {
         Self { proof_data }
     }
 
-    /// Create a range proof for a value
-    pub fn create(value: u64, parameters: &MimblewimbleParameters) -> Result<Self> {
+    /// Placeholder for an output carried without its proof
+    pub fn absent() -> Self {
+        Self::new(Vec::new())
+    }
+
+    /// Check whether the proof is absent
+    pub fn is_absent(&self) -> bool {
+        self.proof_data.is_empty()
+    }
+
+    /// Create a range proof for a value, bound to the commitment it proves
+    ///
+    /// The proof is derived from the commitment alone, so it reveals nothing
+    /// about the value beyond what the commitment already hides. Fails if the
+    /// value does not fit in `range_proof_bits`.
+    pub fn create(
+        value: u64,
+        commitment: &[u8],
+        parameters: &MimblewimbleParameters,
+    ) -> Result<Self> {
+        if parameters.range_proof_bits < 64 && value >> parameters.range_proof_bits != 0 {
+            return Err(MimblewimbleError::RangeProofError(format!(
+                "value exceeds {} bits",
+                parameters.range_proof_bits
+            )));
+        }
+
         // Generate range proof
         let mut hasher = Sha512::new();
-        hasher.update(value.to_le_bytes());
         hasher.update(parameters.range_proof_bits.to_le_bytes());
+        hasher.update(commitment);
 
-        let proof_data = hex::encode(hasher.finalize()).into_bytes();
+        let mut proof_data = hex::encode(hasher.finalize()).into_bytes();
+        proof_data.extend_from_slice(&binding(commitment));
+        debug_assert_eq!(proof_data.len(), 2 * DIGEST_HEX_LENGTH);
 
         Ok(Self { proof_data })
     }
 
-    /// Verify the range proof
-    pub fn verify(&self, parameters: &MimblewimbleParameters) -> Result<bool> {
+    /// Create a range proof carrying the value encrypted under `rewind_key`
+    ///
+    /// The owner of `rewind_key` can recover the value with `rewind`, while
+    /// to anyone else the extra bytes are indistinguishable from noise.
+    pub fn create_with_rewind(
+        value: u64,
+        commitment: &[u8],
+        rewind_key: &[u8],
+        parameters: &MimblewimbleParameters,
+    ) -> Result<Self> {
+        let mut proof = Self::create(value, commitment, parameters)?;
+        let hint = value ^ rewind_mask(commitment, rewind_key);
+        proof
+            .proof_data
+            .splice(DIGEST_HEX_LENGTH..DIGEST_HEX_LENGTH, hint.to_le_bytes());
+        Ok(proof)
+    }
+
+    /// Recover the value hidden by `create_with_rewind`
+    ///
+    /// Returns `None` if the proof carries no rewind data. A wrong key yields
+    /// a meaningless value, so callers must check it against the commitment.
+    pub fn rewind(&self, commitment: &[u8], rewind_key: &[u8]) -> Option<u64> {
+        if self.proof_data.len() != 2 * DIGEST_HEX_LENGTH + REWIND_LENGTH {
+            return None;
+        }
+        let hint = self.proof_data[DIGEST_HEX_LENGTH..DIGEST_HEX_LENGTH + REWIND_LENGTH]
+            .try_into()
+            .ok()?;
+        Some(u64::from_le_bytes(hint) ^ rewind_mask(commitment, rewind_key))
+    }
+
+    /// Verify the range proof for a commitment
+    ///
+    /// Fails with `ProofVerificationFailed` if the proof was created for a
+    /// different commitment. An absent proof verifies only when
+    /// `require_range_proofs` is off.
+    pub fn verify(&self, commitment: &[u8], parameters: &MimblewimbleParameters) -> Result<bool> {
+        if self.is_absent() {
+            return Ok(!parameters.require_range_proofs);
+        }
+
         // Verify range proof
         if self.proof_data.len() < parameters.range_proof_bits / 8 {
             return Ok(false);
         }
 
+        if !self.proof_data.ends_with(&binding(commitment)) {
+            return Err(MimblewimbleError::ProofVerificationFailed);
+        }
+
         Ok(true)
     }
 }
 
+/// Mask hiding a value from everyone but the holder of `rewind_key`
+fn rewind_mask(commitment: &[u8], rewind_key: &[u8]) -> u64 {
+    let mut hasher = Sha512::new();
+    hasher.update(REWIND_TAG);
+    hasher.update(rewind_key);
+    hasher.update(commitment);
+    let digest = hasher.finalize();
+    u64::from_le_bytes(
+        digest[..REWIND_LENGTH]
+            .try_into()
+            .expect("digest is 64 bytes"),
+    )
+}
+
+/// Commitment binding appended to a proof
+fn binding(commitment: &[u8]) -> Vec<u8> {
+    hex::encode(Sha512::digest(commitment)).into_bytes()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::commitment::Commitment;
 
     #[test]
     fn test_range_proof_creation() {
         let params = MimblewimbleParameters::default();
-        let proof = RangeProof::create(1000, &params);
+        let proof = RangeProof::create(1000, &[7; 67], &params);
         assert!(proof.is_ok());
     }
 
     #[test]
     fn test_range_proof_verification() -> Result<()> {
         let params = MimblewimbleParameters::default();
-        let proof = RangeProof::create(1000, &params)?;
-        let valid = proof.verify(&params)?;
+        let proof = RangeProof::create(1000, &[7; 67], &params)?;
+        let valid = proof.verify(&[7; 67], &params)?;
         assert!(valid);
+
+        assert!(matches!(
+            proof.verify(&[8; 67], &params),
+            Err(MimblewimbleError::ProofVerificationFailed)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_proof_does_not_reveal_value() -> Result<()> {
+        let params = MimblewimbleParameters::default();
+        let leaked = |value: u64| {
+            let mut hasher = Sha512::new();
+            hasher.update(value.to_le_bytes());
+            hasher.update(params.range_proof_bits.to_le_bytes());
+            hex::encode(hasher.finalize()).into_bytes()
+        };
+
+        for (value, blinding) in [(0, 1), (1000, 2), (1999, 3)] {
+            let output = Commitment::create(value, vec![blinding; 32], &params)?;
+            assert!(output.range_proof.verify(output.commitment(), &params)?);
+
+            // No candidate value can be matched against the proof data
+            let proof = &output.range_proof.proof_data;
+            assert!((0..2000)
+                .map(leaked)
+                .all(|guess| !proof.windows(guess.len()).any(|w| w == guess.as_slice())));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_value_must_fit_range_proof_bits() {
+        let params = MimblewimbleParameters {
+            range_proof_bits: 32,
+            ..MimblewimbleParameters::default()
+        };
+        assert!(RangeProof::create(u32::MAX as u64, &[7; 67], &params).is_ok());
+        assert!(matches!(
+            RangeProof::create(1 << 32, &[7; 67], &params),
+            Err(MimblewimbleError::RangeProofError(_))
+        ));
+    }
+
+    #[test]
+    fn test_rewind_recovers_value() -> Result<()> {
+        let params = MimblewimbleParameters::default();
+        let proof = RangeProof::create_with_rewind(1000, &[7; 67], &[9; 32], &params)?;
+        assert!(proof.verify(&[7; 67], &params)?);
+        assert_eq!(proof.rewind(&[7; 67], &[9; 32]), Some(1000));
+        assert_ne!(proof.rewind(&[7; 67], &[8; 32]), Some(1000));
+
+        let plain = RangeProof::create(1000, &[7; 67], &params)?;
+        assert_eq!(plain.rewind(&[7; 67], &[9; 32]), None);
         Ok(())
     }
 }
@@ -0,0 +1,108 @@
+//! Serialization helpers for Mimblewimble
+
+use crate::errors::{MimblewimbleError, Result};
+use hex;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sha2::{Digest, Sha512};
+
+/// Serialize a value to bytes
+pub fn to_vec<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>> {
+    serde_json::to_vec(value).map_err(|e| MimblewimbleError::SerializationError(e.to_string()))
+}
+
+/// Deserialize a value from bytes
+pub fn from_slice<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+    serde_json::from_slice(bytes).map_err(|e| MimblewimbleError::SerializationError(e.to_string()))
+}
+
+/// Length of the serialized form of a value, without allocating it
+pub fn serialized_size<T: Serialize + ?Sized>(value: &T) -> Result<usize> {
+    let mut counter = ByteCounter(0);
+    serde_json::to_writer(&mut counter, value)
+        .map_err(|e| MimblewimbleError::SerializationError(e.to_string()))?;
+    Ok(counter.0)
+}
+
+/// Writer that only counts the bytes written to it
+struct ByteCounter(usize);
+
+impl std::io::Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writer that feeds the bytes written to it into a hasher
+struct HashWriter(Sha512);
+
+impl std::io::Write for HashWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Hash the serialized form of a value
+///
+/// The serialization is streamed into the hasher rather than buffered, so
+/// memory use does not grow with the size of the value.
+pub fn hash<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>> {
+    let mut writer = HashWriter(Sha512::new());
+    serde_json::to_writer(&mut writer, value)
+        .map_err(|e| MimblewimbleError::SerializationError(e.to_string()))?;
+    Ok(hex::encode(writer.0.finalize()).into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Serializer;
+
+    /// Value whose serialization always fails
+    struct Unserializable;
+
+    impl Serialize for Unserializable {
+        fn serialize<S: Serializer>(&self, _serializer: S) -> std::result::Result<S::Ok, S::Error> {
+            Err(serde::ser::Error::custom("injected failure"))
+        }
+    }
+
+    #[test]
+    fn test_streamed_hash_matches_buffered_hash() -> Result<()> {
+        let tx = crate::tests::balanced_tx(
+            vec![crate::tests::output(1000, 1)],
+            vec![crate::tests::output(400, 2), crate::tests::output(590, 3)],
+            10,
+        );
+        let buffered = hex::encode(Sha512::digest(to_vec(&tx)?)).into_bytes();
+        assert_eq!(hash(&tx)?, buffered);
+        assert_eq!(tx.hash()?, buffered);
+        Ok(())
+    }
+
+    #[test]
+    fn test_serialization_failure_is_an_error() {
+        assert!(matches!(
+            hash(&Unserializable),
+            Err(MimblewimbleError::SerializationError(_))
+        ));
+        assert!(matches!(
+            to_vec(&Unserializable),
+            Err(MimblewimbleError::SerializationError(_))
+        ));
+        assert!(matches!(
+            serialized_size(&Unserializable),
+            Err(MimblewimbleError::SerializationError(_))
+        ));
+    }
+}
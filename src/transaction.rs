@@ -1,33 +1,78 @@
 //! Mimblewimble transactions
 
 use crate::commitment::Commitment;
-use crate::kernel::Kernel;
+use crate::crypto::{self, PointCache};
+use crate::errors::{MimblewimbleError, Result};
+use crate::kernel::{self, Kernel, KernelFeatures};
+use crate::mmr::{self, MembershipProof};
+use crate::parameters::MimblewimbleParameters;
+use crate::range_proof::RangeProof;
+use crate::serialization;
+use crate::MIMBLEWIMBLE_VERSION;
 use hex;
+use lru::LruCache;
+use p521::{ProjectivePoint, Scalar};
+use parking_lot::Mutex;
+use rand::seq::SliceRandom;
+use rand::Rng;
 use serde::{Deserialize, Serialize};
-use serde_json;
 use sha2::{Digest, Sha512};
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+
+/// Weight of a transaction input
+pub const INPUT_WEIGHT: u64 = 1;
+
+/// Weight of a transaction output, dominated by its range proof
+pub const OUTPUT_WEIGHT: u64 = 21;
+
+/// Weight of a transaction kernel
+pub const KERNEL_WEIGHT: u64 = 3;
 
 /// Mimblewimble transaction
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Transaction {
     /// Version
-    pub version: u32,
+    version: u32,
 
     /// Input commitments
-    pub inputs: Vec<Commitment>,
+    inputs: Vec<Commitment>,
 
     /// Output commitments
-    pub outputs: Vec<Commitment>,
+    outputs: Vec<Commitment>,
 
-    /// Transaction kernel
-    pub kernel: Kernel,
+    /// Transaction kernels, one per aggregated transaction
+    kernels: Vec<Kernel>,
 
     /// Transaction fee
-    pub fee: u64,
+    fee: u64,
+
+    /// Kernel offset, a scalar split off the kernel excesses so they cannot
+    /// be matched to the inputs and outputs they balance; empty for zero
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    offset: Vec<u8>,
+
+    /// Hash computed on first use
+    #[serde(skip)]
+    hash_cache: HashCache,
+}
+
+/// Lazily computed transaction hash, ignored by equality
+#[derive(Debug, Clone, Default)]
+struct HashCache(OnceLock<Vec<u8>>);
+
+impl PartialEq for HashCache {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
 }
 
+impl Eq for HashCache {}
+
 impl Transaction {
-    /// Create a new transaction
+    /// Create a new transaction with a single kernel
     pub fn new(
         version: u32,
         inputs: Vec<Commitment>,
@@ -39,28 +84,653 @@ impl Transaction {
             version,
             inputs,
             outputs,
-            kernel,
+            kernels: vec![kernel],
             fee,
+            offset: Vec::new(),
+            hash_cache: HashCache::default(),
+        }
+    }
+
+    /// Protocol version
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Input commitments
+    pub fn inputs(&self) -> &[Commitment] {
+        &self.inputs
+    }
+
+    /// Mutable input commitments, discarding the cached hash
+    pub fn inputs_mut(&mut self) -> &mut Vec<Commitment> {
+        self.invalidate_hash();
+        &mut self.inputs
+    }
+
+    /// Output commitments
+    pub fn outputs(&self) -> &[Commitment] {
+        &self.outputs
+    }
+
+    /// Mutable output commitments, discarding the cached hash
+    pub fn outputs_mut(&mut self) -> &mut Vec<Commitment> {
+        self.invalidate_hash();
+        &mut self.outputs
+    }
+
+    /// Transaction kernels, one per aggregated transaction
+    pub fn kernels(&self) -> &[Kernel] {
+        &self.kernels
+    }
+
+    /// Mutable transaction kernels, discarding the cached hash
+    pub fn kernels_mut(&mut self) -> &mut Vec<Kernel> {
+        self.invalidate_hash();
+        &mut self.kernels
+    }
+
+    /// Transaction fee
+    pub fn fee(&self) -> u64 {
+        self.fee
+    }
+
+    /// Set the transaction fee, discarding the cached hash
+    pub fn set_fee(&mut self, fee: u64) {
+        self.invalidate_hash();
+        self.fee = fee;
+    }
+
+    /// Encoded kernel offset, empty for zero
+    pub fn offset(&self) -> &[u8] {
+        &self.offset
+    }
+
+    /// Mutable encoded kernel offset, discarding the cached hash
+    pub fn offset_mut(&mut self) -> &mut Vec<u8> {
+        self.invalidate_hash();
+        &mut self.offset
+    }
+
+    /// Build the coinbase transaction for a block at `height`
+    ///
+    /// The single output pays the block reward plus `fees` to
+    /// `recipient_key`, which is also the blinding factor signing the
+    /// coinbase kernel.
+    pub fn coinbase(
+        height: u64,
+        fees: u64,
+        recipient_key: &[u8],
+        parameters: &MimblewimbleParameters,
+    ) -> Result<Self> {
+        let value = parameters
+            .block_reward(height)
+            .checked_add(fees)
+            .ok_or(MimblewimbleError::InvalidTransaction)?;
+
+        let output = Commitment::create(value, recipient_key.to_vec(), parameters)?;
+
+        let mut kernel = Kernel::new(KernelFeatures::Coinbase, 0, 0, vec![], vec![]);
+        kernel.sign(&output.blinding, parameters)?;
+
+        Ok(Self::new(
+            MIMBLEWIMBLE_VERSION,
+            vec![],
+            vec![output],
+            kernel,
+            0,
+        ))
+    }
+
+    /// Aggregate transactions into one
+    ///
+    /// Inputs, outputs and kernels are combined and sorted canonically, so
+    /// the result does not depend on the order of `transactions`. Anchored
+    /// transactions must share the same anchor. The offsets are summed into
+    /// the aggregate's single offset, so no constituent offset remains to
+    /// split the aggregate back into its transactions.
+    pub fn aggregate(transactions: &[Transaction]) -> Result<Self> {
+        let version = transactions
+            .iter()
+            .map(|tx| tx.version)
+            .max()
+            .ok_or(MimblewimbleError::InvalidTransaction)?;
+
+        let mut aggregate = Self {
+            version,
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            kernels: Vec::new(),
+            fee: 0,
+            offset: Vec::new(),
+            hash_cache: HashCache::default(),
+        };
+        let mut anchor = None;
+        let mut offset = Scalar::ZERO;
+        for tx in transactions {
+            // Anchors must agree, since the aggregate is bound to all of them
+            if let Some(own) = tx.anchor() {
+                if *anchor.get_or_insert(own) != own {
+                    return Err(MimblewimbleError::InvalidTransaction);
+                }
+            }
+            aggregate.inputs.extend(tx.inputs.iter().cloned());
+            aggregate.outputs.extend(tx.outputs.iter().cloned());
+            aggregate.kernels.extend(tx.kernels.iter().cloned());
+            aggregate.fee = aggregate
+                .fee
+                .checked_add(tx.fee)
+                .ok_or(MimblewimbleError::InvalidTransaction)?;
+            offset += tx.offset_scalar()?;
+        }
+        aggregate.set_offset(&offset);
+        aggregate.sort_canonical();
+        Ok(aggregate)
+    }
+
+    /// Kernel offset as a scalar, zero if absent
+    ///
+    /// Fails with `InvalidTransaction` if the offset is not a valid scalar.
+    pub fn offset_scalar(&self) -> Result<Scalar> {
+        if self.offset.is_empty() {
+            return Ok(Scalar::ZERO);
+        }
+        if self.offset.len() != crypto::SCALAR_LENGTH {
+            return Err(MimblewimbleError::InvalidTransaction);
+        }
+        crypto::scalar_from_bytes(&self.offset).map_err(|_| MimblewimbleError::InvalidTransaction)
+    }
+
+    /// Set the kernel offset, encoding zero as no offset
+    pub fn set_offset(&mut self, offset: &Scalar) {
+        self.offset = if *offset == Scalar::ZERO {
+            Vec::new()
+        } else {
+            crypto::scalar_to_bytes(offset)
+        };
+        self.invalidate_hash();
+    }
+
+    /// Randomly permute inputs, outputs and kernels before relaying
+    ///
+    /// Breaks any positional link between the parts of an aggregate and the
+    /// transactions it was built from. Balance and signatures do not depend
+    /// on order; receivers restore it with `sort_canonical`.
+    pub fn shuffle_for_relay<R: Rng + ?Sized>(&mut self, rng: &mut R) {
+        self.inputs.shuffle(rng);
+        self.outputs.shuffle(rng);
+        self.kernels.shuffle(rng);
+        self.invalidate_hash();
+    }
+
+    /// Block hash the transaction's kernels are anchored to, if any
+    pub fn anchor(&self) -> Option<&[u8]> {
+        self.kernels
+            .iter()
+            .find_map(|kernel| kernel.anchor.as_deref())
+    }
+
+    /// Check whether the transaction carries a coinbase kernel
+    pub fn is_coinbase(&self) -> bool {
+        self.kernels
+            .iter()
+            .any(|kernel| kernel.features == KernelFeatures::Coinbase)
+    }
+
+    /// Lowest block height the transaction may be included at
+    pub fn lock_height(&self) -> u64 {
+        self.kernels
+            .iter()
+            .map(|kernel| kernel.lock_height)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Lowest block height at which every kernel lock is satisfied
+    ///
+    /// Relative locks mature `blocks` after the creation height that
+    /// `created_at` reports for the referenced output. Returns `None` if a
+    /// relative lock references an output the transaction does not spend,
+    /// one whose creation height is unknown, or one that never matures.
+    pub fn maturity_height(&self, created_at: impl Fn(&[u8]) -> Option<u64>) -> Option<u64> {
+        self.kernels
+            .iter()
+            .filter_map(|kernel| kernel.relative_lock.as_ref())
+            .try_fold(self.lock_height(), |height, lock| {
+                if !self
+                    .inputs
+                    .iter()
+                    .any(|input| input.commitment == lock.commitment)
+                {
+                    return None;
+                }
+                let mature =
+                    kernel::relative_lock_height(created_at(&lock.commitment)?, lock.blocks)?;
+                Some(height.max(mature))
+            })
+    }
+
+    /// Check that inputs are strictly sorted by commitment bytes
+    ///
+    /// Sorted inputs give the transaction a single hash, so relays cannot
+    /// malleate it by reordering them. The order is strict so an input
+    /// cannot be listed, and counted towards the balance, twice.
+    pub fn has_sorted_inputs(&self) -> bool {
+        self.inputs
+            .windows(2)
+            .all(|pair| pair[0].commitment < pair[1].commitment)
+    }
+
+    /// Check that inputs, outputs and kernels are in canonical order
+    ///
+    /// Inputs and outputs are ordered by commitment bytes and kernels by
+    /// excess bytes.
+    pub fn is_canonical(&self) -> bool {
+        self.has_sorted_inputs()
+            && self
+                .outputs
+                .windows(2)
+                .all(|pair| pair[0].commitment <= pair[1].commitment)
+            && self
+                .kernels
+                .windows(2)
+                .all(|pair| pair[0].excess <= pair[1].excess)
+    }
+
+    /// Sort inputs, outputs and kernels into canonical order
+    pub fn sort_canonical(&mut self) {
+        self.inputs.sort_by(|a, b| a.commitment.cmp(&b.commitment));
+        self.outputs.sort_by(|a, b| a.commitment.cmp(&b.commitment));
+        self.kernels.sort_by(|a, b| a.excess.cmp(&b.excess));
+        self.invalidate_hash();
+    }
+
+    /// Check that every commitment and kernel excess decodes to a curve point
+    ///
+    /// Meant to run right after deserialization, so malformed points are
+    /// rejected before any verification work. Fails with `InvalidCommitment`
+    /// or `InvalidKernel`.
+    pub fn validate_encoding(&self) -> Result<()> {
+        for commitment in self.inputs.iter().chain(&self.outputs) {
+            crypto::point_from_bytes(&commitment.commitment)
+                .map_err(|_| MimblewimbleError::InvalidCommitment)?;
+        }
+        for kernel in &self.kernels {
+            crypto::point_from_bytes(&kernel.excess)
+                .map_err(|_| MimblewimbleError::InvalidKernel)?;
+        }
+        Ok(())
+    }
+
+    /// Sum of the fees committed to by each kernel
+    ///
+    /// Fails with `InvalidTransaction` if the sum overflows.
+    pub fn kernel_fee_sum(&self) -> Result<u64> {
+        self.kernels.iter().try_fold(0u64, |sum, kernel| {
+            sum.checked_add(kernel.fee)
+                .ok_or(MimblewimbleError::InvalidTransaction)
+        })
+    }
+
+    /// Validate the transaction without reference to chain state
+    ///
+    /// Checks the kernel count against `max_kernels_per_tx`, that a coinbase
+    /// spends no inputs, that the kernel fees sum to the transaction fee, the
+    /// offset encoding, the kernel fees against `max_fee`, that no input
+    /// spends one of its own outputs, the output fan-out against
+    /// `max_output_to_input_ratio` and the range proofs of all outputs.
+    pub fn validate(&self, parameters: &MimblewimbleParameters) -> Result<()> {
+        self.validate_structure(parameters)?;
+        self.verify_range_proofs(parameters)
+    }
+
+    /// Run the stateless checks of `validate` other than the range proofs
+    pub(crate) fn validate_structure(&self, parameters: &MimblewimbleParameters) -> Result<()> {
+        if self.kernels.is_empty() || self.kernels.len() > parameters.max_kernels_per_tx {
+            return Err(MimblewimbleError::InvalidTransaction);
         }
+
+        // A coinbase mints new coins and spends nothing
+        if self.is_coinbase() && !self.inputs.is_empty() {
+            return Err(MimblewimbleError::InvalidTransaction);
+        }
+
+        if self.kernel_fee_sum()? != self.fee {
+            return Err(MimblewimbleError::InvalidTransaction);
+        }
+
+        // A single combined offset, with zero encoded only as no offset
+        if !self.offset.is_empty() && self.offset_scalar()? == Scalar::ZERO {
+            return Err(MimblewimbleError::InvalidTransaction);
+        }
+
+        // Checked per kernel, so aggregating transactions cannot exceed it
+        if let Some(max_fee) = parameters.max_fee {
+            if self.kernels.iter().any(|kernel| kernel.fee > max_fee) {
+                return Err(MimblewimbleError::FeeTooHigh);
+            }
+        }
+
+        if self.spends_own_output() {
+            return Err(MimblewimbleError::InvalidTransaction);
+        }
+
+        if self.exceeds_output_ratio(parameters) {
+            return Err(MimblewimbleError::InvalidTransaction);
+        }
+
+        Ok(())
+    }
+
+    /// Check whether an input spends an output of the same transaction
+    ///
+    /// Such a pair should have been removed by cut-through, which this
+    /// crate does not perform.
+    fn spends_own_output(&self) -> bool {
+        self.inputs.iter().any(|input| {
+            self.outputs
+                .iter()
+                .any(|output| output.commitment == input.commitment)
+        })
+    }
+
+    /// Check whether outputs outnumber inputs by more than the allowed ratio
+    ///
+    /// Coinbase transactions have no inputs and are exempt.
+    fn exceeds_output_ratio(&self, parameters: &MimblewimbleParameters) -> bool {
+        let ratio = parameters.max_output_to_input_ratio;
+        ratio != 0
+            && !self.is_coinbase()
+            && self.outputs.len() > self.inputs.len().max(1).saturating_mul(ratio)
+    }
+
+    /// Check for an output known to commit to zero
+    ///
+    /// Values are hidden, so only outputs whose opening is known, such as
+    /// those of wallet-built transactions, can be checked. Peers cannot see
+    /// the opening, so this is mempool policy rather than a consensus rule.
+    pub fn has_known_zero_value_output(&self) -> Result<bool> {
+        for output in &self.outputs {
+            if output.value == 0 && !output.blinding.is_empty() && output.verify()? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Weight of the transaction for fee purposes
+    pub fn weight(&self) -> u64 {
+        (self.inputs.len() as u64)
+            .saturating_mul(INPUT_WEIGHT)
+            .saturating_add((self.outputs.len() as u64).saturating_mul(OUTPUT_WEIGHT))
+            .saturating_add((self.kernels.len() as u64).saturating_mul(KERNEL_WEIGHT))
+    }
+
+    /// Smallest fee that passes consensus for this transaction
+    pub fn minimum_fee(&self, parameters: &MimblewimbleParameters) -> u64 {
+        self.weight().saturating_mul(parameters.min_fee_per_weight)
     }
 
-    /// Get transaction size
-    pub fn size(&self) -> usize {
-        serde_json::to_vec(self).unwrap_or_default().len()
+    /// Size of the transaction's JSON encoding, as framed by the net codec
+    /// (bytes)
+    pub fn wire_size(&self) -> Result<usize> {
+        serialization::serialized_size(self)
+    }
+
+    /// Bytes saved by sending the transaction rather than one transaction per
+    /// kernel
+    ///
+    /// Each kernel is split into its own transaction paying the kernel's fee.
+    /// The aggregate no longer records which inputs and outputs belong to
+    /// which kernel, so they are spread evenly over the split transactions,
+    /// and the first keeps the offset.
+    pub fn size_savings_vs_separate(&self) -> Result<usize> {
+        let count = self.kernels.len();
+        let share = |items: &[Commitment], index: usize| {
+            items[items.len() * index / count..items.len() * (index + 1) / count].to_vec()
+        };
+
+        let mut separate_size = 0;
+        for (index, kernel) in self.kernels.iter().enumerate() {
+            let mut split = Transaction::new(
+                self.version,
+                share(&self.inputs, index),
+                share(&self.outputs, index),
+                kernel.clone(),
+                kernel.fee,
+            );
+            if index == 0 {
+                split.offset = self.offset.clone();
+            }
+            separate_size += split.wire_size()?;
+        }
+        Ok(separate_size.saturating_sub(self.wire_size()?))
+    }
+
+    /// Verify the range proofs of all outputs
+    ///
+    /// Fails with `InvalidRangeProof` carrying the index of the first output
+    /// whose proof does not verify.
+    pub fn verify_range_proofs(&self, parameters: &MimblewimbleParameters) -> Result<()> {
+        for (index, output) in self.outputs.iter().enumerate() {
+            if !output.range_proof.verify(&output.commitment, parameters)? {
+                return Err(MimblewimbleError::InvalidRangeProof(index));
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that the commitments balance against the kernel excesses
+    ///
+    /// Verifies `sum(outputs) - sum(inputs) + fee*H == sum(excesses) + offset*G`
+    /// using only public commitments, so it holds for transactions received
+    /// without the secret values and blinding factors.
+    pub fn is_balanced(&self) -> bool {
+        self.balances(crypto::sum_points)
+    }
+
+    /// Check the balance like `is_balanced`, decoding points through a cache
+    pub fn is_balanced_with(&self, cache: &PointCache) -> bool {
+        self.balances(|points| cache.sum_points(points))
+    }
+
+    /// Check the balance equation with a given point summation
+    fn balances<'a, F>(&'a self, sum: F) -> bool
+    where
+        F: Fn(Vec<&'a [u8]>) -> Result<ProjectivePoint>,
+    {
+        let points = |commitments: &'a [Commitment]| {
+            commitments
+                .iter()
+                .map(|c| c.commitment.as_slice())
+                .collect()
+        };
+        match (
+            sum(points(&self.outputs)),
+            sum(points(&self.inputs)),
+            sum(self.kernels.iter().map(|k| k.excess.as_slice()).collect()),
+            self.offset_scalar(),
+        ) {
+            (Ok(outputs), Ok(inputs), Ok(excesses), Ok(offset)) => {
+                outputs - inputs + crypto::commit(self.fee, &Scalar::ZERO)
+                    == excesses + crypto::commit(0, &offset)
+            }
+            _ => false,
+        }
+    }
+
+    /// Verify the excess points and signatures of all kernels
+    ///
+    /// Needs no chain state. Fails with `InvalidKernelSignature` carrying the
+    /// index of the first kernel that does not verify.
+    pub fn verify_kernels(&self, parameters: &MimblewimbleParameters) -> Result<()> {
+        for (index, kernel) in self.kernels.iter().enumerate() {
+            if !matches!(kernel.verify(parameters), Ok(true)) {
+                return Err(MimblewimbleError::InvalidKernelSignature(index));
+            }
+        }
+        Ok(())
+    }
+
+    /// Check inputs against an output root without the UTXO set
+    ///
+    /// Light clients supply one membership proof per input, in input order.
+    pub fn verify_input_proofs(&self, proofs: &[MembershipProof], output_root: &[u8]) -> bool {
+        self.inputs.len() == proofs.len()
+            && self.inputs.iter().zip(proofs).all(|(input, proof)| {
+                proof.commitment == input.commitment && mmr::verify_membership(proof, output_root)
+            })
+    }
+
+    /// Serialize the transaction with its range proofs stripped
+    ///
+    /// For relaying to peers that already hold the proofs, which dominate
+    /// the transaction size.
+    pub fn serialize_without_proofs(&self) -> Result<Vec<u8>> {
+        let mut stripped = self.clone();
+        for output in &mut stripped.outputs {
+            output.range_proof = RangeProof::absent();
+        }
+        serialization::to_vec(&stripped)
+    }
+
+    /// Rebuild a proof-stripped transaction from known range proofs
+    ///
+    /// `proofs` maps output commitments to their range proofs. Fails with
+    /// `InvalidProof` if any output's proof is unknown.
+    pub fn reconstruct(bytes: &[u8], proofs: &HashMap<Vec<u8>, RangeProof>) -> Result<Self> {
+        let mut transaction: Self = serialization::from_slice(bytes)?;
+        for output in &mut transaction.outputs {
+            output.range_proof = proofs
+                .get(&output.commitment)
+                .cloned()
+                .ok_or(MimblewimbleError::InvalidProof)?;
+        }
+        Ok(transaction)
     }
 
     /// Get transaction hash
-    pub fn hash(&self) -> Vec<u8> {
+    ///
+    /// The hash is computed once and cached. Every mutating method discards
+    /// the cached hash, so it always matches the current contents.
+    pub fn hash(&self) -> Result<Vec<u8>> {
+        if let Some(hash) = self.hash_cache.0.get() {
+            return Ok(hash.clone());
+        }
+
+        let hash = serialization::hash(self)?;
+        Ok(self.hash_cache.0.get_or_init(|| hash).clone())
+    }
+
+    /// Discard the cached hash before mutating the transaction
+    fn invalidate_hash(&mut self) {
+        self.hash_cache = HashCache::default();
+    }
+
+    /// Identity of the transaction's effect, for deduplication
+    ///
+    /// Hashes the sorted input and output commitments and the kernels'
+    /// features, fees, locks and anchors. Kernel excesses are left out: the
+    /// inputs, outputs and fee determine the excess up to the offset, so
+    /// relays that re-randomize the offset, or alter proofs, signatures or
+    /// ordering, keep the fingerprint.
+    pub fn fingerprint(&self) -> Vec<u8> {
+        fn sorted(items: impl Iterator<Item = Vec<u8>>) -> Vec<Vec<u8>> {
+            let mut items: Vec<_> = items.collect();
+            items.sort();
+            items
+        }
+
         let mut hasher = Sha512::new();
-        hasher.update(serde_json::to_vec(self).unwrap_or_default());
+        for section in [
+            sorted(self.inputs.iter().map(|input| input.commitment.clone())),
+            sorted(self.outputs.iter().map(|output| output.commitment.clone())),
+            sorted(self.kernels.iter().map(kernel::kernel_terms_hash)),
+        ] {
+            hasher.update((section.len() as u64).to_be_bytes());
+            for item in section {
+                hasher.update((item.len() as u64).to_be_bytes());
+                hasher.update(item);
+            }
+        }
         hex::encode(hasher.finalize()).into_bytes()
     }
 }
 
+/// Bounded least-recently-used cache of verified transactions
+///
+/// Entries are keyed by `Transaction::hash`, which each transaction computes
+/// once and covers signatures and proofs, so a relayed variant is verified
+/// afresh. A hit vouches only for checks that do not depend on the chain
+/// state.
+#[derive(Debug)]
+pub struct VerificationCache {
+    hashes: Mutex<LruCache<Vec<u8>, ()>>,
+    hits: AtomicU64,
+}
+
+impl VerificationCache {
+    /// Create a cache holding at most `capacity` transactions
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        Self {
+            hashes: Mutex::new(LruCache::new(capacity)),
+            hits: AtomicU64::new(0),
+        }
+    }
+
+    /// Check whether a transaction has been verified, counting a hit if so
+    pub fn contains(&self, transaction: &Transaction) -> Result<bool> {
+        let hash = transaction.hash()?;
+        let verified = self.hashes.lock().get(&hash).is_some();
+        if verified {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(verified)
+    }
+
+    /// Record a transaction as verified
+    pub fn insert(&self, transaction: &Transaction) -> Result<()> {
+        self.hashes.lock().put(transaction.hash()?, ());
+        Ok(())
+    }
+
+    /// Forget every verified transaction
+    pub fn clear(&self) {
+        self.hashes.lock().clear();
+    }
+
+    /// Number of lookups that found a verified transaction
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Number of cached transactions
+    pub fn len(&self) -> usize {
+        self.hashes.lock().len()
+    }
+
+    /// Check whether the cache is empty
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Sum the kernel offsets of transactions
+pub(crate) fn sum_offsets<'a>(
+    transactions: impl IntoIterator<Item = &'a Transaction>,
+) -> Result<Scalar> {
+    transactions
+        .into_iter()
+        .try_fold(Scalar::ZERO, |sum, tx| Ok(sum + tx.offset_scalar()?))
+}
+
+/// Sum the points of commitments
+pub(crate) fn sum_commitments(commitments: &[Commitment]) -> Result<ProjectivePoint> {
+    crypto::sum_points(commitments.iter().map(|c| c.commitment.as_slice()))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::kernel::KernelFeatures;
 
     #[test]
     fn test_transaction_creation() {
@@ -72,13 +742,561 @@ mod tests {
         assert_eq!(tx.fee, 100);
     }
 
+    #[test]
+    fn test_size_savings_vs_separate() -> Result<()> {
+        use crate::tests::{balanced_tx, output};
+
+        let separate = [
+            balanced_tx(
+                vec![output(1000, 1)],
+                vec![output(600, 2), output(390, 3)],
+                10,
+            ),
+            balanced_tx(vec![output(1000, 4)], vec![output(990, 5)], 10),
+            balanced_tx(
+                vec![output(500, 6), output(500, 7)],
+                vec![output(990, 8)],
+                10,
+            ),
+        ];
+        assert_eq!(separate[0].size_savings_vs_separate()?, 0);
+
+        let aggregate = Transaction::aggregate(&separate)?;
+        let separate_size = separate
+            .iter()
+            .map(Transaction::wire_size)
+            .sum::<Result<usize>>()?;
+        let savings = aggregate.size_savings_vs_separate()?;
+        assert!(savings > 0);
+        assert_eq!(savings, separate_size - aggregate.wire_size()?);
+        Ok(())
+    }
+
     #[test]
     fn test_transaction_hash() {
         let kernel = Kernel::new(KernelFeatures::Plain, 100, 0, vec![1; 32], vec![2; 64]);
 
         let tx = Transaction::new(1, vec![], vec![], kernel, 100);
 
-        let hash = tx.hash();
+        let hash = tx.hash().unwrap();
         assert!(!hash.is_empty());
     }
+
+    #[test]
+    fn test_verify_kernels_reports_index() -> Result<()> {
+        let params = MimblewimbleParameters::default();
+        let signed = |fee: u64, secret: u8| {
+            let mut kernel = Kernel::new(KernelFeatures::Plain, fee, 0, vec![], vec![]);
+            kernel.sign(&[secret; 32], &params).map(|_| kernel)
+        };
+
+        let mut tx = Transaction::new(1, vec![], vec![], signed(10, 1)?, 30);
+        tx.kernels.push(signed(20, 2)?);
+        tx.verify_kernels(&params)?;
+
+        // A signature over a different fee does not verify
+        tx.kernels[1].fee = 21;
+        assert!(matches!(
+            tx.verify_kernels(&params),
+            Err(MimblewimbleError::InvalidKernelSignature(1))
+        ));
+
+        tx.kernels[1] = signed(20, 2)?;
+        tx.kernels[0].excess = vec![1; 32];
+        assert!(matches!(
+            tx.verify_kernels(&params),
+            Err(MimblewimbleError::InvalidKernelSignature(0))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_kernel_fees_must_sum_to_fee() -> Result<()> {
+        let params = MimblewimbleParameters::default();
+        let kernel = |fee: u64| Kernel::new(KernelFeatures::Plain, fee, 0, vec![1; 32], vec![]);
+
+        let mut tx = Transaction::new(1, vec![], vec![], kernel(10), 30);
+        tx.kernels.push(kernel(20));
+        assert_eq!(tx.kernel_fee_sum()?, 30);
+        tx.validate(&params)?;
+
+        tx.kernels[1].fee = 25;
+        assert!(matches!(
+            tx.validate(&params),
+            Err(MimblewimbleError::InvalidTransaction)
+        ));
+
+        tx.kernels[1].fee = u64::MAX;
+        assert!(matches!(
+            tx.kernel_fee_sum(),
+            Err(MimblewimbleError::InvalidTransaction)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_known_zero_value_output() -> Result<()> {
+        let params = MimblewimbleParameters::default();
+        let kernel = Kernel::new(KernelFeatures::Plain, 0, 0, vec![1; 32], vec![]);
+        let tx = Transaction::new(
+            1,
+            vec![],
+            vec![Commitment::create(0, vec![5; 32], &params)?],
+            kernel,
+            0,
+        );
+        assert!(tx.has_known_zero_value_output()?);
+
+        // Without the opening the value is hidden and cannot be checked
+        let received: Transaction = serialization::from_slice(&serialization::to_vec(&tx)?)?;
+        assert!(!received.has_known_zero_value_output()?);
+
+        // Either way the transaction is valid by consensus
+        tx.validate(&params)?;
+        received.validate(&params)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_input_spending_own_output_is_rejected() -> Result<()> {
+        let params = MimblewimbleParameters::default();
+        let funding = crate::tests::output(1000, 1);
+        let own = crate::tests::output(400, 2);
+        let change = crate::tests::output(590, 3);
+
+        let tx =
+            crate::tests::balanced_tx(vec![funding.clone()], vec![own.clone(), change.clone()], 10);
+        tx.validate(&params)?;
+
+        // Additionally spending its own output keeps the transaction
+        // balanced, but it must be rejected
+        let change = crate::tests::output(990, 3);
+        let tx = crate::tests::balanced_tx(vec![funding, own.clone()], vec![own, change], 10);
+        assert!(tx.is_balanced());
+        assert!(matches!(
+            tx.validate(&params),
+            Err(MimblewimbleError::InvalidTransaction)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_fingerprint_ignores_malleable_fields() -> Result<()> {
+        let tx = crate::tests::balanced_tx(
+            vec![crate::tests::output(1000, 1)],
+            vec![crate::tests::output(400, 2), crate::tests::output(590, 3)],
+            10,
+        );
+
+        let mut relayed = tx.clone();
+        relayed.outputs_mut().reverse();
+        relayed.outputs_mut()[0].range_proof.proof_data.insert(0, 7);
+        relayed.kernels_mut()[0].signature = vec![0; crypto::SIGNATURE_LENGTH];
+        assert_ne!(relayed.hash()?, tx.hash()?);
+        assert_eq!(relayed.fingerprint(), tx.fingerprint());
+
+        // Spending or creating something else is a different transaction
+        let mut other = tx.clone();
+        other.outputs.pop();
+        assert_ne!(other.fingerprint(), tx.fingerprint());
+        let mut moved = tx.clone();
+        moved.inputs = std::mem::take(&mut moved.outputs);
+        moved.outputs = tx.inputs.clone();
+        assert_ne!(moved.fingerprint(), tx.fingerprint());
+        let mut locked = tx.clone();
+        locked.kernels_mut()[0].lock_height = 5;
+        assert_ne!(locked.fingerprint(), tx.fingerprint());
+        Ok(())
+    }
+
+    #[test]
+    fn test_fingerprint_survives_offset_rerandomization() -> Result<()> {
+        let tx = crate::tests::balanced_tx(
+            vec![crate::tests::output(1000, 1)],
+            vec![crate::tests::output(400, 2), crate::tests::output(590, 3)],
+            10,
+        );
+
+        // Move part of the excess into the offset and sign with the rest
+        let params = MimblewimbleParameters::default();
+        let split = crypto::scalar_from_bytes(&[9; 32])?;
+        let excess = crypto::scalar_from_bytes(&[2; 32])? + crypto::scalar_from_bytes(&[3; 32])?
+            - crypto::scalar_from_bytes(&[1; 32])?;
+        let mut rerandomized = tx.clone();
+        rerandomized.set_offset(&split);
+        rerandomized.kernels_mut()[0].sign(&crypto::scalar_to_bytes(&(excess - split)), &params)?;
+        rerandomized.validate(&params)?;
+        assert!(rerandomized.is_balanced());
+        assert_ne!(rerandomized.kernels()[0].excess, tx.kernels()[0].excess);
+        assert_eq!(rerandomized.fingerprint(), tx.fingerprint());
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_fee() -> Result<()> {
+        let params = MimblewimbleParameters {
+            max_fee: Some(100),
+            ..Default::default()
+        };
+        let spend = |fee: u64| {
+            crate::tests::balanced_tx(
+                vec![crate::tests::output(1000, 1)],
+                vec![crate::tests::output(1000 - fee, 2)],
+                fee,
+            )
+        };
+
+        spend(100).validate(&params)?;
+        assert!(matches!(
+            spend(101).validate(&params),
+            Err(MimblewimbleError::FeeTooHigh)
+        ));
+
+        // The limit applies per kernel, not to an aggregate's total fee
+        let aggregate = Transaction::aggregate(&[
+            spend(60),
+            crate::tests::balanced_tx(
+                vec![crate::tests::output(1000, 3)],
+                vec![crate::tests::output(940, 4)],
+                60,
+            ),
+        ])?;
+        assert_eq!(aggregate.fee, 120);
+        aggregate.validate(&params)?;
+
+        spend(101).validate(&MimblewimbleParameters::default())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_output_to_input_ratio() -> Result<()> {
+        let params = MimblewimbleParameters {
+            max_output_to_input_ratio: 3,
+            ..Default::default()
+        };
+        let output = |n: u8| Commitment::create(100, vec![n; 32], &params);
+        let fan_out = |inputs: usize, outputs: u8| -> Result<Transaction> {
+            let kernel = Kernel::new(KernelFeatures::Plain, 0, 0, vec![1; 32], vec![]);
+            Ok(Transaction::new(
+                1,
+                (0..inputs as u8)
+                    .map(|n| output(100 + n))
+                    .collect::<Result<_>>()?,
+                (1..=outputs).map(output).collect::<Result<_>>()?,
+                kernel,
+                0,
+            ))
+        };
+
+        fan_out(1, 3)?.validate(&params)?;
+        fan_out(2, 6)?.validate(&params)?;
+        // Input-less transactions count as having one input
+        fan_out(0, 3)?.validate(&params)?;
+        assert!(matches!(
+            fan_out(1, 4)?.validate(&params),
+            Err(MimblewimbleError::InvalidTransaction)
+        ));
+        fan_out(1, 4)?.validate(&MimblewimbleParameters {
+            max_output_to_input_ratio: 0,
+            ..Default::default()
+        })?;
+
+        // Coinbase transactions are exempt
+        let mut coinbase = Transaction::coinbase(0, 0, &[3; 32], &params)?;
+        coinbase
+            .outputs
+            .extend((1..=3).map(output).collect::<Result<Vec<_>>>()?);
+        coinbase.validate(&params)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_range_proofs_reports_index() -> Result<()> {
+        let params = MimblewimbleParameters::default();
+        let output = |value: u64| Commitment::create(value, vec![value as u8; 32], &params);
+
+        let mut outputs = vec![output(1)?, output(2)?, output(3)?];
+        outputs[1].range_proof = RangeProof::new(vec![0; 4]);
+        let kernel = Kernel::new(KernelFeatures::Plain, 100, 0, vec![1; 32], vec![2; 64]);
+        let mut tx = Transaction::new(1, vec![], outputs, kernel, 100);
+
+        assert!(matches!(
+            tx.verify_range_proofs(&params),
+            Err(MimblewimbleError::InvalidRangeProof(1))
+        ));
+
+        tx.outputs.remove(1);
+        tx.verify_range_proofs(&params)?;
+
+        // A valid proof attached to another output is rejected
+        let (first, second) = (
+            tx.outputs[0].range_proof.clone(),
+            tx.outputs[1].range_proof.clone(),
+        );
+        tx.outputs[0].range_proof = second;
+        tx.outputs[1].range_proof = first;
+        assert!(matches!(
+            tx.verify_range_proofs(&params),
+            Err(MimblewimbleError::ProofVerificationFailed)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_cached_hash_matches_fresh_hash() -> Result<()> {
+        let kernel = Kernel::new(KernelFeatures::Plain, 100, 0, vec![1; 32], vec![2; 64]);
+        let mut tx = Transaction::new(1, vec![], vec![], kernel, 100);
+
+        let first = tx.hash()?;
+        assert_eq!(first, serialization::hash(&tx)?);
+        assert_eq!(tx.hash()?, first);
+        assert_eq!(tx.clone().hash()?, first);
+
+        // Every mutating accessor discards the cached hash
+        tx.set_fee(200);
+        assert_ne!(tx.hash()?, first);
+        assert_eq!(tx.hash()?, serialization::hash(&tx)?);
+
+        let second = tx.hash()?;
+        tx.kernels_mut()[0].fee = 200;
+        assert_ne!(tx.hash()?, second);
+        assert_eq!(tx.hash()?, serialization::hash(&tx)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_verification_cache_forgets_mutated_transactions() -> Result<()> {
+        let kernel = Kernel::new(KernelFeatures::Plain, 100, 0, vec![1; 32], vec![2; 64]);
+        let mut tx = Transaction::new(1, vec![], vec![], kernel, 100);
+        let cache = VerificationCache::new(NonZeroUsize::new(4).unwrap());
+
+        cache.insert(&tx)?;
+        assert!(cache.contains(&tx)?);
+        assert!(cache.contains(&tx.clone())?);
+        assert_eq!(cache.hits(), 2);
+
+        tx.kernels_mut()[0].signature[0] ^= 1;
+        assert!(!cache.contains(&tx)?);
+        assert_eq!(cache.hits(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_serialization_omits_secrets() -> Result<()> {
+        let params = MimblewimbleParameters::default();
+        let blinding = vec![0xab; 32];
+        let input = Commitment::create(1000, vec![1; 32], &params)?;
+        let change = Commitment::create(990, blinding.clone(), &params)?;
+        let excess = crypto::scalar_from_bytes(&blinding)? - crypto::scalar_from_bytes(&[1; 32])?;
+        let mut kernel = Kernel::new(KernelFeatures::Plain, 10, 0, vec![], vec![]);
+        kernel.sign(&crypto::scalar_to_bytes(&excess), &params)?;
+        let tx = Transaction::new(1, vec![input], vec![change], kernel, 10);
+        assert!(tx.is_balanced());
+
+        let json = String::from_utf8(serialization::to_vec(&tx)?).unwrap();
+        assert!(!json.contains("blinding"));
+        assert!(!json.contains(&serde_json::to_string(&blinding).unwrap()));
+
+        // The public commitments alone still prove the balance
+        let received: Transaction = serialization::from_slice(json.as_bytes())?;
+        assert!(received.outputs[0].blinding.is_empty());
+        assert!(received.is_balanced());
+
+        let mut inflated = tx.clone();
+        inflated.outputs[0] = Commitment::create(991, blinding, &params)?;
+        assert!(!inflated.is_balanced());
+        Ok(())
+    }
+
+    #[test]
+    fn test_point_cache_does_not_change_balance_results() -> Result<()> {
+        let params = MimblewimbleParameters::default();
+        let input = Commitment::create(1000, vec![1; 32], &params)?;
+        let change = Commitment::create(990, vec![2; 32], &params)?;
+        let excess = crypto::scalar_from_bytes(&[1; 32])?;
+        let mut kernel = Kernel::new(KernelFeatures::Plain, 10, 0, vec![], vec![]);
+        kernel.sign(&crypto::scalar_to_bytes(&excess), &params)?;
+        let balanced = Transaction::new(1, vec![input], vec![change], kernel, 10);
+
+        let mut unbalanced = balanced.clone();
+        unbalanced.fee = 11;
+        let mut malformed = balanced.clone();
+        malformed.kernels[0].excess = vec![0xff; crypto::POINT_LENGTH];
+
+        // A two-point cache is evicted repeatedly by three distinct points
+        let cache = PointCache::new(std::num::NonZeroUsize::new(2).unwrap());
+        for _ in 0..3 {
+            for tx in [&balanced, &unbalanced, &malformed] {
+                assert_eq!(tx.is_balanced_with(&cache), tx.is_balanced());
+            }
+        }
+        assert!(balanced.is_balanced_with(&cache));
+        assert!(!unbalanced.is_balanced_with(&cache));
+        assert!(!malformed.is_balanced_with(&cache));
+        assert_eq!(cache.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_proof_stripped_round_trip() -> Result<()> {
+        let params = MimblewimbleParameters::default();
+        let outputs = (1..=2)
+            .map(|value| Commitment::create(value, vec![value as u8; 32], &params))
+            .collect::<Result<Vec<_>>>()?;
+        let kernel = Kernel::new(KernelFeatures::Plain, 100, 0, vec![1; 32], vec![2; 64]);
+        let tx = Transaction::new(1, vec![], outputs, kernel, 100);
+
+        let stripped = tx.serialize_without_proofs()?;
+        assert!(stripped.len() < serialization::to_vec(&tx)?.len());
+
+        let mut proofs: HashMap<Vec<u8>, RangeProof> = tx
+            .outputs
+            .iter()
+            .map(|output| (output.commitment.clone(), output.range_proof.clone()))
+            .collect();
+        assert_eq!(Transaction::reconstruct(&stripped, &proofs)?, tx);
+
+        proofs.remove(&tx.outputs[1].commitment);
+        assert!(matches!(
+            Transaction::reconstruct(&stripped, &proofs),
+            Err(MimblewimbleError::InvalidProof)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_aggregate_is_order_independent() -> Result<()> {
+        let params = MimblewimbleParameters::default();
+        let output =
+            |value: u64, blinding: u8| Commitment::create(value, vec![blinding; 32], &params);
+        let kernel =
+            |excess: u8| Kernel::new(KernelFeatures::Plain, 10, 0, vec![excess; 32], vec![2; 64]);
+
+        let a = Transaction::new(
+            1,
+            vec![output(100, 1)?],
+            vec![output(90, 2)?],
+            kernel(7),
+            10,
+        );
+        let b = Transaction::new(
+            1,
+            vec![output(200, 3)?],
+            vec![output(190, 4)?],
+            kernel(3),
+            10,
+        );
+
+        let ab = Transaction::aggregate(&[a.clone(), b.clone()])?;
+        let ba = Transaction::aggregate(&[b, a])?;
+        assert_eq!(serialization::to_vec(&ab)?, serialization::to_vec(&ba)?);
+        assert_eq!(ab.kernels.len(), 2);
+        assert_eq!(ab.kernels[0].excess, vec![3; 32]);
+        assert_eq!(ab.fee, 20);
+        assert!(ab.is_canonical());
+
+        assert!(Transaction::aggregate(&[]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_aggregate_combines_offsets() -> Result<()> {
+        use crate::tests::output;
+
+        let params = MimblewimbleParameters::default();
+        let transactions: Vec<Transaction> = (0..3u8)
+            .map(|n| {
+                let (input, change) = (output(1000, 2 * n + 1), output(990, 2 * n + 2));
+                let offset = Scalar::from(u64::from(n) + 7);
+                let excess = crate::wallet::blinding_sum(
+                    &[crypto::scalar_from_bytes(&input.blinding)?],
+                    &[crypto::scalar_from_bytes(&change.blinding)?],
+                    offset,
+                );
+                let mut kernel = Kernel::new(KernelFeatures::Plain, 10, 0, vec![], vec![]);
+                kernel.sign(&crypto::scalar_to_bytes(&excess), &params)?;
+                let mut tx = Transaction::new(1, vec![input], vec![change], kernel, 10);
+                tx.set_offset(&offset);
+                assert!(tx.is_balanced());
+                Ok(tx)
+            })
+            .collect::<Result<_>>()?;
+
+        let aggregate = Transaction::aggregate(&transactions)?;
+        assert_eq!(aggregate.kernels.len(), 3);
+        assert_eq!(aggregate.fee, 30);
+        assert_eq!(aggregate.offset_scalar()?, Scalar::from(7u64 + 8 + 9));
+        assert!(aggregate.is_balanced());
+        aggregate.validate(&params)?;
+
+        // A kernel excess alone no longer balances its own inputs and outputs
+        let mut split = transactions[0].clone();
+        split.offset.clear();
+        assert!(!split.is_balanced());
+
+        // Zero has a single encoding
+        let mut zero = aggregate;
+        zero.offset = vec![0; crypto::SCALAR_LENGTH];
+        assert!(matches!(
+            zero.validate(&params),
+            Err(MimblewimbleError::InvalidTransaction)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_limits_kernel_count() -> Result<()> {
+        let params = MimblewimbleParameters {
+            max_kernels_per_tx: 3,
+            ..Default::default()
+        };
+        let kernel = Kernel::new(KernelFeatures::Plain, 1, 0, vec![1; 32], vec![2; 64]);
+        let mut tx = Transaction::new(1, vec![], vec![], kernel.clone(), 3);
+
+        tx.kernels = vec![kernel.clone(); 3];
+        tx.validate(&params)?;
+
+        tx.kernels.push(kernel);
+        assert!(matches!(
+            tx.validate(&params),
+            Err(MimblewimbleError::InvalidTransaction)
+        ));
+
+        tx.kernels.clear();
+        assert!(tx.validate(&params).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_shuffle_for_relay() -> Result<()> {
+        use crate::tests::{balanced_tx, output};
+        use rand::SeedableRng;
+
+        let params = MimblewimbleParameters::default();
+        let transactions: Vec<Transaction> = (0..4u8)
+            .map(|n| {
+                balanced_tx(
+                    vec![output(1000, 3 * n + 1)],
+                    vec![output(600, 3 * n + 2), output(390, 3 * n + 3)],
+                    10,
+                )
+            })
+            .collect();
+        let aggregate = Transaction::aggregate(&transactions)?;
+
+        let mut shuffled = aggregate.clone();
+        shuffled.shuffle_for_relay(&mut rand::rngs::StdRng::seed_from_u64(7));
+        assert_ne!(shuffled.outputs, aggregate.outputs);
+        assert!(!shuffled.is_canonical());
+
+        // Only the order changed, and the balance does not depend on it
+        assert!(shuffled.is_balanced());
+        for kernel in &shuffled.kernels {
+            assert!(kernel.verify_signature(&params)?);
+        }
+        shuffled.sort_canonical();
+        assert_eq!(shuffled, aggregate);
+        shuffled.validate(&params)?;
+        Ok(())
+    }
 }
@@ -0,0 +1,623 @@
+//! Wallet helpers for tracking outputs and building transactions
+
+use crate::commitment::Commitment;
+use crate::crypto;
+use crate::errors::{MimblewimbleError, Result};
+use crate::kernel::{Kernel, KernelFeatures};
+use crate::parameters::MimblewimbleParameters;
+use crate::transaction::Transaction;
+use crate::{MimblewimbleState, MIMBLEWIMBLE_VERSION};
+use p521::Scalar;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+/// Change below this value is added to the fee instead of creating an output
+pub const DUST_THRESHOLD: u64 = 100;
+
+/// Blocks on top of an output's creation before a wallet spends it by default
+const DEFAULT_MIN_CONFIRMATIONS: u64 = 1;
+
+/// Domain tag for wallet key derivation
+const KEY_DERIVATION_TAG: &[u8] = b"silver-mimblewimble/wallet-key";
+
+/// Domain tag for deriving kernel offsets
+const KERNEL_OFFSET_TAG: &[u8] = b"silver-mimblewimble/kernel-offset";
+
+/// Derive the blinding key at `index` from a wallet's master key
+///
+/// Keys are independent of each other, so a wallet can hand out a fresh
+/// key per output and later regenerate them all from the master key.
+pub fn derive_key(master_key: &[u8], index: u32) -> Vec<u8> {
+    crypto::scalar_to_bytes(&crypto::hash_to_scalar(
+        KEY_DERIVATION_TAG,
+        &[master_key, &index.to_be_bytes()],
+    ))
+}
+
+/// Compute the kernel excess secret for a transaction
+///
+/// The excess is `sum(outputs) - sum(inputs) - offset` over the blinding
+/// factors, and is the secret key the kernel is signed with.
+pub fn blinding_sum(inputs: &[Scalar], outputs: &[Scalar], offset: Scalar) -> Scalar {
+    let input_sum = inputs
+        .iter()
+        .fold(Scalar::ZERO, |sum, blinding| sum + blinding);
+    let output_sum = outputs
+        .iter()
+        .fold(Scalar::ZERO, |sum, blinding| sum + blinding);
+
+    output_sum - input_sum - offset
+}
+
+/// Derive the kernel offset of a transaction from its blinding factors
+///
+/// Only the owner of the blinding factors can compute it, so the offset
+/// reveals nothing, yet rebuilding the same transaction reproduces it.
+fn kernel_offset(inputs: &[Scalar], outputs: &[Scalar]) -> Scalar {
+    let encoded: Vec<Vec<u8>> = inputs
+        .iter()
+        .chain(outputs)
+        .map(crypto::scalar_to_bytes)
+        .collect();
+    let data: Vec<&[u8]> = encoded.iter().map(Vec::as_slice).collect();
+    crypto::hash_to_scalar(KERNEL_OFFSET_TAG, &data)
+}
+
+/// Builder for a transaction spending owned inputs
+#[derive(Debug, Clone)]
+pub struct TransactionBuilder<'a> {
+    /// Protocol parameters
+    parameters: &'a MimblewimbleParameters,
+
+    /// Inputs being spent
+    inputs: Vec<Commitment>,
+
+    /// Outputs being created
+    outputs: Vec<Commitment>,
+
+    /// Payments still to be turned into outputs, as value and recipient key
+    recipients: Vec<(u64, Vec<u8>)>,
+
+    /// Requested fee
+    fee: u64,
+
+    /// Blinding key of the change output, if change is wanted
+    change_key: Option<Vec<u8>>,
+}
+
+impl<'a> TransactionBuilder<'a> {
+    /// Start building a transaction
+    pub fn new(parameters: &'a MimblewimbleParameters) -> Self {
+        Self {
+            parameters,
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            recipients: Vec::new(),
+            fee: 0,
+            change_key: None,
+        }
+    }
+
+    /// Spend an input
+    pub fn input(mut self, input: Commitment) -> Self {
+        self.inputs.push(input);
+        self
+    }
+
+    /// Create an output
+    pub fn output(mut self, output: Commitment) -> Self {
+        self.outputs.push(output);
+        self
+    }
+
+    /// Pay `value` to a new output blinded by `recipient_key`
+    ///
+    /// May be called once per recipient; every payment shares the
+    /// transaction's single kernel.
+    pub fn add_recipient(mut self, value: u64, recipient_key: &[u8]) -> Self {
+        self.recipients.push((value, recipient_key.to_vec()));
+        self
+    }
+
+    /// Set the fee
+    pub fn fee(mut self, fee: u64) -> Self {
+        self.fee = fee;
+        self
+    }
+
+    /// Send change to a new output blinded by `recipient_key`
+    pub fn with_change(mut self, recipient_key: &[u8]) -> Self {
+        self.change_key = Some(recipient_key.to_vec());
+        self
+    }
+
+    /// Send change to a new output blinded by the key derived at `index`
+    ///
+    /// The change can later be recovered with `Wallet::scan_outputs`.
+    pub fn with_derived_change(self, master_key: &[u8], index: u32) -> Self {
+        let key = derive_key(master_key, index);
+        self.with_change(&key)
+    }
+
+    /// Build and sign the transaction
+    ///
+    /// Whatever the inputs hold beyond the outputs and fee becomes change.
+    /// Change below `DUST_THRESHOLD` is added to the fee; larger change
+    /// needs a change key and otherwise fails with `BalanceMismatch`.
+    pub fn build(mut self) -> Result<Transaction> {
+        for (value, key) in std::mem::take(&mut self.recipients) {
+            self.outputs
+                .push(Commitment::create(value, key, self.parameters)?);
+        }
+
+        let input_sum = self
+            .inputs
+            .iter()
+            .try_fold(0u64, |sum, input| sum.checked_add(input.value));
+        let spent = self
+            .outputs
+            .iter()
+            .try_fold(self.fee, |sum, output| sum.checked_add(output.value));
+        let change = match (input_sum, spent) {
+            (Some(input_sum), Some(spent)) => input_sum.checked_sub(spent),
+            _ => None,
+        }
+        .ok_or(MimblewimbleError::BalanceMismatch)?;
+
+        if change < DUST_THRESHOLD {
+            self.fee += change;
+        } else if let Some(key) = &self.change_key {
+            self.outputs
+                .push(Commitment::create(change, key.clone(), self.parameters)?);
+        } else {
+            return Err(MimblewimbleError::BalanceMismatch);
+        }
+
+        let blindings = |commitments: &[Commitment]| {
+            commitments
+                .iter()
+                .map(|c| crypto::scalar_from_bytes(&c.blinding))
+                .collect::<Result<Vec<_>>>()
+        };
+        let (inputs, outputs) = (blindings(&self.inputs)?, blindings(&self.outputs)?);
+        let offset = kernel_offset(&inputs, &outputs);
+        let excess = blinding_sum(&inputs, &outputs, offset);
+        let mut kernel = Kernel::new(KernelFeatures::Plain, self.fee, 0, vec![], vec![]);
+        kernel.sign(&crypto::scalar_to_bytes(&excess), self.parameters)?;
+
+        let mut tx = Transaction::new(
+            MIMBLEWIMBLE_VERSION,
+            self.inputs,
+            self.outputs,
+            kernel,
+            self.fee,
+        );
+        tx.set_offset(&offset);
+        tx.sort_canonical();
+        Ok(tx)
+    }
+}
+
+/// Balance of a wallet against the chain state
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Balance {
+    /// Value of all tracked outputs not yet spent
+    pub total: u64,
+
+    /// Value of unspent outputs with enough confirmations
+    pub spendable: u64,
+
+    /// Value of unspent outputs still short of the required confirmations
+    pub immature: u64,
+
+    /// Value of outputs not yet in the UTXO set
+    pub pending: u64,
+
+    /// Value of tracked outputs the wallet has spent
+    pub spent: u64,
+}
+
+/// Outputs owned by a wallet
+#[derive(Debug, Clone)]
+pub struct Wallet {
+    /// Owned outputs, with their openings
+    outputs: Vec<Commitment>,
+
+    /// Commitments of tracked outputs spent by the wallet
+    spent: HashSet<Vec<u8>>,
+
+    /// Blocks on top of an output's creation before it is spendable
+    min_confirmations: u64,
+}
+
+impl Default for Wallet {
+    fn default() -> Self {
+        Self {
+            outputs: Vec::new(),
+            spent: HashSet::new(),
+            min_confirmations: DEFAULT_MIN_CONFIRMATIONS,
+        }
+    }
+}
+
+impl Wallet {
+    /// Create an empty wallet
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Require `min_confirmations` blocks on top of an output before
+    /// counting it as spendable
+    pub fn with_min_confirmations(mut self, min_confirmations: u64) -> Self {
+        self.min_confirmations = min_confirmations;
+        self
+    }
+
+    /// Record the tracked outputs a transaction spends
+    ///
+    /// They stay tracked, counted as spent, until `remove_output` drops them.
+    pub fn record_spend(&mut self, transaction: &Transaction) {
+        for input in transaction.inputs() {
+            if self
+                .outputs
+                .iter()
+                .any(|o| o.commitment == input.commitment)
+            {
+                self.spent.insert(input.commitment.clone());
+            }
+        }
+    }
+
+    /// Track an owned output
+    pub fn add_output(&mut self, output: Commitment) {
+        if !self.outputs.contains(&output) {
+            self.outputs.push(output);
+        }
+    }
+
+    /// Stop tracking an output, e.g. once it has been spent
+    pub fn remove_output(&mut self, commitment: &[u8]) -> Option<Commitment> {
+        let index = self
+            .outputs
+            .iter()
+            .position(|output| output.commitment == commitment)?;
+        self.spent.remove(commitment);
+        Some(self.outputs.remove(index))
+    }
+
+    /// Tracked outputs
+    pub fn outputs(&self) -> &[Commitment] {
+        &self.outputs
+    }
+
+    /// Track unspent outputs blinded by keys derived from `master_key`
+    ///
+    /// Rewinds the range proof of every UTXO with the keys at `indices` and
+    /// keeps the outputs whose recovered value opens the commitment, plain
+    /// or switched. Only outputs made by `Commitment::create` carry the
+    /// value in their proof. Returns the number of newly tracked outputs.
+    pub fn scan_outputs(
+        &mut self,
+        state: &MimblewimbleState,
+        master_key: &[u8],
+        indices: Range<u32>,
+    ) -> Result<usize> {
+        let keys = indices
+            .map(|index| {
+                let key = derive_key(master_key, index);
+                Ok((crypto::scalar_from_bytes(&key)?, key))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut found = 0;
+        for utxo in state.snapshot().utxo_set {
+            for (scalar, key) in &keys {
+                let Some(value) = utxo.range_proof.rewind(&utxo.commitment, key) else {
+                    continue;
+                };
+                let opens = |blinding: &Scalar| {
+                    crypto::point_to_bytes(&crypto::commit(value, blinding)) == utxo.commitment
+                };
+                let blinding = if opens(scalar) {
+                    key.clone()
+                } else {
+                    let switched = crypto::switch_blinding(value, scalar);
+                    if !opens(&switched) {
+                        continue;
+                    }
+                    crypto::scalar_to_bytes(&switched)
+                };
+
+                if !self.outputs.contains(&utxo) {
+                    self.outputs.push(Commitment {
+                        value,
+                        blinding,
+                        ..utxo
+                    });
+                    found += 1;
+                }
+                break;
+            }
+        }
+
+        Ok(found)
+    }
+
+    /// Sum the tracked outputs against one snapshot of the chain state
+    ///
+    /// Outputs recorded as spent count only towards `spent`. Unspent
+    /// outputs in the UTXO set are spendable once `min_confirmations` blocks
+    /// sit on top of their creation height, or if that height is unknown,
+    /// and immature before; the rest are pending until a block confirms
+    /// them. Totals saturate at `u64::MAX`.
+    pub fn balance(&self, state: &MimblewimbleState) -> Balance {
+        let snapshot = state.snapshot();
+        let confirmed: HashMap<&[u8], Option<u64>> = snapshot
+            .utxo_set
+            .iter()
+            .map(|utxo| utxo.commitment.as_slice())
+            .zip(snapshot.output_heights.iter().copied())
+            .collect();
+
+        let mut balance = Balance::default();
+        for output in &self.outputs {
+            let bucket = if self.spent.contains(&output.commitment) {
+                &mut balance.spent
+            } else {
+                balance.total = balance.total.saturating_add(output.value);
+                match confirmed.get(output.commitment.as_slice()) {
+                    Some(Some(height))
+                        if snapshot.height.saturating_sub(*height) < self.min_confirmations =>
+                    {
+                        &mut balance.immature
+                    }
+                    Some(_) => &mut balance.spendable,
+                    None => &mut balance.pending,
+                }
+            };
+            *bucket = bucket.saturating_add(output.value);
+        }
+        balance
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use p521::ProjectivePoint;
+
+    #[test]
+    fn test_blinding_sum_signs_kernel() -> Result<()> {
+        let params = MimblewimbleParameters::default();
+        let commitment = |value: u64, blinding: &Scalar| {
+            Commitment::create(value, crypto::scalar_to_bytes(blinding), &params)
+        };
+
+        let (input_blinding, change_blinding, payment_blinding) = (
+            Scalar::from(11u64),
+            Scalar::from(23u64),
+            Scalar::from(47u64),
+        );
+        let offset = Scalar::from(5u64);
+        let fee = 10;
+
+        let excess = blinding_sum(
+            &[input_blinding],
+            &[change_blinding, payment_blinding],
+            offset,
+        );
+        let mut kernel = Kernel::new(KernelFeatures::Plain, fee, 0, vec![], vec![]);
+        kernel.sign(&crypto::scalar_to_bytes(&excess), &params)?;
+
+        let tx = Transaction::new(
+            1,
+            vec![commitment(1000, &input_blinding)?],
+            vec![
+                commitment(600, &change_blinding)?,
+                commitment(390, &payment_blinding)?,
+            ],
+            kernel,
+            fee,
+        );
+        let kernel = &tx.kernels()[0];
+        assert!(kernel.verify_signature(&params)?);
+
+        // The excess is the commitment sum with the fee and offset removed
+        let outputs = crypto::sum_points(tx.outputs().iter().map(|o| o.commitment.as_slice()))?;
+        let inputs = crypto::sum_points(tx.inputs().iter().map(|i| i.commitment.as_slice()))?;
+        let expected = outputs - inputs + crypto::commit(fee, &Scalar::ZERO)
+            - ProjectivePoint::GENERATOR * offset;
+        assert_eq!(crypto::point_to_bytes(&expected), kernel.excess);
+        Ok(())
+    }
+
+    #[test]
+    fn test_builder_change_and_dust() -> Result<()> {
+        let params = MimblewimbleParameters::default();
+        let input = Commitment::create(1000, vec![11; 32], &params)?;
+        let payment = Commitment::create(600, vec![23; 32], &params)?;
+
+        let tx = TransactionBuilder::new(&params)
+            .input(input.clone())
+            .output(payment.clone())
+            .fee(10)
+            .with_change(&[47; 32])
+            .build()?;
+        assert_eq!(tx.fee(), 10);
+        assert_eq!(tx.outputs().len(), 2);
+        let change = tx.outputs().iter().find(|o| *o != &payment).unwrap();
+        assert_eq!(change.value, 390);
+        assert_eq!(change.blinding, vec![47; 32]);
+        assert!(tx.kernels()[0].verify_signature(&params)?);
+
+        // Change below the dust threshold goes to the miner
+        let dust = TransactionBuilder::new(&params)
+            .input(input.clone())
+            .output(Commitment::create(
+                1000 - 10 - (DUST_THRESHOLD - 1),
+                vec![23; 32],
+                &params,
+            )?)
+            .fee(10)
+            .with_change(&[47; 32])
+            .build()?;
+        assert_eq!(dust.outputs().len(), 1);
+        assert_eq!(dust.fee(), 10 + DUST_THRESHOLD - 1);
+        assert!(dust.kernels()[0].verify_signature(&params)?);
+
+        // Non-dust change without a change key would be lost
+        assert!(matches!(
+            TransactionBuilder::new(&params)
+                .input(input)
+                .output(payment)
+                .fee(10)
+                .build(),
+            Err(MimblewimbleError::BalanceMismatch)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_multi_recipient_payment() -> Result<()> {
+        let input = crate::tests::output(10_000, 11);
+        let state = crate::tests::state_with_outputs(std::slice::from_ref(&input));
+        let params = state.parameters();
+
+        let tx = TransactionBuilder::new(&params)
+            .input(input)
+            .add_recipient(1_000, &[21; 32])
+            .add_recipient(2_000, &[22; 32])
+            .add_recipient(3_000, &[23; 32])
+            .fee(10)
+            .with_change(&[47; 32])
+            .build()?;
+
+        assert_eq!(tx.kernels().len(), 1);
+        let mut values: Vec<u64> = tx.outputs().iter().map(|output| output.value).collect();
+        values.sort_unstable();
+        assert_eq!(values, vec![1_000, 2_000, 3_000, 10_000 - 6_000 - 10]);
+        assert!(tx.is_balanced());
+        assert!(state.verify_transaction(&tx)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_scan_recovers_derived_change() -> Result<()> {
+        let funding: Vec<Commitment> = (1..=3).map(|n| crate::tests::output(1_000, n)).collect();
+        let state = crate::tests::state_with_outputs(&funding);
+        let params = state.parameters();
+        let master_key = [42; 32];
+
+        for (index, input) in (0..).zip(&funding) {
+            let tx = TransactionBuilder::new(&params)
+                .input(input.clone())
+                .add_recipient(500, &[60 + index as u8; 32])
+                .fee(10)
+                .with_derived_change(&master_key, index)
+                .build()?;
+            state.add_transaction(&tx)?;
+        }
+
+        // Every change output is found again from the master key alone
+        let mut wallet = Wallet::new();
+        assert_eq!(wallet.scan_outputs(&state, &master_key, 0..3)?, 3);
+        for index in 0..3 {
+            let key = derive_key(&master_key, index);
+            assert!(wallet
+                .outputs()
+                .iter()
+                .any(|output| output.value == 490 && output.blinding == key));
+        }
+
+        // No block has confirmed the change yet
+        assert_eq!(wallet.balance(&state).immature, 3 * 490);
+
+        // Rescanning tracks nothing new, and other keys find nothing
+        assert_eq!(wallet.scan_outputs(&state, &master_key, 0..3)?, 0);
+        assert_eq!(Wallet::new().scan_outputs(&state, &[7; 32], 0..3)?, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_scan_recovers_large_switch_outputs() -> Result<()> {
+        let params = MimblewimbleParameters {
+            use_switch_commitments: true,
+            ..MimblewimbleParameters::default()
+        };
+        let master_key = [42; 32];
+        let value = 5_000_000_000_000;
+        let owned = Commitment::create(value, derive_key(&master_key, 4), &params)?;
+        let state =
+            crate::tests::state_with_outputs(&[owned.clone(), crate::tests::output(1_000, 1)]);
+
+        // No value search is involved, however large the value
+        let mut wallet = Wallet::new();
+        assert_eq!(wallet.scan_outputs(&state, &master_key, 0..8)?, 1);
+        assert_eq!(wallet.outputs()[0].value, value);
+        assert_eq!(wallet.outputs()[0].blinding, owned.blinding);
+        assert!(wallet.outputs()[0].verify()?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_wallet_balance() -> Result<()> {
+        let (confirmed, spent) = (
+            crate::tests::output(5_000, 1),
+            crate::tests::output(3_000, 2),
+        );
+        let state = MimblewimbleState {
+            parameters: std::sync::Arc::new(MimblewimbleParameters::regtest()),
+            ..crate::tests::state_with_outputs(&[confirmed.clone(), spent.clone()])
+        };
+        let params = state.parameters();
+
+        // Spending one output creates change that is not yet confirmed
+        let tx = TransactionBuilder::new(&params)
+            .input(spent.clone())
+            .add_recipient(1_000, &[21; 32])
+            .fee(10)
+            .with_change(&[22; 32])
+            .build()?;
+        let change = tx
+            .outputs()
+            .iter()
+            .find(|output| output.value == 3_000 - 1_000 - 10)
+            .unwrap()
+            .clone();
+
+        let mut wallet = Wallet::new();
+        wallet.add_output(confirmed);
+        wallet.add_output(spent.clone());
+        wallet.add_output(change.clone());
+        wallet.record_spend(&tx);
+        assert_eq!(
+            wallet.balance(&state),
+            Balance {
+                total: 5_000 + 1_990,
+                spendable: 5_000,
+                immature: 0,
+                pending: 1_990,
+                spent: 3_000,
+            }
+        );
+
+        // Once in the UTXO set the change waits for a block on top of it
+        state.add_transaction(&tx)?;
+        assert_eq!(wallet.balance(&state).immature, 1_990);
+        assert_eq!(wallet.balance(&state).pending, 0);
+
+        state.submit_block(state.block_template(&[9; 32])?.into_block(0))?;
+        assert!(wallet.remove_output(&spent.commitment).is_some());
+        assert_eq!(
+            wallet.balance(&state),
+            Balance {
+                total: 6_990,
+                spendable: 6_990,
+                immature: 0,
+                pending: 0,
+                spent: 0,
+            }
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,65 @@
+//! Heap usage tests
+//!
+//! This binary installs a counting global allocator, so it holds a single
+//! test: concurrent tests would disturb each other's peak measurements.
+
+use silver_mimblewimble::kernel::KernelFeatures;
+use silver_mimblewimble::*;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Heap bytes currently allocated
+static LIVE_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+/// Highest value of `LIVE_BYTES` since the last reset
+static PEAK_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+/// System allocator that tracks peak heap use
+struct PeakAllocator;
+
+unsafe impl GlobalAlloc for PeakAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let live = LIVE_BYTES.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            PEAK_BYTES.fetch_max(live, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        LIVE_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: PeakAllocator = PeakAllocator;
+
+/// Peak heap growth while running `f` (bytes)
+fn peak_allocation<R>(f: impl FnOnce() -> R) -> usize {
+    let base = LIVE_BYTES.load(Ordering::Relaxed);
+    PEAK_BYTES.store(base, Ordering::Relaxed);
+    black_box(f());
+    PEAK_BYTES.load(Ordering::Relaxed) - base
+}
+
+#[test]
+fn test_streamed_hash_does_not_buffer_the_transaction() {
+    let params = MimblewimbleParameters::default();
+    let outputs = (0..256u32)
+        .map(|n| Commitment::create(u64::from(n) + 1, n.to_be_bytes().repeat(8), &params).unwrap())
+        .collect();
+    let kernel = Kernel::new(KernelFeatures::Plain, 100, 0, vec![1; 32], vec![2; 64]);
+    let tx = Transaction::new(1, vec![], outputs, kernel, 100);
+
+    let encoded = serialization::to_vec(&tx).unwrap().len();
+    let buffered = peak_allocation(|| serialization::to_vec(&tx).unwrap());
+    let streamed = peak_allocation(|| serialization::hash(&tx).unwrap());
+    assert!(buffered >= encoded);
+    assert!(
+        streamed < encoded / 10,
+        "hashing allocated {streamed} bytes for a {encoded}-byte transaction"
+    );
+}
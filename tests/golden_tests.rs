@@ -0,0 +1,108 @@
+//! Serialization compatibility tests against stored golden files
+//!
+//! Each test serializes a canonical instance with `serialization::to_vec`
+//! and compares it to the bytes in `tests/golden`. Run with `UPDATE_GOLDEN=1`
+//! to regenerate the files after an intended format change.
+
+use silver_mimblewimble::block;
+use silver_mimblewimble::kernel::KernelFeatures;
+use silver_mimblewimble::serialization;
+use silver_mimblewimble::*;
+use std::path::PathBuf;
+
+/// Path of a golden file
+fn golden_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("golden")
+        .join(format!("{}.json", name))
+}
+
+/// Compare a serialized value against its golden file
+fn check_golden<T: serde::Serialize>(name: &str, value: &T) -> Result<()> {
+    let path = golden_path(name);
+    let bytes = serialization::to_vec(value)?;
+
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::write(&path, &bytes).map_err(|e| MimblewimbleError::IoError(e.to_string()))?;
+        return Ok(());
+    }
+
+    let golden = std::fs::read(&path).map_err(|e| MimblewimbleError::IoError(e.to_string()))?;
+    assert!(
+        bytes == golden,
+        "serialization of {} changed; rerun with UPDATE_GOLDEN=1 if intended",
+        name
+    );
+    Ok(())
+}
+
+fn canonical_commitment() -> Result<Commitment> {
+    Commitment::create(1000, vec![7; 32], &MimblewimbleParameters::default())
+}
+
+fn canonical_kernel() -> Result<Kernel> {
+    let mut kernel = Kernel::new(KernelFeatures::Plain, 10, 0, vec![], vec![]);
+    kernel.sign(&[5; 32], &MimblewimbleParameters::default())?;
+    Ok(kernel)
+}
+
+fn canonical_transaction() -> Result<Transaction> {
+    Transaction::coinbase(1, 0, &[3; 32], &MimblewimbleParameters::default())
+}
+
+fn canonical_block() -> Result<Block> {
+    let transactions = vec![canonical_transaction()?];
+    let header = BlockHeader {
+        version: 1,
+        height: 1,
+        timestamp: 1_700_000_000,
+        previous_hash: vec![0; 128],
+        merkle_root: block::compute_merkle_root(&transactions)?,
+        output_root: vec![0; 128],
+        kernel_root: vec![0; 128],
+        difficulty: 1000,
+        nonce: 42,
+    };
+    Ok(Block {
+        header,
+        transactions,
+    })
+}
+
+#[test]
+fn test_commitment_golden() -> Result<()> {
+    check_golden("commitment", &canonical_commitment()?)
+}
+
+#[test]
+fn test_kernel_golden() -> Result<()> {
+    check_golden("kernel", &canonical_kernel()?)
+}
+
+#[test]
+fn test_transaction_golden() -> Result<()> {
+    check_golden("transaction", &canonical_transaction()?)
+}
+
+#[test]
+fn test_block_golden() -> Result<()> {
+    check_golden("block", &canonical_block()?)
+}
+
+#[test]
+fn test_golden_files_deserialize() -> Result<()> {
+    let golden = |name: &str| {
+        std::fs::read(golden_path(name)).map_err(|e| MimblewimbleError::IoError(e.to_string()))
+    };
+
+    let commitment: Commitment = serialization::from_slice(&golden("commitment")?)?;
+    assert_eq!(commitment, canonical_commitment()?);
+    let kernel: Kernel = serialization::from_slice(&golden("kernel")?)?;
+    assert_eq!(kernel, canonical_kernel()?);
+    let tx: Transaction = serialization::from_slice(&golden("transaction")?)?;
+    assert_eq!(tx, canonical_transaction()?);
+    let block: Block = serialization::from_slice(&golden("block")?)?;
+    assert_eq!(block.header, canonical_block()?.header);
+    Ok(())
+}
@@ -19,7 +19,7 @@ fn test_mimblewimble_transaction_creation() {
     // Create transaction
     let tx = Transaction::new(1, vec![], vec![], kernel, 100);
     
-    assert_eq!(tx.fee, 100);
+    assert_eq!(tx.fee(), 100);
 }
 
 #[test]
@@ -34,6 +34,10 @@ fn test_mimblewimble_block_creation() {
         timestamp: 0,
         previous_hash: vec![0; 32],
         merkle_root: vec![0; 32],
+        output_root: vec![0; 32],
+        kernel_root: vec![0; 32],
+        difficulty: 1,
+        nonce: 0,
     };
     
     let block = Block {
@@ -47,7 +51,8 @@ fn test_mimblewimble_block_creation() {
 #[test]
 fn test_mimblewimble_range_proof() {
     let params = MimblewimbleParameters::default();
-    let proof = RangeProof::create(1000, &params).expect("Failed to create proof");
+    let output = Commitment::new(1000, vec![7; 32], RangeProof::new(vec![])).expect("Failed to commit");
+    let proof = RangeProof::create(1000, output.commitment(), &params).expect("Failed to create proof");
     
-    assert!(proof.verify(&params).expect("Failed to verify proof"));
+    assert!(proof.verify(output.commitment(), &params).expect("Failed to verify proof"));
 }